@@ -0,0 +1,62 @@
+use uuid::Uuid;
+use common::ClipboardItem;
+
+// whatever was copied last. it stays after the app which copied it quits.
+pub struct Clipboard {
+    pub owner:Option<Uuid>,
+    items:Vec<ClipboardItem>,
+}
+
+impl Clipboard {
+    pub fn new() -> Clipboard {
+        Clipboard { owner: None, items: vec![] }
+    }
+    pub fn set(&mut self, owner:Uuid, items:Vec<ClipboardItem>) {
+        self.owner = Some(owner);
+        self.items = items;
+    }
+    pub fn mime_types(&self) -> Vec<String> {
+        self.items.iter().map(|item| item.mime_type.clone()).collect()
+    }
+    // the items with the asked for types, in the order asked for. no types means everything.
+    pub fn get(&self, mime_types:&[String]) -> Vec<ClipboardItem> {
+        if mime_types.is_empty() {
+            return self.items.clone();
+        }
+        mime_types.iter()
+            .filter_map(|mt| self.items.iter().find(|item| &item.mime_type == mt))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use common::{ClipboardData, ClipboardItem, clipboard_types};
+    use crate::clipboard::Clipboard;
+
+    fn item(mime_type:&str, data:ClipboardData) -> ClipboardItem {
+        ClipboardItem { mime_type: mime_type.to_string(), data }
+    }
+
+    #[test]
+    fn pick_representations() {
+        let mut clip = Clipboard::new();
+        assert!(clip.get(&[]).is_empty());
+        clip.set(Uuid::new_v4(), vec![
+            item(clipboard_types::DB_OBJECT, ClipboardData::ObjectId(String::from("obj-1"))),
+            item(clipboard_types::TEXT, ClipboardData::Text(String::from("Groceries"))),
+        ]);
+        assert_eq!(clip.mime_types(), vec![clipboard_types::DB_OBJECT, clipboard_types::TEXT]);
+        assert_eq!(clip.get(&[]).len(), 2);
+
+        // a text editor only wants text
+        let items = clip.get(&[String::from(clipboard_types::IMAGE), String::from(clipboard_types::TEXT)]);
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0].data, ClipboardData::Text(text) if text == "Groceries"));
+        // the first one asked for comes first
+        let items = clip.get(&[String::from(clipboard_types::TEXT), String::from(clipboard_types::DB_OBJECT)]);
+        assert_eq!(items[0].mime_type, clipboard_types::TEXT);
+    }
+}
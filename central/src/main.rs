@@ -1,90 +1,305 @@
-use std::io::Write;
+use std::collections::HashMap;
 use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync::{Arc, LockResult, mpsc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::{io, thread};
 use std::path::PathBuf;
 use std::thread::{JoinHandle, sleep};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use log::{error, info, LevelFilter, set_logger, warn};
 use serde::Deserialize;
 use uuid::Uuid;
-use common::{APICommand, APP_MANAGER_PORT, AppDisconnected, AudioPauseTrackResponse, AudioPlayTrackResponse, DBAddResponse, DBDeleteResponse, DBQueryClause, DBQueryClauseKind, DBQueryResponse, DBUpdateResponse, DEBUG_PORT, DebugMessage, HelloAppResponse, HelloWindowManagerResponse, IncomingMessage, OpenWindowCommand, OpenWindowResponse, WINDOW_MANAGER_PORT};
+use common::journal::{Direction, Journal, PeerKind};
+use common::protocol::{accepts_image_encoding, capability, negotiate, PROTOCOL_VERSION};
+use common::transport::{ClientStream, default_socket_dir};
+use common::wire::WireFormat;
+use common::{APICommand, APP_MANAGER_PORT, AppDisconnected, CloseWindowResponse, AudioPauseTrackResponse, AudioPlayTrackResponse, ClipboardChanged, ClipboardGetResponse, DBAddResponse, DragEnded, DBDeleteResponse, DBQueryClause, DBQueryClauseKind, DBQueryResponse, DBUpdateResponse, DEBUG_PORT, DebugAppInfo, DebugMessage, DebugWindowInfo, DrawBatchCommand, DrawImageCommand, DrawSharedImageCommand, HelloAppResponse, HelloWindowManagerResponse, IncomingMessage, MAX_NOTIFICATION_ACTIONS, NOTIFICATION_TYPE, NotificationActionInvoked, OpenWindowCommand, OpenWindowResponse, PostNotification, PostNotificationResponse, RegisterSharedBuffer, RegisterSharedBufferResponse, SharedBufferInfo, WINDOW_MANAGER_PORT};
+use common::shm::SharedBuffer;
 use structopt::StructOpt;
 use cool_logger::CoolLogger;
 use db::{JDB, JObj, JQuery};
 // use audio::AudioService;
 use gfx::graphics::Rect;
-use crate::network::{setup_interface, spawn_client_handler};
-use crate::state::CentralState;
+use crate::clipboard::Clipboard;
+use crate::network::{Address, Listener, peer_pid, setup_interface, spawn_client_handler};
+use crate::outbound::{Outbound, OverflowPolicy, QueueConfig};
+use crate::state::{CentralState, Drag, DROP_RESPONSE_TIMEOUT};
 
+mod clipboard;
 mod network;
+mod outbound;
 mod state;
 
 struct Window {
     id:Uuid,
     bounds:Rect,
     title:String,
+    visible:bool,
+    parent:Option<Uuid>,
 }
 struct WM {
     id:Uuid,
-    stream:TcpStream,
+    outbound:Outbound,
+    wire_format:WireFormat,
+    name:String,
+    capabilities:Vec<String>,
+    // set when the window manager is on this machine
+    peer_pid:Option<u32>,
+    // set once the handshake reply is queued. broadcasts skip it until then,
+    // so the reply is always the first thing it reads.
+    greeted:bool,
 }
 struct Debugger {
     id:Uuid,
-    stream:TcpStream,
+    outbound:Outbound,
 }
 struct App {
     id:Uuid,
-    stream:TcpStream,
+    outbound:Outbound,
     windows:Vec<Window>,
+    wire_format:WireFormat,
+    name:String,
+    capabilities:Vec<String>,
+    metadata:HashMap<String,String>,
+    // set when the app is on this machine
+    peer_pid:Option<u32>,
+    // same as for window managers
+    greeted:bool,
+    // the buffers the app registered, as the app described them, with our own mapping of each
+    // for window managers which can't map them
+    shared_buffers:HashMap<Uuid,(SharedBufferInfo,SharedBuffer)>,
+}
+
+impl WM {
+    fn has_capability(&self, cap:&str) -> bool {
+        self.capabilities.iter().any(|c| c == cap)
+    }
+}
+
+// shared memory only works between processes on the same machine
+fn restrict_capabilities(capabilities:&mut Vec<String>, peer_pid:Option<u32>) {
+    if peer_pid.is_none() {
+        capabilities.retain(|c| c != capability::SHARED_MEMORY);
+    }
 }
 
 impl CentralState {
-    fn init(file: PathBuf) -> CentralState {
+    fn init(file: PathBuf, queue_config: QueueConfig, journal: Option<Arc<Journal>>) -> CentralState {
         CentralState {
             wms: vec![],
             apps: vec![],
             debuggers: vec![],
             db:JDB::load_from_file(file),
+            clipboard:Clipboard::new(),
+            drags:HashMap::new(),
+            input_wm:None,
+            queue_config,
+            journal,
             // audio_service: AudioService::make(),
         }
     }
-    fn add_app_from_stream(&mut self, stream:TcpStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
+    fn add_app_from_stream(&mut self, stream:ClientStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
         let id = Uuid::new_v4();
-        self.apps.push(App{ id,stream,windows:vec![] });
-        if let Some(app) = self.apps.iter().find(|a|a.id == id) {
-            spawn_client_handler(id.clone(), app.stream.try_clone().unwrap(), sender, stop);
+        let outbound = Outbound::start(id, stream.try_clone().unwrap(), self.queue_config, self.journal_for(PeerKind::App));
+        let peer_pid = peer_pid(&stream);
+        self.apps.push(App{ id,outbound,windows:vec![], wire_format:WireFormat::Json, name:String::new(), capabilities:vec![], metadata:HashMap::new(), peer_pid, shared_buffers:HashMap::new(), greeted:false });
+        let disconnected = APICommand::AppDisconnected(AppDisconnected { app_id: id });
+        spawn_client_handler(id.clone(), stream, sender, stop, disconnected);
+    }
+    fn journal_for(&self, peer:PeerKind) -> Option<(Arc<Journal>, PeerKind)> {
+        self.journal.clone().map(|journal| (journal, peer))
+    }
+    // record a message from a client, if recording. messages from clients which are already gone are skipped.
+    fn record_inbound(&self, msg:&IncomingMessage) {
+        if let Some(journal) = &self.journal {
+            let peer = if self.apps.iter().any(|a|a.id == msg.source) {
+                PeerKind::App
+            } else if self.wms.iter().any(|w|w.id == msg.source) {
+                PeerKind::WindowManager
+            } else if self.debuggers.iter().any(|d|d.id == msg.source) {
+                PeerKind::Debugger
+            } else {
+                return;
+            };
+            journal.record(Direction::Inbound, peer, msg.source, msg);
         }
     }
-    fn add_window_to_app(&mut self, appid: Uuid, ow: &OpenWindowCommand) -> Uuid {
+    // only apps which finished their hello can open windows
+    fn add_window_to_app(&mut self, appid: Uuid, ow: &OpenWindowCommand) -> Option<Uuid> {
+        let app = self.apps.iter_mut().find(|a|a.id == appid && a.greeted)?;
         let winid = Uuid::new_v4();
         let win = Window {
             id: winid,
             bounds: ow.bounds.clone(),
             title: ow.window_title.clone(),
+            visible: true,
+            parent: ow.parent,
         };
-        let app = self.apps.iter_mut().find(|a|a.id == appid).unwrap();
         app.windows.push(win);
-        winid
+        Some(winid)
+    }
+    fn is_wm(&self, id:Uuid) -> bool {
+        self.wms.iter().any(|w|w.id == id && w.greeted)
+    }
+    fn is_client(&self, id:Uuid) -> bool {
+        self.apps.iter().any(|a|a.id == id)
+            || self.wms.iter().any(|w|w.id == id)
+            || self.debuggers.iter().any(|d|d.id == id)
     }
-    fn add_wm_from_stream(&mut self, stream:TcpStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
+    fn add_wm_from_stream(&mut self, stream:ClientStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
         let id = Uuid::new_v4();
-        self.wms.push(WM{id,stream});
-        if let Some(wm) = self.wms.iter().find(|w|w.id == id) {
-            spawn_client_handler(id.clone(), wm.stream.try_clone().unwrap(), sender, stop);
+        let outbound = Outbound::start(id, stream.try_clone().unwrap(), self.queue_config, self.journal_for(PeerKind::WindowManager));
+        let peer_pid = peer_pid(&stream);
+        self.wms.push(WM{id,outbound, wire_format:WireFormat::Json, name:String::new(), capabilities:vec![], peer_pid, greeted:false});
+        let disconnected = APICommand::Debug(DebugMessage::WindowManagerDisconnected);
+        spawn_client_handler(id.clone(), stream, sender, stop, disconnected);
+    }
+    fn app_pid(&self, id:Uuid) -> Option<u32> {
+        self.apps.iter().find(|a|a.id == id).and_then(|a|a.peer_pid)
+    }
+    fn wm_pid(&self, id:Uuid) -> Option<u32> {
+        self.wms.iter().find(|w|w.id == id).and_then(|w|w.peer_pid)
+    }
+    // map a buffer an app made, after checking it really belongs to that app
+    fn register_shared_buffer(&mut self, appid:Uuid, info:&SharedBufferInfo) -> Result<(),String> {
+        let app = self.apps.iter_mut().find(|a|a.id == appid).ok_or_else(|| format!("no app {}", appid))?;
+        if !app.capabilities.iter().any(|c| c == capability::SHARED_MEMORY) {
+            return Err(String::from("the app doesn't have the shared-memory capability"));
         }
+        // otherwise an app could read any other process's files through /proc
+        if app.peer_pid != Some(info.pid) {
+            return Err(format!("pid {} isn't the app's pid {:?}", info.pid, app.peer_pid));
+        }
+        let buf = SharedBuffer::open(info).map_err(|e| e.to_string())?;
+        app.shared_buffers.insert(info.buffer_id, (info.clone(), buf));
+        Ok(())
     }
-    fn add_debugger_from_stream(&mut self, stream: TcpStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
-        let id = Uuid::new_v4();
-        self.debuggers.push(Debugger{id,stream});
-        if let Some(c) = self.debuggers.iter().find(|w|w.id == id) {
-            self.spawn_debugger_handler(id.clone(), c.stream.try_clone().unwrap(), sender, stop);
+    fn unregister_shared_buffer(&mut self, appid:Uuid, buffer_id:Uuid) -> bool {
+        self.apps.iter_mut().find(|a|a.id == appid)
+            .map(|app| app.shared_buffers.remove(&buffer_id).is_some())
+            .unwrap_or(false)
+    }
+    fn set_app_wire_format(&mut self, id:Uuid, format:WireFormat) {
+        if let Some(app) = self.apps.iter_mut().find(|a|a.id == id) {
+            app.wire_format = format;
+        }
+    }
+    fn set_wm_wire_format(&mut self, id:Uuid, format:WireFormat) {
+        if let Some(wm) = self.wms.iter_mut().find(|w|w.id == id) {
+            wm.wire_format = format;
+        }
+    }
+    fn set_app_hello(&mut self, id:Uuid, name:String, capabilities:Vec<String>, metadata:HashMap<String,String>) {
+        if let Some(app) = self.apps.iter_mut().find(|a|a.id == id) {
+            app.name = name;
+            app.capabilities = capabilities;
+            app.metadata = metadata;
+        }
+    }
+    // an app's record of one of its own windows
+    fn app_window_mut(&mut self, appid:Uuid, winid:Uuid) -> Option<&mut Window> {
+        let win = self.apps.iter_mut().find(|a|a.id == appid).and_then(|a|a.windows.iter_mut().find(|w|w.id == winid));
+        if win.is_none() {
+            warn!("app {} doesn't have a window {}", appid, winid);
+        }
+        win
+    }
+    // the window and every window opened on top of it, children after their parents
+    fn window_and_children(&self, appid:Uuid, winid:Uuid) -> Vec<Uuid> {
+        let mut ids = vec![winid];
+        if let Some(app) = self.apps.iter().find(|a|a.id == appid) {
+            let mut n = 0;
+            while n < ids.len() {
+                let parent = ids[n];
+                ids.extend(app.windows.iter().filter(|w|w.parent == Some(parent)).map(|w|w.id));
+                n += 1;
+            }
         }
+        ids
+    }
+    fn remove_window_from_app(&mut self, appid:Uuid, winid:Uuid) {
+        if let Some(app) = self.apps.iter_mut().find(|a|a.id == appid) {
+            app.windows.retain(|w|w.id != winid);
+        }
+    }
+    fn app_info(&self, appid:Uuid) -> DebugAppInfo {
+        let app = self.apps.iter().find(|a|a.id == appid);
+        DebugAppInfo {
+            app_id: appid,
+            app_name: app.map(|a|a.name.clone()).unwrap_or_default(),
+            metadata: app.map(|a|a.metadata.clone()).unwrap_or_default(),
+        }
+    }
+    fn window_info(&self, appid:Uuid, winid:Uuid) -> DebugWindowInfo {
+        let app = self.apps.iter().find(|a|a.id == appid);
+        DebugWindowInfo {
+            app_id: appid,
+            app_name: app.map(|a|a.name.clone()).unwrap_or_default(),
+            window_id: winid,
+            window_title: app.and_then(|a|a.windows.iter().find(|w|w.id == winid)).map(|w|w.title.clone()).unwrap_or_default(),
+        }
+    }
+    fn set_wm_hello(&mut self, id:Uuid, name:String, capabilities:Vec<String>) {
+        if let Some(wm) = self.wms.iter_mut().find(|w|w.id == id) {
+            wm.name = name;
+            wm.capabilities = capabilities;
+        }
+    }
+    // call after the handshake reply is queued
+    fn greet_app(&mut self, id:Uuid) {
+        if let Some(app) = self.apps.iter_mut().find(|a|a.id == id) {
+            app.greeted = true;
+        }
+    }
+    fn greet_wm(&mut self, id:Uuid) {
+        if let Some(wm) = self.wms.iter_mut().find(|w|w.id == id) {
+            wm.greeted = true;
+        }
+    }
+    // forget about a client. its writer sends anything still queued, like a rejection, then hangs up.
+    // returns false if the app was already gone
+    fn drop_app(&mut self, id:Uuid) -> bool {
+        if self.apps.iter().any(|a|a.id == id) {
+            self.apps.retain(|a|a.id != id);
+            self.drags.retain(|_, drag| drag.app_id != id);
+            // it will never answer the drops it was given
+            let drags:Vec<Uuid> = self.drags.iter().filter(|(_, d)| d.dropped_on.is_some_and(|(app_id, _)| app_id == id)).map(|(drag_id, _)| *drag_id).collect();
+            for drag_id in drags {
+                self.end_drag(drag_id, false);
+            }
+            true
+        } else {
+            false
+        }
+    }
+    fn drop_wm(&mut self, id:Uuid) -> bool {
+        if self.wms.iter().any(|w|w.id == id) {
+            self.wms.retain(|w|w.id != id);
+            if self.input_wm == Some(id) {
+                self.input_wm = None;
+            }
+            // nothing is left to finish its drags
+            let drags:Vec<Uuid> = self.drags.iter().filter(|(_, d)| d.wm_id == id).map(|(drag_id, _)| *drag_id).collect();
+            for drag_id in drags {
+                self.end_drag(drag_id, false);
+            }
+            true
+        } else {
+            false
+        }
+    }
+    fn drop_debugger(&mut self, id:Uuid) {
+        self.debuggers.retain(|d|d.id != id);
+    }
+    fn add_debugger_from_stream(&mut self, stream: ClientStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) {
+        let id = Uuid::new_v4();
+        let outbound = Outbound::start(id, stream.try_clone().unwrap(), self.queue_config, self.journal_for(PeerKind::Debugger));
+        self.debuggers.push(Debugger{id,outbound});
+        self.spawn_debugger_handler(id.clone(), stream, sender, stop);
     }
 
-    fn spawn_debugger_handler(&self, id:Uuid, stream: TcpStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) -> JoinHandle<()>{
+    fn spawn_debugger_handler(&self, id:Uuid, stream: ClientStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) -> JoinHandle<()>{
         thread::spawn(move ||{
             info!("debugger thread starting: {}",id);
             stream.set_nonblocking(false).unwrap();
@@ -104,6 +319,7 @@ impl CentralState {
                             command:APICommand::Debug(cmd),
                             trace:false,
                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                            request_id: None,
                         }).unwrap();
                     }
                     Err(e) => {
@@ -116,25 +332,38 @@ impl CentralState {
                     }
                 }
             }
+            if !stop.load(Ordering::Relaxed) {
+                let _ = sender.send(IncomingMessage{
+                    source:id,
+                    command:APICommand::Debug(DebugMessage::DebuggerDisconnected),
+                    trace:false,
+                    timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                    request_id: None,
+                });
+            }
             println!("CENTRAL: debugger thread ending: {}",id);
         })
     }
 
     fn send_to_app(&mut self, id:Uuid, resp: APICommand) {
+        self.send_reply_to_app(id, resp, None)
+    }
+    // send a response to an app, tagged with the request id from the app's original request
+    fn send_reply_to_app(&mut self, id:Uuid, resp: APICommand, request_id:Option<u64>) {
         // info!("sending to app {:?}",resp);
-        let msg:IncomingMessage = IncomingMessage {
+        let mut msg:IncomingMessage = IncomingMessage {
             source:Default::default(),
             trace:false,
             command:resp,
             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id,
         };
-        let data = serde_json::to_string(&msg).unwrap();
         if let Some(app) = self.apps.iter_mut().find(|a|a.id == id){
-            let res = app.stream.write_all(data.as_ref());
-            if let Err(e) = res {
-                error!("error happened in app thread {}: {}",id,e);
+            // apps which didn't ask for request ids get exactly the messages they used to
+            if !app.capabilities.iter().any(|c| c == capability::REQUEST_IDS) {
+                msg.request_id = None;
             }
-            //.expect("failed to send rect");
+            app.outbound.send(&msg, app.wire_format);
         } else {
             info!("didnt send to the app. couldnt find an app for {}",id);
         }
@@ -146,15 +375,11 @@ impl CentralState {
             trace:source.trace,
             command:resp,
             timestamp_usec:source.timestamp_usec,
+            request_id: None,
         };
 
-        let data = serde_json::to_string(&msg).unwrap();
         if let Some(app) = self.apps.iter_mut().find(|a|a.id == id){
-            let res = app.stream.write_all(data.as_ref());
-            if let Err(e) = res {
-                error!("error happened in app thread {}: {}",id,e);
-            }
-            //.expect("failed to send rect");
+            app.outbound.send(&msg, app.wire_format);
         } else {
             info!("didnt send to the app. couldnt find an app for {}",id);
         }
@@ -166,10 +391,10 @@ impl CentralState {
             command: resp,
             trace:false,
             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
         };
-        let data = serde_json::to_string(&im).unwrap();
-        for app in self.apps.iter_mut() {
-            app.stream.write_all(data.as_ref()).expect("failed to send to all wm")
+        for app in self.apps.iter().filter(|a|a.greeted) {
+            app.outbound.send(&im, app.wire_format);
         }
     }
     fn send_to_wm(&mut self, id:Uuid, resp: APICommand, trace:bool) {
@@ -179,10 +404,11 @@ impl CentralState {
             command: resp,
             trace: trace,
             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
         };
-        let data = serde_json::to_string(&im).unwrap();
-        let wm = self.wms.iter_mut().find(|a|a.id == id).unwrap();
-        wm.stream.write_all(data.as_ref()).expect("failed to send data to wm");
+        if let Some(wm) = self.wms.iter().find(|a|a.id == id) {
+            wm.outbound.send(&im, wm.wire_format);
+        }
     }
     fn send_to_all_wm(&mut self, resp: APICommand) {
         // info!("CENTRAL: sending to all wm {:?}",resp);
@@ -191,20 +417,126 @@ impl CentralState {
             command: resp,
             trace: false,
             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
         };
-        let data = serde_json::to_string(&im).unwrap();
-        for wm in self.wms.iter_mut() {
-            wm.stream.write_all(data.as_ref()).expect("failed to send to all wm")
+        for wm in self.wms.iter().filter(|w|w.greeted) {
+            wm.outbound.send(&im, wm.wire_format);
+        }
+    }
+    fn send_draw_batch_to_all_wm(&mut self, batch: DrawBatchCommand) {
+        let wrap = |command| IncomingMessage {
+            source: Default::default(),
+            command,
+            trace: false,
+            timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
+        };
+        let whole = wrap(APICommand::DrawBatchCommand(batch.clone()));
+        // older window managers get it one command at a time
+        let pieces:Vec<IncomingMessage> = batch.expand().into_iter().map(wrap).collect();
+        for wm in self.wms.iter().filter(|w|w.greeted) {
+            if wm.capabilities.iter().any(|c| c == capability::DRAW_BATCH) {
+                wm.outbound.send(&whole, wm.wire_format);
+            } else {
+                for msg in &pieces {
+                    wm.outbound.send(msg, wm.wire_format);
+                }
+            }
+        }
+    }
+    // whether any window manager can't read the image's encoding, so it has to be decoded first
+    fn needs_plain_image(&self, cmd: &DrawImageCommand) -> bool {
+        self.wms.iter().any(|w| w.greeted && !accepts_image_encoding(&w.capabilities, cmd.encoding))
+    }
+    // window managers which can't read the encoding get the plain pixels, if the image could be decoded
+    fn send_draw_image_to_all_wm(&mut self, cmd: DrawImageCommand, plain: Option<DrawImageCommand>) {
+        let wrap = |command| IncomingMessage {
+            source: Default::default(),
+            command,
+            trace: false,
+            timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
+        };
+        let encoding = cmd.encoding;
+        let encoded = wrap(APICommand::DrawImageCommand(cmd));
+        let raw = plain.map(|plain| wrap(APICommand::DrawImageCommand(plain)));
+        for wm in self.wms.iter().filter(|w|w.greeted) {
+            if accepts_image_encoding(&wm.capabilities, encoding) {
+                wm.outbound.send(&encoded, wm.wire_format);
+            } else if let Some(msg) = &raw {
+                wm.outbound.send(msg, wm.wire_format);
+            }
+        }
+    }
+    fn send_to_wms_with(&mut self, cap:&str, resp: APICommand) {
+        let im = IncomingMessage {
+            source: Default::default(),
+            command: resp,
+            trace: false,
+            timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
+        };
+        for wm in self.wms.iter().filter(|w| w.greeted && w.has_capability(cap)) {
+            wm.outbound.send(&im, wm.wire_format);
+        }
+    }
+    // tell a window manager which just connected about the buffers registered before it
+    fn send_shared_buffers_to_wm(&mut self, wmid:Uuid) {
+        let regs:Vec<APICommand> = self.apps.iter().flat_map(|app| app.shared_buffers.values().map(move |(info, _)| {
+            APICommand::RegisterSharedBuffer(RegisterSharedBuffer { app_id: app.id, info: info.clone() })
+        })).collect();
+        for reg in regs {
+            self.send_to_wm(wmid, reg, false);
+        }
+    }
+    fn send_shared_draw_to_all_wm(&mut self, cmd: DrawSharedImageCommand) {
+        let buf = match self.apps.iter().find(|a|a.id == cmd.app_id).and_then(|a|a.shared_buffers.get(&cmd.buffer_id)) {
+            Some((_, buf)) => buf,
+            None => {
+                warn!("app {} drew from unknown shared buffer {}", cmd.app_id, cmd.buffer_id);
+                return;
+            }
+        };
+        let wrap = |command| IncomingMessage {
+            source: Default::default(),
+            command,
+            trace: false,
+            timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+            request_id: None,
+        };
+        // the pixels only get copied if some window manager can't map the buffer
+        let mut copy:Option<IncomingMessage> = None;
+        let shared = wrap(APICommand::DrawSharedImageCommand(cmd.clone()));
+        for wm in self.wms.iter().filter(|w|w.greeted) {
+            if wm.has_capability(capability::SHARED_MEMORY) {
+                wm.outbound.send(&shared, wm.wire_format);
+            } else {
+                let msg = copy.get_or_insert_with(|| wrap(APICommand::DrawImageCommand(cmd.to_image(buf))));
+                wm.outbound.send(msg, wm.wire_format);
+            }
         }
     }
     fn send_to_debugger(&mut self, resp: DebugMessage) {
         // info!("CENTRAL: sending to debugger {:?}",resp);
-        let data = serde_json::to_string(&resp).unwrap();
-        for dbg in self.debuggers.iter_mut() {
-            dbg.stream.write_all(data.as_ref()).expect("CENTRAL: error sending to debugger");
+        let data = serde_json::to_vec(&resp).unwrap();
+        if let Some(journal) = &self.journal {
+            // debuggers get bare debug messages. record them the same way as everything else.
+            let msg = IncomingMessage {
+                source: Default::default(),
+                command: APICommand::Debug(resp),
+                trace: false,
+                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                request_id: None,
+            };
+            for dbg in self.debuggers.iter() {
+                journal.record(Direction::Outbound, PeerKind::Debugger, dbg.id, &msg);
+            }
+        }
+        for dbg in self.debuggers.iter() {
+            dbg.outbound.send_raw(data.clone());
         }
     }
-    fn send_to_database(&mut self, cmd: APICommand) {
+    fn send_to_database(&mut self, cmd: APICommand, request_id:Option<u64>) {
         // info!("sending to database {:?}",cmd);
         match cmd {
             APICommand::DBQueryRequest(req) => {
@@ -215,7 +547,7 @@ impl CentralState {
                     success: true,
                     results: data,
                 };
-                self.send_to_app(msg.app_id,APICommand::DBQueryResponse(msg));
+                self.send_reply_to_app(msg.app_id,APICommand::DBQueryResponse(msg), request_id);
             }
             APICommand::DBUpdateRequest(req) => {
                 let data :JObj = self.db.process_update(req.object);
@@ -224,7 +556,7 @@ impl CentralState {
                     success: true,
                     object:data,
                 };
-                self.send_to_app(msg.app_id,APICommand::DBUpdateResponse(msg));
+                self.send_reply_to_app(msg.app_id,APICommand::DBUpdateResponse(msg), request_id);
             }
             APICommand::DBAddRequest(req) => {
                 let data:JObj = self.db.process_add(req.object);
@@ -233,7 +565,7 @@ impl CentralState {
                     success: true,
                     object:data,
                 };
-                self.send_to_app(msg.app_id,APICommand::DBAddResponse(msg));
+                self.send_reply_to_app(msg.app_id,APICommand::DBAddResponse(msg), request_id);
             }
             APICommand::DBDeleteRequest(req) => {
                 let data:JObj = self.db.process_delete(req.object);
@@ -242,14 +574,14 @@ impl CentralState {
                     success: true,
                     object:data,
                 };
-                self.send_to_app(msg.app_id,APICommand::DBDeleteResponse(msg));
+                self.send_reply_to_app(msg.app_id,APICommand::DBDeleteResponse(msg), request_id);
             }
             _ => {
                 info!("invalid command sent to database! {:?}",cmd)
             }
         }
     }
-    fn send_to_audio(&mut self, cmd: APICommand) {
+    fn send_to_audio(&mut self, cmd: APICommand, request_id:Option<u64>) {
         match cmd {
             APICommand::AudioPlayTrackRequest(req) => {
                 // if let Some(processor) = self.audio_service.load_track(&req.track, &self.db.base_path) {
@@ -259,8 +591,15 @@ impl CentralState {
                 //         success: true,
                 //         track: req.track,
                 //     };
-                //     self.send_to_app(msg.app_id, APICommand::AudioPlayTrackResponse(msg))
+                //     self.send_reply_to_app(msg.app_id, APICommand::AudioPlayTrackResponse(msg), request_id)
                 // }
+                // the audio service is turned off for now. say so instead of leaving the app waiting.
+                let msg = AudioPlayTrackResponse {
+                    app_id: req.app_id,
+                    success: false,
+                    track: req.track,
+                };
+                self.send_reply_to_app(msg.app_id, APICommand::AudioPlayTrackResponse(msg), request_id)
             }
             APICommand::AudioPauseTrackRequest(req) => {
                 let mut msg = AudioPauseTrackResponse {
@@ -274,13 +613,95 @@ impl CentralState {
                 // } else {
                 //     msg.success = false
                 // }
-                self.send_to_app(msg.app_id, APICommand::AudioPauseTrackResponse(msg))
+                self.send_reply_to_app(msg.app_id, APICommand::AudioPauseTrackResponse(msg), request_id)
             }
             _ => {
                 info!("invalid command sent to audio! {:?}",cmd)
             }
         }
     }
+    // keep it in the history, give it an id and put it on screen
+    fn post_notification(&mut self, mut cmd: PostNotification, request_id:Option<u64>) {
+        cmd.notification_id = Uuid::new_v4();
+        if cmd.actions.len() > MAX_NOTIFICATION_ACTIONS {
+            warn!("app {} sent {} notification actions. only showing the first {}", cmd.app_id, cmd.actions.len(), MAX_NOTIFICATION_ACTIONS);
+            cmd.actions.truncate(MAX_NOTIFICATION_ACTIONS);
+        }
+        let mut obj = JObj::make();
+        obj.data.insert(String::from("type"), String::from(NOTIFICATION_TYPE));
+        obj.data.insert(String::from("notification_id"), cmd.notification_id.to_string());
+        obj.data.insert(String::from("app_id"), cmd.app_id.to_string());
+        obj.data.insert(String::from("app_name"), self.app_info(cmd.app_id).app_name);
+        obj.data.insert(String::from("title"), cmd.title.clone());
+        obj.data.insert(String::from("body"), cmd.body.clone());
+        obj.data.insert(String::from("posted"), SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string());
+        self.db.process_add(obj);
+        let resp = PostNotificationResponse { app_id: cmd.app_id, notification_id: cmd.notification_id };
+        self.send_reply_to_app(cmd.app_id, APICommand::PostNotificationResponse(resp), request_id);
+        self.send_to_all_wm(APICommand::PostNotification(cmd));
+    }
+    // the history remembers which action the user picked. only the app which posted it hears about it.
+    fn notification_action(&mut self, evt: NotificationActionInvoked) {
+        let mut query = JQuery::new();
+        query.add_equal("type", NOTIFICATION_TYPE);
+        query.add_equal("notification_id", &evt.notification_id.to_string());
+        query.add_equal("app_id", &evt.app_id.to_string());
+        let found = self.db.process_query(&query);
+        if found.is_empty() {
+            warn!("app {} didn't post notification {}", evt.app_id, evt.notification_id);
+            return;
+        }
+        for mut obj in found {
+            obj.data.insert(String::from("action"), evt.action_id.clone());
+            self.db.process_update(obj);
+        }
+        self.send_to_app(evt.app_id, APICommand::NotificationActionInvoked(evt));
+    }
+    // tell the app which started a drag how it ended
+    fn end_drag(&mut self, drag_id:Uuid, accepted:bool) {
+        if let Some(drag) = self.drags.remove(&drag_id) {
+            let (app_id, window_id) = (drag.app_id, drag.window_id);
+            self.send_to_app(app_id, APICommand::DragEnded(DragEnded { app_id, window_id, drag_id, accepted }));
+        }
+    }
+    // drops whose target never answered
+    fn expire_drops(&mut self, now:Instant) {
+        let drags:Vec<Uuid> = self.drags.iter().filter(|(_, d)| d.dropped_at.is_some_and(|at| now.duration_since(at) > DROP_RESPONSE_TIMEOUT)).map(|(drag_id, _)| *drag_id).collect();
+        for drag_id in drags {
+            warn!("drop {} was never answered", drag_id);
+            self.end_drag(drag_id, false);
+        }
+    }
+    // whether a message about a drag came from the window manager running it
+    fn runs_drag(&self, drag_id:Uuid, wm_id:Uuid) -> bool {
+        self.drags.get(&drag_id).is_some_and(|d| d.wm_id == wm_id)
+    }
+    fn send_to_clipboard(&mut self, cmd: APICommand, request_id:Option<u64>) {
+        match cmd {
+            APICommand::ClipboardSet(req) => {
+                self.clipboard.set(req.app_id, req.items);
+                let mime_types = self.clipboard.mime_types();
+                let app_ids:Vec<Uuid> = self.apps.iter().filter(|a|a.greeted).map(|a|a.id).collect();
+                for app_id in app_ids {
+                    self.send_to_app(app_id, APICommand::ClipboardChanged(ClipboardChanged {
+                        app_id,
+                        owner: req.app_id,
+                        mime_types: mime_types.clone(),
+                    }));
+                }
+            }
+            APICommand::ClipboardGet(req) => {
+                let msg = ClipboardGetResponse {
+                    app_id: req.app_id,
+                    items: self.clipboard.get(&req.mime_types),
+                };
+                self.send_reply_to_app(msg.app_id, APICommand::ClipboardGetResponse(msg), request_id)
+            }
+            _ => {
+                info!("invalid command sent to the clipboard! {:?}",cmd)
+            }
+        }
+    }
 }
 
 fn to_query(clauses: Vec<DBQueryClause>) -> JQuery {
@@ -307,43 +728,112 @@ fn main() {
         PathBuf::from("../db/test_data.json")
     };
     info!("using database at {:?}",file.to_str());
-    let state = Arc::new(Mutex::new(CentralState::init(file)));
+    let queue_config = QueueConfig {
+        capacity: args.queue_size,
+        policy: args.overflow_policy,
+    };
+    let journal = match &args.record {
+        Some(path) => match Journal::create(path) {
+            Ok(journal) => {
+                info!("recording all traffic to {:?}",path);
+                Some(Arc::new(journal))
+            }
+            Err(e) => {
+                error!("couldn't create the recording {:?}: {}",path,e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let state = Arc::new(Mutex::new(CentralState::init(file, queue_config, journal)));
     let stop:Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     setup_c_handler(stop.clone());
     let (tx, rx) = mpsc::channel::<IncomingMessage>();
     // let cls = move |stream:TcpStream, tx:Sender<IncomingMessage>, stop:Arc<AtomicBool>, state:Arc<Mutex<CentralState>>| {
     // };
-    let app_network_thread = setup_interface(stop.clone(),tx.clone(), state.clone(),
-                                                     String::from("app"),APP_MANAGER_PORT,
-                                                     |stream,tx,stop,state|{
-                                                         state.lock().unwrap().add_app_from_stream(stream,tx,stop.clone());
-                                                     });
-    let wm_network_thread = setup_interface(stop.clone(),tx.clone(), state.clone(),
-                                                    String::from("winman"),
-                                                    WINDOW_MANAGER_PORT,
-                                                    |stream,tx,stop,state|{
-                                                        state.lock().unwrap().add_wm_from_stream(stream.try_clone().unwrap(),tx.clone(),stop.clone());
-                                                    });
-    let debug_network_thread = setup_interface(stop.clone(),tx.clone(), state.clone(),
-                                                    String::from("debug"),
-                                                    DEBUG_PORT,
-                                                    |stream,tx,stop,state|{
-                                                        state.lock().unwrap().add_debugger_from_stream(stream.try_clone().unwrap(), tx.clone(), stop.clone());
-                                                    });
+    let mut interfaces = vec![];
+    let socket_dir = args.socket_dir.or_else(default_socket_dir);
+    let mut addresses = vec![];
+    if !args.no_tcp {
+        let host = &args.tcp_address;
+        let tcp = |port| Address::Tcp(format!("{}:{}", host, port));
+        addresses.push((tcp(APP_MANAGER_PORT), tcp(WINDOW_MANAGER_PORT), tcp(DEBUG_PORT)));
+    }
+    #[cfg(unix)]
+    if let Some(dir) = socket_dir {
+        addresses.push((Address::Unix(dir.join("app.sock")), Address::Unix(dir.join("wm.sock")), Address::Unix(dir.join("debug.sock"))));
+    }
+    // bind everything before starting, so a second central stops here instead of taking over the first one's sockets
+    let mut listeners = vec![];
+    for (app, wm, debug) in addresses {
+        match (bind_interface("app", &app), bind_interface("winman", &wm), bind_interface("debug", &debug)) {
+            (Some(app), Some(wm), Some(debug)) => listeners.push((app, wm, debug)),
+            _ => return,
+        }
+    }
+    for (app, wm, debug) in listeners {
+        interfaces.push(setup_interface(stop.clone(),tx.clone(), state.clone(),
+                                        String::from("app"),app,
+                                        |stream,tx,stop,state|{
+                                            state.lock().unwrap().add_app_from_stream(stream,tx,stop.clone());
+                                        }));
+        interfaces.push(setup_interface(stop.clone(),tx.clone(), state.clone(),
+                                        String::from("winman"),
+                                        wm,
+                                        |stream,tx,stop,state|{
+                                            state.lock().unwrap().add_wm_from_stream(stream.try_clone().unwrap(),tx.clone(),stop.clone());
+                                        }));
+        interfaces.push(setup_interface(stop.clone(),tx.clone(), state.clone(),
+                                        String::from("debug"),
+                                        debug,
+                                        |stream,tx,stop,state|{
+                                            state.lock().unwrap().add_debugger_from_stream(stream.try_clone().unwrap(), tx.clone(), stop.clone());
+                                        }));
+    }
     let router_thread = start_router(stop.clone(),rx,state.clone());
-    info!("waiting for the app interface thread to end");
-    app_network_thread.join();
-    info!("waiting for the wm interface thread to end");
-    wm_network_thread.join();
-    info!("waiting for the debug interface thread to end");
-    debug_network_thread.join();
+    info!("waiting for the interface threads to end");
+    for interface in interfaces {
+        interface.join();
+    }
     info!("central server stopping");
 }
 
+fn bind_interface(name:&str, address:&Address) -> Option<Listener> {
+    match Listener::bind(address) {
+        Ok(listener) => {
+            info!("starting {} interface on {}", name, address);
+            Some(listener)
+        }
+        Err(e) => {
+            error!("couldn't start the {} interface on {}: {}", name, address, e);
+            None
+        }
+    }
+}
+
 fn start_router(stop: Arc<AtomicBool>, rx: Receiver<IncomingMessage>, state: Arc<Mutex<CentralState>>) -> JoinHandle<()> {
     thread::spawn(move||{
         info!("router thread starting");
-        for msg in rx {
+        loop {
+            // wake up now and then, to end drops nobody answered
+            let msg = match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => {
+                    state.lock().unwrap().expire_drops(Instant::now());
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            {
+                let mut st = state.lock().unwrap();
+                st.expire_drops(Instant::now());
+                // a rejected client's reader can still hand on what it sent after its hello
+                if !st.is_client(msg.source) {
+                    info!("dropping a message from {}, which is gone", msg.source);
+                    continue;
+                }
+                st.record_inbound(&msg);
+            }
             let msg2 = msg.clone();
             if msg.trace {
                 info!("==== trace: ====== {:?}",msg);
@@ -365,8 +855,20 @@ fn start_router(stop: Arc<AtomicBool>, rx: Receiver<IncomingMessage>, state: Arc
                 APICommand::Debug(DebugMessage::BackgroundReceivedMouseEvent) => {
                     state.lock().unwrap().send_to_debugger(DebugMessage::BackgroundReceivedMouseEvent);
                 }
-                APICommand::Debug(DebugMessage::WindowFocusChanged(str)) => {
-                    state.lock().unwrap().send_to_debugger(DebugMessage::WindowFocusChanged(str));
+                APICommand::Debug(DebugMessage::WindowFocusChanged(info)) => {
+                    let mut st = state.lock().unwrap();
+                    let info = st.window_info(info.app_id, info.window_id);
+                    st.send_to_debugger(DebugMessage::WindowFocusChanged(info));
+                }
+                APICommand::Debug(DebugMessage::WindowManagerDisconnected) => {
+                    let mut st = state.lock().unwrap();
+                    if st.drop_wm(msg.source) {
+                        info!("window manager disconnected {}",msg.source);
+                        st.send_to_debugger(DebugMessage::WindowManagerDisconnected);
+                    }
+                }
+                APICommand::Debug(DebugMessage::DebuggerDisconnected) => {
+                    state.lock().unwrap().drop_debugger(msg.source);
                 }
                 APICommand::Debug(DebugMessage::AppLog(str)) => {
                     state.lock().unwrap().send_to_debugger(DebugMessage::AppLog(str));
@@ -392,89 +894,369 @@ fn start_router(stop: Arc<AtomicBool>, rx: Receiver<IncomingMessage>, state: Arc
                     break;
                 }
                 APICommand::AppConnect(ap) => {
-                    info!("app connected {}",msg.source);
-                    let resp = APICommand::AppConnectResponse(HelloAppResponse{
-                        app_id: msg.source
-                    });
-                    state.lock().unwrap().send_to_app(msg.source, resp.clone());
-                    state.lock().unwrap().send_to_all_wm(resp.clone());
-                    state.lock().unwrap().send_to_debugger(DebugMessage::AppConnected(String::from("foo")))
+                    info!("app connected {} {:?} version {}",msg.source, ap.client_name, ap.protocol_version);
+                    match negotiate(ap.protocol_version, &ap.capabilities) {
+                        Ok(mut neg) => {
+                            let pid = state.lock().unwrap().app_pid(msg.source);
+                            restrict_capabilities(&mut neg.capabilities, pid);
+                            let resp = APICommand::AppConnectResponse(HelloAppResponse{
+                                app_id: msg.source,
+                                wire_format: ap.wire_format,
+                                protocol_version: neg.protocol_version,
+                                capabilities: neg.capabilities.clone(),
+                                rejected: None,
+                            });
+                            let mut st = state.lock().unwrap();
+                            st.set_app_hello(msg.source, ap.client_name, neg.capabilities, ap.metadata);
+                            // the response still goes out as json. switch formats after it.
+                            st.send_reply_to_app(msg.source, resp.clone(), msg.request_id);
+                            st.set_app_wire_format(msg.source, ap.wire_format);
+                            st.greet_app(msg.source);
+                            st.send_to_all_wm(resp);
+                            let info = st.app_info(msg.source);
+                            st.send_to_debugger(DebugMessage::AppConnected(info))
+                        }
+                        Err(reason) => {
+                            warn!("rejecting app {}: {}", msg.source, reason);
+                            let resp = APICommand::AppConnectResponse(HelloAppResponse{
+                                app_id: msg.source,
+                                wire_format: WireFormat::Json,
+                                protocol_version: PROTOCOL_VERSION,
+                                capabilities: vec![],
+                                rejected: Some(reason),
+                            });
+                            let mut st = state.lock().unwrap();
+                            st.send_reply_to_app(msg.source, resp, msg.request_id);
+                            st.drop_app(msg.source);
+                        }
+                    }
                 },
-                APICommand::AppDisconnected(dis) => {
-                    let resp = APICommand::AppDisconnected(dis);
-                    state.lock().unwrap().send_to_all_wm(resp.clone());
-                    state.lock().unwrap().send_to_debugger(DebugMessage::AppDisconnected(String::from("foo")))
+                APICommand::AppDisconnected(_) => {
+                    // either the app said goodbye or its connection dropped. only the sender can leave.
+                    let mut st = state.lock().unwrap();
+                    let info = st.app_info(msg.source);
+                    if st.drop_app(msg.source) {
+                        info!("app disconnected {} {:?}",msg.source, info.app_name);
+                        st.send_to_all_wm(APICommand::AppDisconnected(AppDisconnected { app_id: msg.source }));
+                        st.send_to_debugger(DebugMessage::AppDisconnected(info))
+                    }
                 }
-                APICommand::OpenWindowCommand(ow) => {
+                APICommand::OpenWindowCommand(mut ow) => {
                     info!("opening window");
-                    let winid = state.lock().unwrap().add_window_to_app(msg.source, &ow);
+                    if let Some(parent) = ow.parent {
+                        if state.lock().unwrap().app_window_mut(msg.source, parent).is_none() {
+                            warn!("opening the window without a parent");
+                            ow.parent = None;
+                        }
+                    }
+                    let winid = match state.lock().unwrap().add_window_to_app(msg.source, &ow) {
+                        Some(winid) => winid,
+                        None => {
+                            warn!("{} isn't a connected app. not opening the window", msg.source);
+                            continue;
+                        }
+                    };
                     let resp = APICommand::OpenWindowResponse(OpenWindowResponse{
                         app_id: msg.source,
                         window_id: winid,
                         window_type: ow.window_type.clone(),
                         bounds: ow.bounds.clone(),
                         window_title: ow.window_title,
+                        parent: ow.parent,
                     });
-                    state.lock().unwrap().send_to_app(msg.source, resp.clone());
+                    state.lock().unwrap().send_reply_to_app(msg.source, resp.clone(), msg.request_id);
                     state.lock().unwrap().send_to_all_wm(resp.clone());
-                    state.lock().unwrap().send_to_debugger(DebugMessage::WindowOpened(String::from("foo")));
+                    let info = state.lock().unwrap().window_info(msg.source, winid);
+                    state.lock().unwrap().send_to_debugger(DebugMessage::WindowOpened(info));
                 },
                 APICommand::CloseWindowResponse(e) => {
-                    state.lock().unwrap().send_to_app(e.app_id, APICommand::CloseWindowResponse(e))
+                    // the window manager has already taken the window off the screen
+                    let mut st = state.lock().unwrap();
+                    let info = st.window_info(e.app_id, e.window_id);
+                    st.remove_window_from_app(e.app_id, e.window_id);
+                    st.send_to_app(e.app_id, APICommand::CloseWindowResponse(e));
+                    st.send_to_debugger(DebugMessage::WindowClosed(info));
                 },
                 APICommand::WindowResized(e) => {
                     state.lock().unwrap().send_to_app(e.app_id, APICommand::WindowResized(e));
                 }
+                // apps can only change their own windows
+                APICommand::SetWindowTitle(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if let Some(win) = st.app_window_mut(cmd.app_id, cmd.window_id) {
+                        win.title = cmd.title.clone();
+                        st.send_to_all_wm(APICommand::SetWindowTitle(cmd));
+                    }
+                }
+                APICommand::SetWindowBounds(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if let Some(win) = st.app_window_mut(cmd.app_id, cmd.window_id) {
+                        win.bounds = cmd.bounds;
+                        st.send_to_all_wm(APICommand::SetWindowBounds(cmd));
+                    }
+                }
+                APICommand::SetWindowVisible(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if let Some(win) = st.app_window_mut(cmd.app_id, cmd.window_id) {
+                        win.visible = cmd.visible;
+                        st.send_to_all_wm(APICommand::SetWindowVisible(cmd));
+                    }
+                }
+                APICommand::RaiseWindow(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if st.app_window_mut(cmd.app_id, cmd.window_id).is_some() {
+                        st.send_to_all_wm(APICommand::RaiseWindow(cmd));
+                    }
+                }
+                APICommand::CloseWindowRequest(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if st.app_window_mut(cmd.app_id, cmd.window_id).is_some() {
+                        // the window managers take it and its children off the screen. the app hears
+                        // about each one the same way as when the user clicks the close button.
+                        st.send_to_all_wm(APICommand::CloseWindowRequest(cmd.clone()));
+                        for window_id in st.window_and_children(cmd.app_id, cmd.window_id) {
+                            let info = st.window_info(cmd.app_id, window_id);
+                            st.remove_window_from_app(cmd.app_id, window_id);
+                            st.send_to_app(cmd.app_id, APICommand::CloseWindowResponse(CloseWindowResponse { app_id: cmd.app_id, window_id }));
+                            st.send_to_debugger(DebugMessage::WindowClosed(info));
+                        }
+                    }
+                }
                 APICommand::WMConnect(cmd) => {
-                    let resp = APICommand::WMConnectResponse(HelloWindowManagerResponse{
-                        wm_id:msg.source
-                    });
-                    state.lock().unwrap().send_to_wm(msg.source, resp, msg.trace);
-                    state.lock().unwrap().send_to_debugger(DebugMessage::WindowManagerConnected);
+                    info!("window manager connected {} {:?} version {}",msg.source, cmd.client_name, cmd.protocol_version);
+                    match negotiate(cmd.protocol_version, &cmd.capabilities) {
+                        Ok(mut neg) => {
+                            let pid = state.lock().unwrap().wm_pid(msg.source);
+                            restrict_capabilities(&mut neg.capabilities, pid);
+                            let resp = APICommand::WMConnectResponse(HelloWindowManagerResponse{
+                                wm_id:msg.source,
+                                wire_format: cmd.wire_format,
+                                protocol_version: neg.protocol_version,
+                                capabilities: neg.capabilities.clone(),
+                                rejected: None,
+                            });
+                            let mut st = state.lock().unwrap();
+                            st.send_to_wm(msg.source, resp, msg.trace);
+                            st.set_wm_wire_format(msg.source, cmd.wire_format);
+                            let shared = neg.capabilities.iter().any(|c| c == capability::SHARED_MEMORY);
+                            st.set_wm_hello(msg.source, cmd.client_name, neg.capabilities);
+                            st.greet_wm(msg.source);
+                            if shared {
+                                st.send_shared_buffers_to_wm(msg.source);
+                            }
+                            st.send_to_debugger(DebugMessage::WindowManagerConnected);
+                        }
+                        Err(reason) => {
+                            warn!("rejecting window manager {}: {}", msg.source, reason);
+                            let resp = APICommand::WMConnectResponse(HelloWindowManagerResponse{
+                                wm_id:msg.source,
+                                wire_format: WireFormat::Json,
+                                protocol_version: PROTOCOL_VERSION,
+                                capabilities: vec![],
+                                rejected: Some(reason),
+                            });
+                            let mut st = state.lock().unwrap();
+                            st.send_to_wm(msg.source, resp, msg.trace);
+                            st.drop_wm(msg.source);
+                        }
+                    }
                 }
                 APICommand::DrawRectCommand(cmd) => {
                     state.lock().unwrap().send_to_all_wm(APICommand::DrawRectCommand(cmd));
                 },
                 APICommand::DrawImageCommand(cmd) => {
-                    state.lock().unwrap().send_to_all_wm(APICommand::DrawImageCommand(cmd));
+                    // decoding can take a while, so it happens without holding the lock
+                    let plain = if state.lock().unwrap().needs_plain_image(&cmd) {
+                        let mut plain = cmd.clone();
+                        match plain.decode() {
+                            Ok(()) => Some(plain),
+                            Err(e) => {
+                                warn!("couldn't decode {:?} image from app {}: {}", cmd.encoding, cmd.app_id, e);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    state.lock().unwrap().send_draw_image_to_all_wm(cmd, plain);
+                },
+                APICommand::DrawBatchCommand(cmd) => {
+                    state.lock().unwrap().send_draw_batch_to_all_wm(cmd);
+                },
+                APICommand::DrawSharedImageCommand(mut cmd) => {
+                    // apps can only draw from their own buffers
+                    cmd.app_id = msg.source;
+                    state.lock().unwrap().send_shared_draw_to_all_wm(cmd);
+                },
+                APICommand::RegisterSharedBuffer(cmd) => {
+                    let mut st = state.lock().unwrap();
+                    let buffer_id = cmd.info.buffer_id;
+                    let success = match st.register_shared_buffer(msg.source, &cmd.info) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            warn!("couldn't register shared buffer {} from app {}: {}", buffer_id, msg.source, e);
+                            false
+                        }
+                    };
+                    let resp = RegisterSharedBufferResponse { app_id: msg.source, buffer_id, success };
+                    st.send_reply_to_app(msg.source, APICommand::RegisterSharedBufferResponse(resp), msg.request_id);
+                    if success {
+                        st.send_to_wms_with(capability::SHARED_MEMORY, APICommand::RegisterSharedBuffer(RegisterSharedBuffer { app_id: msg.source, info: cmd.info }));
+                    }
+                },
+                APICommand::UnregisterSharedBuffer(mut cmd) => {
+                    let mut st = state.lock().unwrap();
+                    cmd.app_id = msg.source;
+                    if st.unregister_shared_buffer(cmd.app_id, cmd.buffer_id) {
+                        st.send_to_wms_with(capability::SHARED_MEMORY, APICommand::UnregisterSharedBuffer(cmd));
+                    }
                 },
 
                 APICommand::DBQueryRequest(cmd) => {
-                    state.lock().unwrap().send_to_database(APICommand::DBQueryRequest(cmd))
+                    state.lock().unwrap().send_to_database(APICommand::DBQueryRequest(cmd), msg.request_id)
                 }
                 APICommand::DBQueryResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::DBQueryResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::DBQueryResponse(cmd), msg.request_id)
                 }
                 APICommand::DBAddRequest(cmd) => {
-                    state.lock().unwrap().send_to_database(APICommand::DBAddRequest(cmd))
+                    state.lock().unwrap().send_to_database(APICommand::DBAddRequest(cmd), msg.request_id)
                 }
                 APICommand::DBAddResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::DBAddResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::DBAddResponse(cmd), msg.request_id)
                 }
                 APICommand::DBUpdateRequest(cmd) => {
-                    state.lock().unwrap().send_to_database(APICommand::DBUpdateRequest(cmd))
+                    state.lock().unwrap().send_to_database(APICommand::DBUpdateRequest(cmd), msg.request_id)
                 }
                 APICommand::DBUpdateResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::DBUpdateResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::DBUpdateResponse(cmd), msg.request_id)
                 }
                 APICommand::DBDeleteRequest(cmd) => {
-                    state.lock().unwrap().send_to_database(APICommand::DBDeleteRequest(cmd))
+                    state.lock().unwrap().send_to_database(APICommand::DBDeleteRequest(cmd), msg.request_id)
                 }
                 APICommand::DBDeleteResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::DBDeleteResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::DBDeleteResponse(cmd), msg.request_id)
                 }
 
                 APICommand::AudioPlayTrackRequest(cmd) => {
-                    state.lock().unwrap().send_to_audio(APICommand::AudioPlayTrackRequest(cmd))
+                    state.lock().unwrap().send_to_audio(APICommand::AudioPlayTrackRequest(cmd), msg.request_id)
                 }
                 APICommand::AudioPlayTrackResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::AudioPlayTrackResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::AudioPlayTrackResponse(cmd), msg.request_id)
                 }
                 APICommand::AudioPauseTrackRequest(cmd) => {
-                    state.lock().unwrap().send_to_audio(APICommand::AudioPauseTrackRequest(cmd))
+                    state.lock().unwrap().send_to_audio(APICommand::AudioPauseTrackRequest(cmd), msg.request_id)
                 }
                 APICommand::AudioPauseTrackResponse(cmd) => {
-                    state.lock().unwrap().send_to_app(cmd.app_id, APICommand::AudioPauseTrackResponse(cmd))
+                    state.lock().unwrap().send_reply_to_app(cmd.app_id, APICommand::AudioPauseTrackResponse(cmd), msg.request_id)
+                }
+
+                APICommand::ClipboardSet(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    state.lock().unwrap().send_to_clipboard(APICommand::ClipboardSet(cmd), msg.request_id)
+                }
+                APICommand::ClipboardGet(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    state.lock().unwrap().send_to_clipboard(APICommand::ClipboardGet(cmd), msg.request_id)
+                }
+                // from the window manager, for the focused window
+                APICommand::ClipboardShortcut(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.is_wm(msg.source) {
+                        st.send_to_app(e.app_id, APICommand::ClipboardShortcut(e))
+                    } else {
+                        warn!("ignoring a clipboard shortcut from {}, which isn't a window manager", msg.source);
+                    }
+                }
+
+                APICommand::PostNotification(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    state.lock().unwrap().post_notification(cmd, msg.request_id)
+                }
+                // from the window manager
+                APICommand::NotificationActionInvoked(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.is_wm(msg.source) {
+                        st.notification_action(e)
+                    } else {
+                        warn!("ignoring a notification action from {}, which isn't a window manager", msg.source);
+                    }
+                }
+
+                APICommand::SetKeyboardLayout(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    state.lock().unwrap().send_to_all_wm(APICommand::SetKeyboardLayout(cmd))
+                }
+                // from the window manager, once it has loaded the layout
+                APICommand::KeyboardLayoutChanged(e) => {
+                    state.lock().unwrap().send_to_all_apps(APICommand::KeyboardLayoutChanged(e))
+                }
+
+                APICommand::StartDrag(mut cmd) => {
+                    cmd.app_id = msg.source;
+                    let mut st = state.lock().unwrap();
+                    if st.app_window_mut(cmd.app_id, cmd.window_id).is_some() {
+                        // only the window manager with the mouse can run it
+                        let wm_id = st.input_wm.filter(|id| st.wms.iter().any(|w| w.id == *id && w.greeted));
+                        let (app_id, window_id) = (cmd.app_id, cmd.window_id);
+                        match wm_id {
+                            Some(wm_id) => {
+                                st.drags.insert(cmd.drag_id, Drag { app_id, window_id, wm_id, dropped_on: None, dropped_at: None });
+                                st.send_to_wm(wm_id, APICommand::StartDrag(cmd), msg.trace);
+                            }
+                            None => st.send_to_app(app_id, APICommand::DragEnded(DragEnded { app_id, window_id, drag_id: cmd.drag_id, accepted: false })),
+                        }
+                    }
+                }
+                // from the window manager, for the window under the mouse
+                APICommand::DragEnter(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.runs_drag(e.drag_id, msg.source) {
+                        st.send_to_app(e.app_id, APICommand::DragEnter(e))
+                    }
+                }
+                APICommand::DragOver(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.runs_drag(e.drag_id, msg.source) {
+                        st.send_to_app(e.app_id, APICommand::DragOver(e))
+                    }
+                }
+                APICommand::DragLeave(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.runs_drag(e.drag_id, msg.source) {
+                        st.send_to_app(e.app_id, APICommand::DragLeave(e))
+                    }
+                }
+                APICommand::Drop(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.runs_drag(e.drag_id, msg.source) {
+                        if let Some(drag) = st.drags.get_mut(&e.drag_id) {
+                            drag.dropped_on = Some((e.app_id, e.window_id));
+                            drag.dropped_at = Some(Instant::now());
+                        }
+                        st.send_to_app(e.app_id, APICommand::Drop(e))
+                    }
+                }
+                // the target took the drop or not
+                APICommand::DropResponse(cmd) => {
+                    let mut st = state.lock().unwrap();
+                    let target = st.drags.get(&cmd.drag_id).and_then(|d| d.dropped_on);
+                    if target == Some((msg.source, cmd.window_id)) {
+                        st.end_drag(cmd.drag_id, cmd.accepted)
+                    } else {
+                        warn!("ignoring a drop response for drag {} from {}, which wasn't dropped on", cmd.drag_id, msg.source);
+                    }
+                }
+                // the window manager ended the drag without a target
+                APICommand::DragEnded(e) => {
+                    let mut st = state.lock().unwrap();
+                    if st.runs_drag(e.drag_id, msg.source) {
+                        st.end_drag(e.drag_id, false)
+                    }
                 }
 
                 APICommand::KeyDown(e) => {
@@ -483,12 +1265,23 @@ fn start_router(stop: Arc<AtomicBool>, rx: Receiver<IncomingMessage>, state: Arc
                 APICommand::KeyUp(e) => {
                     state.lock().unwrap().send_to_app(e.app_id, APICommand::KeyUp(e))
                 }
+                APICommand::TextInput(e) => {
+                    state.lock().unwrap().send_to_app(e.app_id, APICommand::TextInput(e))
+                }
                 APICommand::MouseDown(e) => {
-                    state.lock().unwrap().send_to_app2(e.app_id, APICommand::MouseDown(e), &msg2)
+                    let mut st = state.lock().unwrap();
+                    let source = msg.source;
+                    if st.wms.iter().any(|w| w.id == source) {
+                        st.input_wm = Some(source);
+                    }
+                    st.send_to_app2(e.app_id, APICommand::MouseDown(e), &msg2)
                 }
                 APICommand::MouseUp(e) => {
                     state.lock().unwrap().send_to_app(e.app_id, APICommand::MouseUp(e))
                 }
+                APICommand::MouseWheel(e) => {
+                    state.lock().unwrap().send_to_app(e.app_id, APICommand::MouseWheel(e))
+                }
                 _ => {
                     warn!("CENTRAL: message not handled {:?}",msg);
                 }
@@ -505,6 +1298,24 @@ struct Cli {
     debug:bool,
     #[structopt(long, parse(from_os_str))]
     database: Option<PathBuf>,
+    // how many messages can wait to be written to a single client
+    #[structopt(long, default_value="1024")]
+    queue_size: usize,
+    // what to do when a client's queue fills up: drop-oldest-draw, coalesce, or disconnect
+    #[structopt(long, default_value="drop-oldest-draw")]
+    overflow_policy: OverflowPolicy,
+    // directory for the app.sock, wm.sock, and debug.sock unix sockets. defaults to $XDG_RUNTIME_DIR/clogwench
+    #[structopt(long, parse(from_os_str))]
+    socket_dir: Option<PathBuf>,
+    // only listen on the unix sockets, not on tcp ports
+    #[structopt(long)]
+    no_tcp: bool,
+    // the address the tcp ports listen on. use 0.0.0.0 to let other machines connect.
+    #[structopt(long, default_value="127.0.0.1")]
+    tcp_address: String,
+    // write every message to and from every client to this file. play it back with the replay tool.
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
 }
 
 
@@ -529,7 +1340,7 @@ fn start_network_interface<F>(stop: Arc<AtomicBool>,
 
     return thread::spawn(move || {
         info!("starting {} interface on port {}",name, port);
-        let listener = TcpListener::bind(format!("0.0.0.0:{}",port)).unwrap();
+        let listener = TcpListener::bind(format!("127.0.0.1:{}",port)).unwrap();
         listener.set_nonblocking(true).unwrap();
         loop {
             // println!("inside the {} loop",name);
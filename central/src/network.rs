@@ -3,30 +3,157 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use common::{APICommand, AppDisconnected, DebugMessage, IncomingMessage};
 use std::thread::{JoinHandle, sleep};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::fs;
+use std::fmt::{Display, Formatter};
+use std::net::{Shutdown, TcpListener};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
 use std::{io, thread};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use log::{error, info};
 use uuid::Uuid;
-use serde::Deserialize;
+use common::transport::ClientStream;
+use common::wire::{MessageReader, WireFormat};
 use crate::state::CentralState;
 
+/// somewhere central listens for clients
+pub enum Address {
+    // host:port
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "{:?}", path),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub fn bind(address: &Address) -> io::Result<Listener> {
+        let listener = match address {
+            Address::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                if let Some(dir) = path.parent() {
+                    // only the user running central can reach the sockets. a directory which
+                    // already exists is left the way its owner set it up.
+                    if !dir.exists() {
+                        fs::create_dir_all(dir)?;
+                        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+                    }
+                }
+                if let Ok(meta) = fs::symlink_metadata(path) {
+                    if !meta.file_type().is_socket() {
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is in the way and isn't a socket", path)));
+                    }
+                    // a socket left over from a crash would make bind fail. a live one still answers.
+                    if UnixStream::connect(path).is_ok() {
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("something is already listening on {:?}", path)));
+                    }
+                    fs::remove_file(path)?;
+                }
+                Listener::Unix(UnixListener::bind(path)?, path.clone())
+            }
+        };
+        match &listener {
+            Listener::Tcp(l) => l.set_nonblocking(true)?,
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+    // returns the new connection and a description of who is on the other end
+    fn accept(&self) -> io::Result<(ClientStream, String)> {
+        match self {
+            Listener::Tcp(l) => {
+                let (stream, addr) = l.accept()?;
+                Ok((ClientStream::Tcp(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l, path) => {
+                let (stream, _) = l.accept()?;
+                let desc = format!("{:?} {}", path, describe_peer(&stream));
+                Ok((ClientStream::Unix(stream), desc))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// the pid and user of the process on the other end of a unix socket
+#[cfg(target_os = "linux")]
+fn peer_cred(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+    if res == 0 {
+        Ok(cred)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+#[cfg(target_os = "linux")]
+fn describe_peer(stream: &UnixStream) -> String {
+    match peer_cred(stream) {
+        Ok(cred) => format!("pid {} uid {} gid {}", cred.pid, cred.uid, cred.gid),
+        Err(e) => format!("unknown peer: {}", e),
+    }
+}
+#[cfg(all(unix, not(target_os = "linux")))]
+fn describe_peer(_stream: &UnixStream) -> String {
+    String::from("unknown peer")
+}
+
+// the process on the other end, if it is on this machine. only known for unix sockets on linux.
+pub fn peer_pid(stream: &ClientStream) -> Option<u32> {
+    match stream {
+        #[cfg(target_os = "linux")]
+        ClientStream::Unix(s) => peer_cred(s).ok().map(|cred| cred.pid as u32),
+        _ => None,
+    }
+}
+
 pub fn setup_interface<F>(stop: Arc<AtomicBool>,
                           tx: Sender<IncomingMessage>,
                           state: Arc<Mutex<CentralState>>,
                           name: String,
-                          port: i32,
+                          listener: Listener,
                           cb: F
 ) -> JoinHandle<()>
     where
-        F: Fn(TcpStream, Sender<IncomingMessage>, Arc<AtomicBool>, Arc<Mutex<CentralState>>),
+        F: Fn(ClientStream, Sender<IncomingMessage>, Arc<AtomicBool>, Arc<Mutex<CentralState>>),
         F: Send + 'static,
 {
 
     return thread::spawn(move || {
-        info!("starting {} interface on port {}",name, port);
-        let listener = TcpListener::bind(format!("0.0.0.0:{}",port)).unwrap();
-        listener.set_nonblocking(true).unwrap();
         loop {
             // println!("inside the {} loop",name);
             sleep(Duration::from_millis(10));
@@ -55,13 +182,25 @@ pub fn setup_interface<F>(stop: Arc<AtomicBool>,
     })
 }
 
-pub fn spawn_client_handler(uuid: Uuid, stream: TcpStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+// true for errors which just mean the other end went away
+pub fn is_hangup(e: &io::Error) -> bool {
+    matches!(e.kind(),
+        io::ErrorKind::UnexpectedEof |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::BrokenPipe)
+}
+
+// reads messages from an app or window manager and passes them to the router.
+// when the connection drops the router gets the disconnected command, sent as if it came from the client.
+pub fn spawn_client_handler(uuid: Uuid, stream: ClientStream, sender: Sender<IncomingMessage>, stop: Arc<AtomicBool>, disconnected: APICommand) -> JoinHandle<()> {
     thread::spawn(move ||{
         info!("CENTRAL: client thread starting: {}",uuid);
         stream.set_nonblocking(false).unwrap();
         // info!("wm thread starting: {}",wm_id);
         let stream2 = stream.try_clone().unwrap();
-        let mut de = serde_json::Deserializer::from_reader(stream);
+        // everyone starts out speaking json. the hello message says what to switch to.
+        let mut reader = MessageReader::new(stream, WireFormat::Json);
         loop {
             if stop.load(Ordering::Relaxed) == true {
                 info!("wm thread stopping");
@@ -69,23 +208,46 @@ pub fn spawn_client_handler(uuid: Uuid, stream: TcpStream, sender: Sender<Incomi
                 break;
             }
             // read IncomingMessage from stream, convert to IncomingMessage, then send to sender
-            match IncomingMessage::deserialize(&mut de) {
+            match reader.read::<IncomingMessage>() {
                 Ok(cmd) => {
                     // info!("central received wm command {:?}",cmd);
+                    let next_format = match &cmd.command {
+                        APICommand::AppConnect(hello) => Some(hello.wire_format),
+                        APICommand::WMConnect(hello) => Some(hello.wire_format),
+                        _ => None,
+                    };
                     sender.send(IncomingMessage{
                         source: uuid,
                         command: cmd.command,
                         trace:cmd.trace,
                         timestamp_usec:cmd.timestamp_usec,
+                        request_id:cmd.request_id,
                     }).unwrap();
+                    if let Some(format) = next_format {
+                        reader.format = format;
+                    }
                 }
                 Err(e) => {
-                    error!("error deserializing from window manager {:?}",e);
-                    stream2.shutdown(Shutdown::Both);
+                    if is_hangup(&e) {
+                        info!("client {} hung up",uuid);
+                    } else {
+                        error!("error deserializing from client {} {:?}",uuid,e);
+                    }
+                    let _ = stream2.shutdown(Shutdown::Both);
                     break;
                 }
             }
         }
+        // nobody needs to clean up after a server shutdown
+        if !stop.load(Ordering::Relaxed) {
+            let _ = sender.send(IncomingMessage {
+                source: uuid,
+                command: disconnected,
+                trace: false,
+                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                request_id: None,
+            });
+        }
         info!("client {} thread ending", uuid);
     })
 }
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::Shutdown;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use log::{error, info, warn};
+use uuid::Uuid;
+use common::{APICommand, IncomingMessage};
+use common::journal::{Direction, Journal, PeerKind};
+use common::transport::ClientStream;
+use common::wire::{encode_message, WireFormat};
+use gfx::graphics::Rect;
+
+// a peer which can't take a single frame in this long is treated as hung
+const WRITE_TIMEOUT:Duration = Duration::from_secs(5);
+
+/// what to do when a client's queue is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// throw away the oldest queued draw command
+    DropOldestDraw,
+    /// drop an older queued message for the same thing, like a mouse move to the same window,
+    /// and queue the new one at the back
+    Coalesce,
+    /// hang up on the client
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest-draw" => Ok(OverflowPolicy::DropOldestDraw),
+            "coalesce" => Ok(OverflowPolicy::Coalesce),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            _ => Err(format!("unknown overflow policy {}. use drop-oldest-draw, coalesce, or disconnect", s)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct QueueConfig {
+    pub capacity:usize,
+    pub policy:OverflowPolicy,
+}
+
+// messages which only matter in their latest form
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CoalesceKey {
+    MouseMove(Uuid),
+    WindowResized(Uuid),
+    DrawImage(Uuid, Rect),
+}
+
+struct Frame {
+    data:Vec<u8>,
+    draw:bool,
+    key:Option<CoalesceKey>,
+}
+
+impl Frame {
+    fn raw(data:Vec<u8>) -> Frame {
+        Frame { data, draw: false, key: None }
+    }
+    fn for_command(data:Vec<u8>, cmd:&APICommand) -> Frame {
+        let draw = matches!(cmd, APICommand::DrawRectCommand(_) | APICommand::DrawImageCommand(_) | APICommand::DrawBatchCommand(_) | APICommand::DrawSharedImageCommand(_));
+        let key = match cmd {
+            APICommand::MouseMove(evt) => Some(CoalesceKey::MouseMove(evt.window_id)),
+            APICommand::WindowResized(evt) => Some(CoalesceKey::WindowResized(evt.window_id)),
+            APICommand::DrawImageCommand(cmd) => Some(CoalesceKey::DrawImage(cmd.window_id, cmd.rect)),
+            _ => None,
+        };
+        Frame { data, draw, key }
+    }
+}
+
+struct FrameQueue {
+    frames:VecDeque<Frame>,
+    // no more frames will be accepted. the writer finishes what is queued and then hangs up.
+    closed:bool,
+}
+
+impl FrameQueue {
+    fn new() -> FrameQueue {
+        FrameQueue { frames: VecDeque::new(), closed: false }
+    }
+    // Err means the queue is full and the policy couldn't make room, so the client must go
+    fn push(&mut self, frame:Frame, config:&QueueConfig) -> Result<(),String> {
+        if self.frames.len() < config.capacity {
+            self.frames.push_back(frame);
+            return Ok(());
+        }
+        match config.policy {
+            OverflowPolicy::DropOldestDraw => {
+                if let Some(n) = self.frames.iter().position(|f| f.draw) {
+                    self.frames.remove(n);
+                    self.frames.push_back(frame);
+                    return Ok(());
+                }
+            }
+            OverflowPolicy::Coalesce => {
+                if let Some(key) = frame.key {
+                    // the new one goes at the back, so it still comes after everything queued before it
+                    if let Some(n) = self.frames.iter().position(|f| f.key == Some(key)) {
+                        self.frames.remove(n);
+                        self.frames.push_back(frame);
+                        return Ok(());
+                    }
+                }
+            }
+            OverflowPolicy::Disconnect => {}
+        }
+        Err(format!("outbound queue is full with {} messages", self.frames.len()))
+    }
+}
+
+/// The sending half of a client connection. Messages are encoded on the caller's
+/// thread and written by a writer thread owned by this client, so a slow or hung
+/// peer only ever blocks itself.
+pub struct Outbound {
+    id:Uuid,
+    stream:ClientStream,
+    config:QueueConfig,
+    queue:Arc<(Mutex<FrameQueue>, Condvar)>,
+    // where to record everything sent, when recording
+    journal:Option<(Arc<Journal>, PeerKind)>,
+}
+
+impl Outbound {
+    pub fn start(id:Uuid, stream:ClientStream, config:QueueConfig, journal:Option<(Arc<Journal>, PeerKind)>) -> Outbound {
+        if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            warn!("couldn't set a write timeout for {}: {}",id,e);
+        }
+        let queue = Arc::new((Mutex::new(FrameQueue::new()), Condvar::new()));
+        thread::spawn({
+            let queue = queue.clone();
+            let mut stream = stream.try_clone().unwrap();
+            move || {
+                loop {
+                    let frame = {
+                        let (lock, cvar) = &*queue;
+                        let mut q = lock.lock().unwrap();
+                        while q.frames.is_empty() && !q.closed {
+                            q = cvar.wait(q).unwrap();
+                        }
+                        match q.frames.pop_front() {
+                            Some(frame) => frame,
+                            None => break,
+                        }
+                    };
+                    if let Err(e) = stream.write_all(&frame.data) {
+                        error!("error writing to client {}: {}",id,e);
+                        let (lock, _) = &*queue;
+                        let mut q = lock.lock().unwrap();
+                        q.closed = true;
+                        q.frames.clear();
+                        break;
+                    }
+                }
+                // the reader thread sees the hangup and tells the router to clean up
+                let _ = stream.shutdown(Shutdown::Both);
+                info!("writer thread for {} ending",id);
+            }
+        });
+        Outbound {
+            id,
+            stream,
+            config,
+            queue,
+            journal,
+        }
+    }
+    pub fn send(&self, msg:&IncomingMessage, format:WireFormat) {
+        if let Some((journal, peer)) = &self.journal {
+            journal.record(Direction::Outbound, *peer, self.id, msg);
+        }
+        match encode_message(msg, format) {
+            Ok(data) => self.push(Frame::for_command(data, &msg.command)),
+            Err(e) => error!("error encoding message for {}: {}",self.id,e),
+        }
+    }
+    // for already encoded data, like the json debugger stream
+    pub fn send_raw(&self, data:Vec<u8>) {
+        self.push(Frame::raw(data))
+    }
+    // send whatever is still queued, then hang up
+    pub fn close(&self) {
+        let (lock, cvar) = &*self.queue;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_one();
+    }
+    fn push(&self, frame:Frame) {
+        let (lock, cvar) = &*self.queue;
+        let mut q = lock.lock().unwrap();
+        if q.closed {
+            return;
+        }
+        if let Err(e) = q.push(frame, &self.config) {
+            warn!("disconnecting client {}: {}",self.id,e);
+            q.closed = true;
+            q.frames.clear();
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+        cvar.notify_one();
+    }
+}
+
+impl Drop for Outbound {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use common::APICommand;
+    use common::events::{MouseButton, MouseMoveEvent};
+    use common::DrawRectCommand;
+    use gfx::graphics::{ARGBColor, Rect};
+    use crate::outbound::{Frame, FrameQueue, OverflowPolicy, QueueConfig};
+
+    fn draw(n:u8) -> Frame {
+        Frame::for_command(vec![n], &APICommand::DrawRectCommand(DrawRectCommand {
+            app_id: Default::default(),
+            window_id: Default::default(),
+            rect: Rect::from_ints(0, 0, 10, 10),
+            color: ARGBColor::new_rgb(0, 0, 0),
+        }))
+    }
+    fn mouse_move(n:u8, window_id:Uuid) -> Frame {
+        Frame::for_command(vec![n], &APICommand::MouseMove(MouseMoveEvent {
+            app_id: Default::default(),
+            window_id,
+            original_timestamp: 0,
+            button: MouseButton::Primary,
+            x: n as i32,
+            y: 0,
+        }))
+    }
+    fn contents(q:&FrameQueue) -> Vec<u8> {
+        q.frames.iter().map(|f| f.data[0]).collect()
+    }
+
+    #[test]
+    fn drop_oldest_draw() {
+        let config = QueueConfig { capacity: 3, policy: OverflowPolicy::DropOldestDraw };
+        let mut q = FrameQueue::new();
+        q.push(Frame::raw(vec![1]), &config).unwrap();
+        q.push(draw(2), &config).unwrap();
+        q.push(draw(3), &config).unwrap();
+        q.push(draw(4), &config).unwrap();
+        assert_eq!(contents(&q), vec![1, 3, 4]);
+        // nothing left to drop
+        let mut q = FrameQueue::new();
+        for n in 0..3 {
+            q.push(Frame::raw(vec![n]), &config).unwrap();
+        }
+        assert!(q.push(draw(4), &config).is_err());
+    }
+
+    #[test]
+    fn coalesce_mouse_moves() {
+        let config = QueueConfig { capacity: 2, policy: OverflowPolicy::Coalesce };
+        let win = Uuid::new_v4();
+        let mut q = FrameQueue::new();
+        q.push(mouse_move(1, win), &config).unwrap();
+        q.push(Frame::raw(vec![2]), &config).unwrap();
+        q.push(mouse_move(3, win), &config).unwrap();
+        assert_eq!(contents(&q), vec![2, 3]);
+        assert!(q.push(mouse_move(4, Uuid::new_v4()), &config).is_err());
+    }
+
+    #[test]
+    fn disconnect_when_full() {
+        let config = QueueConfig { capacity: 1, policy: OverflowPolicy::Disconnect };
+        let mut q = FrameQueue::new();
+        q.push(draw(1), &config).unwrap();
+        assert!(q.push(draw(2), &config).is_err());
+    }
+}
@@ -1,11 +1,40 @@
 // use audio::AudioService;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use common::journal::Journal;
 use db::JDB;
 use crate::{App, Debugger, WM};
+use crate::clipboard::Clipboard;
+use crate::outbound::QueueConfig;
 
 pub struct CentralState {
     pub(crate) wms:Vec<WM>,
     pub(crate) apps:Vec<App>,
     pub(crate) debuggers:Vec<Debugger>,
     pub(crate) db:JDB,
+    pub(crate) clipboard:Clipboard,
+    // drags in progress, by drag id
+    pub(crate) drags:HashMap<Uuid,Drag>,
+    // the window manager the user's mouse input last came from. drags are run by it.
+    pub(crate) input_wm:Option<Uuid>,
+    pub(crate) queue_config:QueueConfig,
+    // set when central was started with --record
+    pub(crate) journal:Option<Arc<Journal>>,
     // pub(crate) audio_service:AudioService,
 }
+
+// how long a drop target has to answer before the drag ends as refused
+pub(crate) const DROP_RESPONSE_TIMEOUT:Duration = Duration::from_secs(5);
+
+pub(crate) struct Drag {
+    // the app and window it started from
+    pub(crate) app_id:Uuid,
+    pub(crate) window_id:Uuid,
+    // the window manager running it. only it can end the drag or drop it.
+    pub(crate) wm_id:Uuid,
+    // the app and window it was dropped on, once it has been. only they can answer.
+    pub(crate) dropped_on:Option<(Uuid,Uuid)>,
+    pub(crate) dropped_at:Option<Instant>,
+}
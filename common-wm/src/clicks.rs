@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+use common::events::MouseButton;
+use gfx::graphics::Point;
+
+// how close together two clicks have to be to count as one double click
+const MULTI_CLICK_TIME:Duration = Duration::from_millis(400);
+const MULTI_CLICK_DISTANCE:i32 = 4;
+
+struct LastClick {
+    button:MouseButton,
+    at:Point,
+    time:Instant,
+    count:u32,
+}
+
+// counts double and triple clicks. after a triple click it starts again at one.
+#[derive(Default)]
+pub struct ClickCounter {
+    last:Option<LastClick>,
+}
+
+impl ClickCounter {
+    pub fn new() -> ClickCounter {
+        ClickCounter { last: None }
+    }
+    // a button went down. returns how many clicks in a row this makes.
+    pub fn click(&mut self, button:&MouseButton, at:Point, now:Instant) -> u32 {
+        let count = match &self.last {
+            Some(last) if last.button == *button
+                && now.duration_since(last.time) <= MULTI_CLICK_TIME
+                && (at.x - last.at.x).abs() <= MULTI_CLICK_DISTANCE
+                && (at.y - last.at.y).abs() <= MULTI_CLICK_DISTANCE => last.count % 3 + 1,
+            _ => 1,
+        };
+        self.last = Some(LastClick { button:button.clone(), at, time:now, count });
+        count
+    }
+    // the last press didn't go to an app, eg it was on a title bar. the next one starts again at one.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use common::events::MouseButton;
+    use gfx::graphics::Point;
+    use crate::clicks::ClickCounter;
+
+    #[test]
+    fn double_and_triple_clicks() {
+        let mut clicks = ClickCounter::new();
+        let start = Instant::now();
+        let at = |ms:u64| start + Duration::from_millis(ms);
+        let pt = Point::init(10, 10);
+        assert_eq!(clicks.click(&MouseButton::Primary, pt, at(0)), 1);
+        assert_eq!(clicks.click(&MouseButton::Primary, Point::init(11, 12), at(200)), 2);
+        assert_eq!(clicks.click(&MouseButton::Primary, pt, at(400)), 3);
+        assert_eq!(clicks.click(&MouseButton::Primary, pt, at(500)), 1);
+        // too slow
+        assert_eq!(clicks.click(&MouseButton::Primary, pt, at(1500)), 1);
+        // moved too far
+        assert_eq!(clicks.click(&MouseButton::Primary, Point::init(30, 10), at(1600)), 1);
+        // a click on the frame in between
+        clicks.reset();
+        assert_eq!(clicks.click(&MouseButton::Primary, Point::init(30, 10), at(1700)), 1);
+        // a different button starts again
+        assert_eq!(clicks.click(&MouseButton::Secondary, Point::init(30, 10), at(1700)), 1);
+        assert_eq!(clicks.click(&MouseButton::Secondary, Point::init(30, 10), at(1800)), 2);
+    }
+}
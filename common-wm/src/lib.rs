@@ -1,5 +1,7 @@
-use std::io::Write;
-use std::net::TcpStream;
+pub mod clicks;
+pub mod toasts;
+
+use std::collections::HashMap;
 use std::sync::{Arc, mpsc};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Sender};
@@ -8,14 +10,23 @@ use std::thread::JoinHandle;
 use std::time::{SystemTime, UNIX_EPOCH};
 use log::{error, info};
 use uuid::Uuid;
-use common::{APICommand, CloseWindowResponse, HelloWindowManager, IncomingMessage};
-use serde::{Deserialize, Serialize};
-use common::events::{MouseButton, MouseDownEvent, MouseMoveEvent, MouseUpEvent};
+use common::{APICommand, ClipboardAction, ClipboardItem, CloseWindowResponse, DragEnded, DragEnterEvent, DragLeaveEvent, DragOverEvent, DropEvent, KeyboardLayoutChanged, StartDrag, window_types, DrawSharedImageCommand, HelloWindowManager, IncomingMessage, SharedBufferInfo, WINDOW_MANAGER_PORT};
+use common::shm::SharedBuffer;
+use common::protocol::{default_capabilities, PROTOCOL_VERSION};
+use common::transport::{ClientStream, connect, WM_SOCKET_ENV};
+use common::wire::{MessageReader, WireFormat, write_message};
+use common::events::{KeyDownEvent, MouseDownEvent, MouseMoveEvent, MouseUpEvent, MouseWheelEvent};
+use common::generated::KeyCode;
+use common::keymap::KeyboardLayout;
+use crate::clicks::ClickCounter;
+use crate::toasts::Toasts;
 use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect, Size};
 
 pub struct App {
     pub id:Uuid,
     pub windows:Vec<Window>,
+    // memfd buffers the app registered, mapped read only
+    pub shared_buffers:HashMap<Uuid,SharedBuffer>,
 }
 
 pub const TITLE_BAR_HEIGHT:i32 = 20;
@@ -29,6 +40,17 @@ pub const FOCUSED_TITLEBAR_COLOR:ARGBColor = ARGBColor { r: 200, g: 255, b: 200,
 pub enum WindowType {
     Plain(),
     Popup(),
+    Modal(),
+}
+
+impl WindowType {
+    pub fn from_name(name: &str) -> WindowType {
+        match name {
+            window_types::POPUP => WindowType::Popup(),
+            window_types::MODAL => WindowType::Modal(),
+            _ => WindowType::Plain(),
+        }
+    }
 }
 pub struct Window {
     pub id:Uuid,
@@ -38,6 +60,12 @@ pub struct Window {
     pub content_size: Size,
     pub window_type:WindowType,
     pub title:String,
+    // hidden windows aren't drawn and don't get clicks
+    pub visible:bool,
+    // popups and modals sit on top of another window of the same app
+    pub parent:Option<Uuid>,
+    // where a child window sits in its parent's contents. it moves when the parent does.
+    pub offset:Point,
 }
 
 impl Window {
@@ -45,10 +73,20 @@ impl Window {
         self.content_size.w = size.w;
         self.content_size.h = size.h;
     }
+    // popups have no title bar or border, so they can't be moved, resized or closed by the user
+    pub fn is_decorated(&self) -> bool {
+        !matches!(self.window_type, WindowType::Popup())
+    }
+    fn border_width(&self) -> i32 {
+        if self.is_decorated() { WINDOW_BORDER_WIDTH } else { 0 }
+    }
+    fn title_bar_height(&self) -> i32 {
+        if self.is_decorated() { TITLE_BAR_HEIGHT } else { 0 }
+    }
     pub fn content_bounds(&self) -> Rect {
         Rect {
-            x:self.position.x + WINDOW_BORDER_WIDTH,
-            y:self.position.y + WINDOW_BORDER_WIDTH + TITLE_BAR_HEIGHT,
+            x:self.position.x + self.border_width(),
+            y:self.position.y + self.border_width() + self.title_bar_height(),
             w:self.content_size.w,
             h:self.content_size.h,
         }
@@ -57,16 +95,16 @@ impl Window {
         Rect {
             x:self.position.x,
             y:self.position.y,
-            w:WINDOW_BORDER_WIDTH+self.content_size.w+WINDOW_BORDER_WIDTH,
-            h:WINDOW_BORDER_WIDTH+TITLE_BAR_HEIGHT+self.content_size.h+WINDOW_BORDER_WIDTH,
+            w:self.border_width()+self.content_size.w+self.border_width(),
+            h:self.border_width()+self.title_bar_height()+self.content_size.h+self.border_width(),
         }
     }
     pub fn titlebar_bounds(&self) -> Rect {
         Rect {
-            x:self.position.x + WINDOW_BORDER_WIDTH,
-            y:self.position.y + WINDOW_BORDER_WIDTH,
+            x:self.position.x + self.border_width(),
+            y:self.position.y + self.border_width(),
             w:self.content_size.w,
-            h:TITLE_BAR_HEIGHT,
+            h:self.title_bar_height(),
         }
     }
     pub fn resize_bounds(&self) -> Rect {
@@ -94,6 +132,10 @@ pub struct WindowManagerState {
     pub preferred_pixel_layout: PixelLayout,
     pub window_order:Vec<Uuid>,
     pub resize_rect:Option<Rect>,
+    // follows the mouse during a drag
+    pub drag_rect:Option<Rect>,
+    pub toasts:Toasts,
+    pub clicks:ClickCounter,
 }
 
 impl WindowManagerState {
@@ -103,14 +145,19 @@ impl WindowManagerState {
             focused: None,
             preferred_pixel_layout:ppl.clone(),
             window_order: vec![],
-            resize_rect:None
+            resize_rect:None,
+            drag_rect:None,
+            toasts:Toasts::new(),
+            clicks:ClickCounter::new(),
         }
     }
 
     pub fn get_windows_in_order(&self) -> Vec<&Window> {
         self.window_order.iter()
             .map(|winid| self.lookup_window(winid.clone()))
-            .filter_map(|o|o).collect::<Vec<&Window>>()
+            .filter_map(|o|o)
+            .filter(|win| win.visible)
+            .collect::<Vec<&Window>>()
     }
 
     pub fn is_focused_window(&self, win: &Window) -> bool {
@@ -124,7 +171,8 @@ impl WindowManagerState {
     pub fn add_app(&mut self, app_id: Uuid) {
         let app = App {
             id: app_id,
-            windows: vec![]
+            windows: vec![],
+            shared_buffers: HashMap::new(),
         };
         self.apps.push(app);
     }
@@ -132,14 +180,29 @@ impl WindowManagerState {
         self.apps.iter_mut().find(|a|a.id == app_id)
     }
     pub fn add_window(&mut self, app_id: Uuid, win_id:Uuid, bounds:&Rect, title: &String) -> Uuid {
+        self.add_child_window(app_id, win_id, bounds, title, WindowType::Plain(), None)
+    }
+    // popups and modals are placed relative to their parent's contents, and take the focus
+    pub fn add_child_window(&mut self, app_id: Uuid, win_id:Uuid, bounds:&Rect, title: &String, window_type:WindowType, parent:Option<Uuid>) -> Uuid {
+        let parent = parent.and_then(|id| self.lookup_window(id)).filter(|p| p.owner == app_id);
+        let position = match parent {
+            Some(p) => p.content_bounds().position().add(&bounds.position()),
+            None => bounds.position(),
+        };
+        let offset = bounds.position();
+        let parent = parent.map(|p| p.id);
+        let takes_focus = parent.is_some() && !matches!(window_type, WindowType::Plain());
         let mut win = Window {
             id: win_id,
-            position:bounds.position(),
+            position,
             content_size:bounds.size(),
             owner: app_id,
             backbuffer: GFXBuffer::new(bounds.w as u32, bounds.h as u32, &self.preferred_pixel_layout),
-            window_type: WindowType::Plain(),
+            window_type,
             title: title.clone(),
+            visible: true,
+            parent,
+            offset,
         };
         let bg_color:ARGBColor = ARGBColor::new_rgb(255, 128, 0);
         win.backbuffer.clear(&bg_color);
@@ -147,14 +210,24 @@ impl WindowManagerState {
         if let Some(app) = self.find_app(app_id) {
             app.windows.push(win);
         }
+        if takes_focus {
+            self.focused = Some(win_id);
+        }
         win_id
     }
 
     pub fn get_focused_window(&self) -> &Option<Uuid> {
         &self.focused
     }
+    // the window behind a dialog can't take the focus. the dialog gets it instead.
     pub fn set_focused_window(&mut self, winid:Uuid) {
-        self.focused = *&Some(winid);
+        self.focused = Some(self.blocking_modal(winid).unwrap_or(winid));
+    }
+    // the window which gets keyboard input. the focus may be on a window which a dialog
+    // has since opened over, or been shown again over.
+    pub fn keyboard_window(&self) -> Option<&Window> {
+        let focused = self.focused?;
+        self.lookup_window(self.blocking_modal(focused).unwrap_or(focused))
     }
     pub fn raise_window(&mut self, win_id: Uuid) {
         if let Some(n) = self.window_order.iter().position(|w|w.eq(&win_id)) {
@@ -164,11 +237,110 @@ impl WindowManagerState {
 
     }
 
+    pub fn set_window_title(&mut self, win_id: Uuid, title: &str) {
+        if let Some(win) = self.lookup_window_mut(win_id) {
+            win.title = title.to_string();
+        }
+    }
+    // moves the window and changes the size of its contents. the backbuffer is resized later,
+    // which is when the app gets told. child windows are placed relative to the parent's contents.
+    pub fn set_window_bounds(&mut self, win_id: Uuid, bounds: &Rect) {
+        let origin = self.parent_origin(win_id);
+        if let Some(win) = self.lookup_window_mut(win_id) {
+            win.set_size(Size::init(bounds.w.max(1), bounds.h.max(1)));
+        }
+        self.move_window(win_id, &origin.map_or(bounds.position(), |o| o.add(&bounds.position())));
+    }
+    // moves the window, along with the popups and dialogs on top of it
+    pub fn move_window(&mut self, win_id: Uuid, position: &Point) {
+        let origin = self.parent_origin(win_id);
+        if let Some(win) = self.lookup_window_mut(win_id) {
+            win.position = *position;
+            if let Some(origin) = origin {
+                win.offset = position.subtract(&origin);
+            }
+        }
+        // parents come before their children
+        for id in self.window_and_children(win_id).into_iter().skip(1) {
+            if let Some(origin) = self.parent_origin(id) {
+                if let Some(win) = self.lookup_window_mut(id) {
+                    win.position = origin.add(&win.offset);
+                }
+            }
+        }
+    }
+    // the top left of the parent's contents, for a child window
+    fn parent_origin(&self, win_id: Uuid) -> Option<Point> {
+        self.lookup_window(win_id)
+            .and_then(|w| w.parent)
+            .and_then(|p| self.lookup_window(p))
+            .map(|p| p.content_bounds().position())
+    }
+    pub fn set_window_visible(&mut self, win_id: Uuid, visible: bool) {
+        if let Some(win) = self.lookup_window_mut(win_id) {
+            win.visible = visible;
+        }
+        if !visible && self.focused == Some(win_id) {
+            self.focused = None;
+        }
+    }
+    // closes the window and everything opened on top of it. returns what was closed.
+    pub fn close_window(&mut self, win_id: Uuid) -> Vec<Window> {
+        let mut closed = vec![];
+        for id in self.window_and_children(win_id) {
+            if let Some(app) = self.apps.iter_mut().find(|a| a.windows.iter().any(|w| w.id == id)) {
+                let n = app.windows.iter().position(|w| w.id == id).unwrap();
+                closed.push(app.windows.remove(n));
+            }
+            self.window_order.retain(|w| *w != id);
+            if self.focused == Some(id) {
+                self.focused = None;
+            }
+        }
+        closed
+    }
+    fn window_and_children(&self, win_id: Uuid) -> Vec<Uuid> {
+        let mut ids = vec![win_id];
+        let mut n = 0;
+        while n < ids.len() {
+            let parent = ids[n];
+            ids.extend(self.window_list().iter().filter(|w| w.parent == Some(parent)).map(|w| w.id));
+            n += 1;
+        }
+        ids
+    }
+    // a click anywhere outside the open popups closes them. clicking in a popup keeps it and
+    // the popups it was opened from. returns what was closed.
+    pub fn dismiss_popups_at(&mut self, pt: Point) -> Vec<Window> {
+        let mut keep = vec![];
+        let mut next = self.pick_window_at(pt).map(|w| w.id);
+        while let Some(id) = next {
+            keep.push(id);
+            next = self.lookup_window(id).and_then(|w| w.parent);
+        }
+        let popups: Vec<Uuid> = self.window_list().iter()
+            .filter(|w| matches!(w.window_type, WindowType::Popup()) && !keep.contains(&w.id))
+            .map(|w| w.id)
+            .collect();
+        popups.into_iter().flat_map(|id| self.close_window(id)).collect()
+    }
+    // the modal dialog which gets the input meant for this window, if there is one
+    pub fn blocking_modal(&self, win_id: Uuid) -> Option<Uuid> {
+        let mut modal = None;
+        let mut current = win_id;
+        while let Some(child) = self.window_list().iter()
+            .find(|w| w.visible && w.parent == Some(current) && matches!(w.window_type, WindowType::Modal())) {
+            modal = Some(child.id);
+            current = child.id;
+        }
+        modal
+    }
+
     pub fn pick_window_at<'a>(&'a self, pt:Point) -> Option<&'a Window> {
         for win_id in self.window_order.iter().rev() {
             for app in &self.apps {
                 for win in &app.windows {
-                    if win.id.eq(win_id) {
+                    if win.id.eq(win_id) && win.visible {
                         if win.external_bounds().contains(&pt) {
                             return Some(win)
                         }
@@ -236,6 +408,42 @@ impl WindowManagerState {
             }
         }
     }
+    // map a buffer an app registered. returns false if it couldn't be opened.
+    pub fn add_shared_buffer(&mut self, app_id: Uuid, info: &SharedBufferInfo) -> bool {
+        let app = match self.find_app(app_id) {
+            Some(app) => app,
+            None => return false,
+        };
+        match SharedBuffer::open(info) {
+            Ok(buf) => {
+                app.shared_buffers.insert(info.buffer_id, buf);
+                true
+            }
+            Err(e) => {
+                error!("couldn't open shared buffer {} from app {}: {}", info.buffer_id, app_id, e);
+                false
+            }
+        }
+    }
+    pub fn remove_shared_buffer(&mut self, app_id: Uuid, buffer_id: Uuid) {
+        if let Some(app) = self.find_app(app_id) {
+            app.shared_buffers.remove(&buffer_id);
+        }
+    }
+    // copy the damaged rect of a shared buffer into the window's backbuffer
+    pub fn draw_shared_image(&mut self, cmd: &DrawSharedImageCommand) -> bool {
+        if let Some(app) = self.apps.iter_mut().find(|a|a.id == cmd.app_id) {
+            let buf = app.shared_buffers.get(&cmd.buffer_id);
+            let win = app.windows.iter_mut().find(|w|w.id == cmd.window_id);
+            if let (Some(buf), Some(win)) = (buf, win) {
+                // exactly what a window manager which can't map the buffer gets from central
+                let img = cmd.to_image(buf);
+                win.backbuffer.fill_rect_with_image(&img.rect, &img.buffer);
+                return true;
+            }
+        }
+        false
+    }
     pub fn remove_app(&mut self, app_id: Uuid) {
         if let Some(app) = self.find_app(app_id) {
             app.windows.clear();
@@ -246,38 +454,36 @@ impl WindowManagerState {
 }
 
 pub struct CentralConnection {
-    pub stream: TcpStream,
+    pub stream: ClientStream,
     recv_thread: JoinHandle<()>,
     send_thread: JoinHandle<()>,
     pub tx_out: Sender<IncomingMessage>,
 }
 
 pub fn start_wm_network_connection(stop: Arc<AtomicBool>, sender: Sender<IncomingMessage>) -> Option<CentralConnection> {
-    let conn_string ="localhost:3334";
-    match TcpStream::connect(conn_string) {
+    match connect(WM_SOCKET_ENV, WINDOW_MANAGER_PORT) {
         Ok(mut master_stream) => {
             //send hello message
             let im = IncomingMessage {
                 trace: true,
                 timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                 source: Default::default(),
-                command: APICommand::WMConnect(HelloWindowManager {})};
-            match serde_json::to_string(&im) {
-                Ok(data) => {
-                    // println!("sending data {:?}", data);
-                    if let Err(e) = master_stream.write_all(data.as_ref()) {
-                        error!("error sending data back to server {}",e);
-                        return None
-                    }
-                }
-                Err(e) => {
-                    error!("error serializing incoming messages {}",e);
-                    return None
-                }
+                command: APICommand::WMConnect(HelloWindowManager {
+                    wire_format: WireFormat::Binary,
+                    protocol_version: PROTOCOL_VERSION,
+                    client_name: String::from("common-wm"),
+                    capabilities: default_capabilities(),
+                }),
+                request_id: None,
+            };
+            // the hello and its response are always json
+            if let Err(e) = write_message(&mut master_stream, &im, WireFormat::Json) {
+                error!("error sending data back to server {}",e);
+                return None
             }
             //wait for the response
-            let mut de = serde_json::Deserializer::from_reader(&master_stream);
-            match IncomingMessage::deserialize(&mut de) {
+            let mut reader = MessageReader::new(master_stream.try_clone().unwrap(), WireFormat::Json);
+            let wire_format = match reader.read::<IncomingMessage>() {
                 Ok(cmd) => {
                     if cmd.trace {
                         info!("==== received command {:?}", cmd);
@@ -285,6 +491,14 @@ pub fn start_wm_network_connection(stop: Arc<AtomicBool>, sender: Sender<Incomin
                     if let APICommand::WMConnectResponse(res) = cmd.command {
                         // info!("got response back from the server {:?}",res);
                         // res.wm_id
+                        if let Some(reason) = res.rejected {
+                            error!("central rejected the window manager: {}", reason);
+                            stop.store(true,Ordering::Relaxed);
+                            return None
+                        }
+                        res.wire_format
+                    } else {
+                        WireFormat::Json
                     }
                 }
                 Err(e) => {
@@ -292,24 +506,23 @@ pub fn start_wm_network_connection(stop: Arc<AtomicBool>, sender: Sender<Incomin
                     stop.store(true,Ordering::Relaxed);
                     return None
                 }
-            }
+            };
+            reader.format = wire_format;
             // info!("window manager fully connected to the central server");
 
             let (tx_out, rx_out) =mpsc::channel::<IncomingMessage>();
             //receiving thread
             // create thread to read IncomingMessage from network and copy to the WM sender
             let receiving_handle = thread::spawn({
-                let stream = master_stream.try_clone().unwrap();
                 let stop = stop.clone();
                 // let tx_in = tx_in.clone();
                 move || {
                     // info!("receiving thread starting");
-                    let mut de = serde_json::Deserializer::from_reader(stream);
                     loop {
                         if stop.load(Ordering::Relaxed) == true {
                             break;
                         }
-                        match IncomingMessage::deserialize(&mut de) {
+                        match reader.read::<IncomingMessage>() {
                             Ok(cmd) => {
                                 // info!("received command {:?}", cmd);
                                 if let Err(e) = sender.send(cmd) {
@@ -344,25 +557,15 @@ pub fn start_wm_network_connection(stop: Arc<AtomicBool>, sender: Sender<Incomin
                             trace: out.trace,
                             timestamp_usec: out.timestamp_usec,
                             source: Default::default(),
-                            command: out.command
+                            command: out.command,
+                            request_id: out.request_id,
                         };
                         if im.trace {
                             info!("sending out message {:?}",im);
                         }
-                        match serde_json::to_string(&im) {
-                            Ok(data) => {
-                                if im.trace {
-                                    info!("sending data {:?}", data);
-                                }
-                                if let Err(e) = stream.write_all(data.as_ref()) {
-                                    error!("error sending data back to server {}",e);
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                error!("error serializing incoming messages {}",e);
-                                break;
-                            }
+                        if let Err(e) = write_message(&mut stream, &im, wire_format) {
+                            error!("error sending data back to server {}",e);
+                            break;
                         }
                         // let data = serde_json::to_string(&im)
                         // println!("sending data {:?}", data);
@@ -379,18 +582,84 @@ pub fn start_wm_network_connection(stop: Arc<AtomicBool>, sender: Sender<Incomin
             })
 
         }
-        _ => {
-            error!("could not connect to server at {}",conn_string);
+        Err(e) => {
+            error!("could not connect to the central server {}",e);
             None
         }
     }
 }
 
 
+// ctrl or meta with C, X or V. the app gets a ClipboardShortcut instead of the key.
+pub fn clipboard_shortcut(evt:&KeyDownEvent) -> Option<ClipboardAction> {
+    if !(evt.mods.ctrl || evt.mods.meta) {
+        return None;
+    }
+    match evt.key {
+        KeyCode::LETTER_C => Some(ClipboardAction::Copy),
+        KeyCode::LETTER_X => Some(ClipboardAction::Cut),
+        KeyCode::LETTER_V => Some(ClipboardAction::Paste),
+        _ => None,
+    }
+}
+
+// tell central the keyboard layout changed, so it can tell the apps
+pub fn send_keyboard_layout_changed(tx_out:&Sender<IncomingMessage>, layout:&KeyboardLayout) {
+    send_event(tx_out, APICommand::KeyboardLayoutChanged(KeyboardLayoutChanged {
+        name: layout.name.clone(),
+        title: layout.title.clone(),
+    }));
+}
+
+// tell central the window manager closed a window, so it can tell the app
+pub fn send_window_closed(tx_out:&Sender<IncomingMessage>, win:&Window) {
+    send_event(tx_out, APICommand::CloseWindowResponse(CloseWindowResponse {
+        app_id: win.owner,
+        window_id: win.id,
+    }));
+}
+
+// a drag which can't start, because the mouse isn't down in the app's window any more
+pub fn send_drag_refused(tx_out:&Sender<IncomingMessage>, start:&StartDrag) {
+    send_event(tx_out, APICommand::DragEnded(DragEnded {
+        app_id: start.app_id,
+        window_id: start.window_id,
+        drag_id: start.drag_id,
+        accepted: false,
+    }));
+}
+
+// scrolling goes to the window under the pointer, whatever the mouse buttons are doing.
+// nothing is sent for the title bar or the background.
+pub fn send_mouse_wheel(tx_out:&Sender<IncomingMessage>, state:&WindowManagerState, evt:&MouseWheelEvent) {
+    let point = Point::init(evt.x, evt.y);
+    if let Some(win) = state.pick_window_at(point) {
+        let bounds = win.content_bounds();
+        if !bounds.contains(&point) {
+            return;
+        }
+        let app_point = point.subtract(&bounds.position());
+        send_event(tx_out, APICommand::MouseWheel(MouseWheelEvent {
+            app_id: win.owner,
+            window_id: win.id,
+            original_timestamp: evt.original_timestamp,
+            x: app_point.x,
+            y: app_point.y,
+            dx: evt.dx,
+            dy: evt.dy,
+        }));
+    }
+}
+
 pub trait InputGesture {
     fn mouse_down(&mut self, evt:MouseDownEvent, source:&IncomingMessage, state:&mut WindowManagerState, tx_out:&Sender<IncomingMessage>);
     fn mouse_move(&mut self, evt:MouseMoveEvent, state:&mut WindowManagerState, tx_out:&Sender<IncomingMessage>);
     fn mouse_up(  &mut self, evt:MouseUpEvent, state:&mut WindowManagerState, tx_out:&Sender<IncomingMessage>);
+    // the window getting the mouse events, if they're going to an app. an app can only start a
+    // drag from there.
+    fn app_window(&self) -> Option<Uuid> {
+        None
+    }
 }
 
 
@@ -451,9 +720,7 @@ impl InputGesture for WindowDragGesture {
         let diff = self.mouse_start.subtract(&self.win_start);
         let new_pos = curr.subtract(&diff);
         // info!("dragging window {} by {:?}",self.winid,diff);
-        if let Some(win) = state.lookup_window_mut(self.winid) {
-            win.position.copy_from(&new_pos);
-        }
+        state.move_window(self.winid, &new_pos);
     }
 
     fn mouse_up(&mut self, evt: MouseUpEvent, state:&mut WindowManagerState, tx_out:&Sender<IncomingMessage>) {
@@ -462,9 +729,7 @@ impl InputGesture for WindowDragGesture {
         let diff = self.mouse_start.subtract(&self.win_start);
         let new_pos = curr.subtract(&diff);
         // info!("new window position is {} to {:?}",self.winid,curr);
-        if let Some(win) = state.lookup_window_mut(self.winid) {
-            win.position.copy_from(&new_pos);
-        }
+        state.move_window(self.winid, &new_pos);
     }
 }
 
@@ -532,21 +797,9 @@ impl InputGesture for WindowCloseButtonGesture {
     fn mouse_up(&mut self, evt: MouseUpEvent, state: &mut WindowManagerState, tx_out: &Sender<IncomingMessage>) {
         // println!("mouse up. send the window close event");
         // let point = Point::init(evt.x, evt.y);
-        if let Some(win) = state.lookup_window(self.winid) {
-            // info!("picked a window for mouse up");
-            let wid = win.id.clone();
-            let aid = win.owner.clone();
-            // let app_point = point.subtract(&win.content_bounds().position());
-            state.remove_window(aid,wid);
-            tx_out.send(IncomingMessage {
-                source:Default::default(),
-                trace: false,
-                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
-                command: APICommand::CloseWindowResponse(CloseWindowResponse {
-                    app_id: aid,
-                    window_id: wid,
-                })
-            }).unwrap();
+        // popups and dialogs on top of the window go with it
+        for win in state.close_window(self.winid) {
+            send_window_closed(tx_out, &win);
         }
     }
 }
@@ -566,6 +819,10 @@ impl AppMouseGesture {
 
 
 impl InputGesture for AppMouseGesture {
+    fn app_window(&self) -> Option<Uuid> {
+        Some(self.winid)
+    }
+
     fn mouse_down(&mut self, evt: MouseDownEvent, source: &IncomingMessage, state: &mut WindowManagerState, tx_out: &Sender<IncomingMessage>) {
         // println!("Mouse down to app. trace is {}",source.trace);
         let point = Point::init(evt.x, evt.y);
@@ -580,10 +837,12 @@ impl InputGesture for AppMouseGesture {
                     app_id: self.app_id,
                     window_id: self.winid,
                     original_timestamp: evt.original_timestamp,
-                    button: MouseButton::Primary,
+                    button: evt.button,
                     x: app_point.x,
-                    y: app_point.y
-                })
+                    y: app_point.y,
+                    clicks: evt.clicks,
+                }),
+                request_id: None,
             }).unwrap();
         }
 
@@ -605,10 +864,11 @@ impl InputGesture for AppMouseGesture {
                     app_id: aid,
                     window_id: wid,
                     original_timestamp: evt.original_timestamp,
-                    button: MouseButton::Primary,
+                    button: evt.button,
                     x: app_point.x,
                     y: app_point.y
-                })
+                }),
+                request_id: None,
             }).unwrap();
         }
     }
@@ -629,11 +889,257 @@ impl InputGesture for AppMouseGesture {
                     app_id: aid,
                     window_id: wid,
                     original_timestamp: evt.original_timestamp,
-                    button: MouseButton::Primary,
+                    button: evt.button,
                     x: app_point.x,
                     y: app_point.y
-                })
+                }),
+                request_id: None,
             }).unwrap();
         }
     }
 }
+
+// an app started a drag while the mouse was down in its window. until the mouse comes up the
+// window under the mouse hears about it instead of getting mouse events.
+pub struct DragGesture {
+    pub drag_id: Uuid,
+    source_app: Uuid,
+    source_window: Uuid,
+    items: Vec<ClipboardItem>,
+    // the app and window under the mouse
+    target: Option<(Uuid, Uuid)>,
+}
+
+impl DragGesture {
+    pub fn init(start: &StartDrag) -> DragGesture {
+        DragGesture {
+            drag_id: start.drag_id,
+            source_app: start.app_id,
+            source_window: start.window_id,
+            items: start.items.clone(),
+            target: None,
+        }
+    }
+    // the window under the point and the point relative to it
+    fn target_at(&self, state: &WindowManagerState, pt: Point) -> Option<(Uuid, Uuid, Point)> {
+        state.pick_window_at(pt).map(|win| (win.owner, win.id, pt.subtract(&win.content_bounds().position())))
+    }
+    fn move_to(&mut self, pt: Point, state: &mut WindowManagerState, tx_out: &Sender<IncomingMessage>) {
+        state.drag_rect = Some(Rect::from_ints(pt.x + 8, pt.y + 8, 16, 16));
+        let under = self.target_at(state, pt);
+        let now = under.map(|(app_id, window_id, _)| (app_id, window_id));
+        if now != self.target {
+            if let Some((app_id, window_id)) = self.target {
+                send_event(tx_out, APICommand::DragLeave(DragLeaveEvent { app_id, window_id, drag_id: self.drag_id }));
+            }
+            if let Some((app_id, window_id, local)) = under {
+                let mime_types = self.items.iter().map(|item| item.mime_type.clone()).collect();
+                send_event(tx_out, APICommand::DragEnter(DragEnterEvent { app_id, window_id, drag_id: self.drag_id, mime_types, x: local.x, y: local.y }));
+            }
+            self.target = now;
+        } else if let Some((app_id, window_id, local)) = under {
+            send_event(tx_out, APICommand::DragOver(DragOverEvent { app_id, window_id, drag_id: self.drag_id, x: local.x, y: local.y }));
+        }
+    }
+}
+
+impl InputGesture for DragGesture {
+    fn mouse_down(&mut self, _evt: MouseDownEvent, _source: &IncomingMessage, _state: &mut WindowManagerState, _tx_out: &Sender<IncomingMessage>) {
+    }
+
+    fn mouse_move(&mut self, evt: MouseMoveEvent, state: &mut WindowManagerState, tx_out: &Sender<IncomingMessage>) {
+        self.move_to(Point::init(evt.x, evt.y), state, tx_out);
+    }
+
+    fn mouse_up(&mut self, evt: MouseUpEvent, state: &mut WindowManagerState, tx_out: &Sender<IncomingMessage>) {
+        state.drag_rect = None;
+        match self.target_at(state, Point::init(evt.x, evt.y)) {
+            // the target says whether it took it
+            Some((app_id, window_id, local)) => send_event(tx_out, APICommand::Drop(DropEvent {
+                app_id,
+                window_id,
+                drag_id: self.drag_id,
+                items: self.items.clone(),
+                x: local.x,
+                y: local.y,
+            })),
+            None => send_event(tx_out, APICommand::DragEnded(DragEnded {
+                app_id: self.source_app,
+                window_id: self.source_window,
+                drag_id: self.drag_id,
+                accepted: false,
+            })),
+        }
+    }
+}
+
+fn send_event(tx_out: &Sender<IncomingMessage>, command: APICommand) {
+    tx_out.send(IncomingMessage {
+        source:Default::default(),
+        trace: false,
+        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+        command,
+        request_id: None,
+    }).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use uuid::Uuid;
+    use common::{APICommand, ClipboardData, ClipboardItem, clipboard_types, DebugMessage, DrawSharedImageCommand, IncomingMessage, StartDrag};
+    #[cfg(target_os = "linux")]
+    use common::shm::SharedBuffer;
+    use common::events::{MouseButton, MouseDownEvent, MouseMoveEvent, MouseUpEvent, MouseWheelEvent};
+    use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect};
+    use crate::{AppMouseGesture, DragGesture, InputGesture, send_mouse_wheel, WindowManagerState, WindowType};
+
+    #[test]
+    fn popups_and_modals() {
+        let mut state = WindowManagerState::init(&PixelLayout::ARGB());
+        let app = Uuid::new_v4();
+        state.add_app(app);
+        let main = state.add_window(app, Uuid::new_v4(), &Rect::from_ints(100, 100, 200, 200), &String::from("main"));
+        let content = state.lookup_window(main).unwrap().content_bounds();
+
+        // placed inside the parent's contents, with no title bar, and focused
+        let menu = state.add_child_window(app, Uuid::new_v4(), &Rect::from_ints(10, 10, 50, 80), &String::new(), WindowType::Popup(), Some(main));
+        let sub = state.add_child_window(app, Uuid::new_v4(), &Rect::from_ints(50, 10, 50, 50), &String::new(), WindowType::Popup(), Some(menu));
+        let win = state.lookup_window(menu).unwrap();
+        assert_eq!((win.position.x, win.position.y), (content.x + 10, content.y + 10));
+        assert_eq!(win.content_bounds(), win.external_bounds());
+        assert_eq!(*state.get_focused_window(), Some(sub));
+
+        // clicking in the menu closes the submenu but not the menu
+        let closed = state.dismiss_popups_at(Point::init(content.x + 15, content.y + 70));
+        assert_eq!(closed.iter().map(|w| w.id).collect::<Vec<Uuid>>(), vec![sub]);
+        // clicking the main window closes the menu
+        let closed = state.dismiss_popups_at(Point::init(content.x + 150, content.y + 150));
+        assert_eq!(closed.iter().map(|w| w.id).collect::<Vec<Uuid>>(), vec![menu]);
+        assert!(state.dismiss_popups_at(Point::init(content.x + 150, content.y + 150)).is_empty());
+
+        // popups move with their parent
+        let menu = state.add_child_window(app, Uuid::new_v4(), &Rect::from_ints(10, 10, 50, 80), &String::new(), WindowType::Popup(), Some(main));
+        state.move_window(main, &Point::init(300, 50));
+        let content = state.lookup_window(main).unwrap().content_bounds();
+        let win = state.lookup_window(menu).unwrap();
+        assert_eq!((win.position.x, win.position.y), (content.x + 10, content.y + 10));
+        // and the app places them relative to the parent too
+        state.set_window_bounds(menu, &Rect::from_ints(30, 5, 50, 80));
+        let win = state.lookup_window(menu).unwrap();
+        assert_eq!((win.position.x, win.position.y), (content.x + 30, content.y + 5));
+        state.close_window(menu);
+
+        let dialog = state.add_child_window(app, Uuid::new_v4(), &Rect::from_ints(20, 20, 100, 50), &String::from("sure?"), WindowType::Modal(), Some(main));
+        assert_eq!(state.blocking_modal(main), Some(dialog));
+        assert_eq!(state.blocking_modal(dialog), None);
+        // the parent can't take the focus or the keyboard back
+        state.set_focused_window(main);
+        assert_eq!(*state.get_focused_window(), Some(dialog));
+        state.set_window_visible(dialog, false);
+        state.set_focused_window(main);
+        state.set_window_visible(dialog, true);
+        assert_eq!(state.keyboard_window().map(|w| w.id), Some(dialog));
+        // closing the parent closes the dialog too
+        assert_eq!(state.close_window(main).len(), 2);
+        assert!(state.lookup_window(dialog).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn shared_images_draw_like_plain_ones() {
+        let mut state = WindowManagerState::init(&PixelLayout::ARGB());
+        let app = Uuid::new_v4();
+        state.add_app(app);
+        let win = state.add_window(app, Uuid::new_v4(), &Rect::from_ints(0, 0, 40, 40), &String::from("canvas"));
+        let blue = ARGBColor::new_rgb(0, 0, 255);
+        let red = ARGBColor::new_rgb(255, 0, 0);
+        state.lookup_window_mut(win).unwrap().backbuffer.clear(&blue);
+        let before = state.lookup_window(win).unwrap().backbuffer.clone();
+
+        // half opaque, half fully transparent
+        let mut img = GFXBuffer::new(32, 32, &PixelLayout::ARGB());
+        img.fill_rect(&Rect::from_ints(0, 0, 16, 32), &red);
+        let mut shared = SharedBuffer::create(32, 32, &PixelLayout::ARGB()).unwrap();
+        assert!(shared.copy_from(&img, img.bounds()));
+        let info = shared.info();
+        assert!(state.add_shared_buffer(app, &info));
+
+        let cmd = DrawSharedImageCommand { app_id: app, window_id: win, buffer_id: info.buffer_id, rect: Rect::from_ints(8, 8, 16, 16) };
+        assert!(state.draw_shared_image(&cmd));
+        // what a window manager without shared memory draws
+        let mut plain = before.clone();
+        let di = cmd.to_image(&shared);
+        plain.fill_rect_with_image(&di.rect, &di.buffer);
+
+        let drawn = &state.lookup_window(win).unwrap().backbuffer;
+        assert_eq!(drawn.data, plain.data);
+        assert_eq!(drawn.get_pixel_vec_argb(10, 10), red.as_layout(&drawn.layout));
+        // the transparent half left the window alone
+        assert_eq!(drawn.get_pixel_vec_argb(20, 10), blue.as_layout(&drawn.layout));
+    }
+
+    #[test]
+    fn buttons_and_wheel_in_window_coords() {
+        let mut state = WindowManagerState::init(&PixelLayout::ARGB());
+        let app = Uuid::new_v4();
+        state.add_app(app);
+        let win = state.add_window(app, Uuid::new_v4(), &Rect::from_ints(100, 100, 200, 200), &String::from("list"));
+        let content = state.lookup_window(win).unwrap().content_bounds();
+        let (tx, rx) = mpsc::channel();
+
+        let mut gesture = AppMouseGesture::init(app, win);
+        let mut down = MouseDownEvent::init_primary(content.x + 5, content.y + 6);
+        down.button = MouseButton::Middle;
+        down.clicks = 2;
+        let source = IncomingMessage { source: Default::default(), trace: false, timestamp_usec: 0, command: APICommand::Debug(DebugMessage::HelloDebugger), request_id: None };
+        gesture.mouse_down(down, &source, &mut state, &tx);
+        let wheel = |x, y| MouseWheelEvent { app_id: Default::default(), window_id: Default::default(), original_timestamp: 0, x, y, dx: 0, dy: 3 };
+        send_mouse_wheel(&tx, &state, &wheel(content.x + 10, content.y + 20));
+        // not on the title bar or the background
+        send_mouse_wheel(&tx, &state, &wheel(content.x + 10, content.y - 5));
+        send_mouse_wheel(&tx, &state, &wheel(10, 10));
+
+        let events: Vec<APICommand> = rx.try_iter().map(|msg| msg.command).collect();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], APICommand::MouseDown(e) if e.button == MouseButton::Middle && e.clicks == 2 && (e.x, e.y) == (5, 6)));
+        assert!(matches!(&events[1], APICommand::MouseWheel(e) if e.window_id == win && (e.x, e.y, e.dy) == (10, 20, 3)));
+    }
+
+    #[test]
+    fn drag_between_windows() {
+        let mut state = WindowManagerState::init(&PixelLayout::ARGB());
+        let (library, playlist) = (Uuid::new_v4(), Uuid::new_v4());
+        state.add_app(library);
+        state.add_app(playlist);
+        let songs = state.add_window(library, Uuid::new_v4(), &Rect::from_ints(0, 0, 100, 100), &String::from("songs"));
+        let list = state.add_window(playlist, Uuid::new_v4(), &Rect::from_ints(200, 0, 100, 100), &String::from("list"));
+        let (tx, rx) = mpsc::channel();
+        let item = ClipboardItem { mime_type: String::from(clipboard_types::DB_OBJECT), data: ClipboardData::ObjectId(String::from("song-1")) };
+        let start = StartDrag { app_id: library, window_id: songs, drag_id: Uuid::new_v4(), items: vec![item] };
+        let mut drag = DragGesture::init(&start);
+        let moved = |x, y| MouseMoveEvent { app_id: Default::default(), window_id: Default::default(), original_timestamp: 0, button: MouseButton::Primary, x, y };
+
+        drag.mouse_move(moved(50, 50), &mut state, &tx);
+        drag.mouse_move(moved(250, 50), &mut state, &tx);
+        drag.mouse_move(moved(260, 50), &mut state, &tx);
+        assert!(state.drag_rect.is_some());
+        drag.mouse_up(MouseUpEvent { app_id: Default::default(), window_id: Default::default(), original_timestamp: 0, button: MouseButton::Primary, x: 260, y: 50 }, &mut state, &tx);
+        assert!(state.drag_rect.is_none());
+
+        let events: Vec<APICommand> = rx.try_iter().map(|msg| msg.command).collect();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(&events[0], APICommand::DragEnter(e) if e.window_id == songs));
+        assert!(matches!(&events[1], APICommand::DragLeave(e) if e.window_id == songs));
+        assert!(matches!(&events[2], APICommand::DragEnter(e) if e.window_id == list && e.mime_types == vec![clipboard_types::DB_OBJECT]));
+        assert!(matches!(&events[3], APICommand::DragOver(e) if e.app_id == playlist));
+        // relative to the playlist window's contents
+        let content = state.lookup_window(list).unwrap().content_bounds();
+        assert!(matches!(&events[4], APICommand::Drop(e) if e.app_id == playlist && e.x == 260 - content.x && e.items.len() == 1));
+
+        // dropped on nothing goes back to the library
+        let mut drag = DragGesture::init(&start);
+        drag.mouse_up(MouseUpEvent { app_id: Default::default(), window_id: Default::default(), original_timestamp: 0, button: MouseButton::Primary, x: 150, y: 300 }, &mut state, &tx);
+        assert!(matches!(rx.try_recv().unwrap().command, APICommand::DragEnded(e) if e.app_id == library && !e.accepted));
+    }
+}
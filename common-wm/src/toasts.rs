@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+use common::PostNotification;
+use gfx::graphics::{GFXBuffer, Point, Rect};
+
+pub const TOAST_WIDTH:i32 = 240;
+pub const TOAST_HEIGHT:i32 = 64;
+const TOAST_MARGIN:i32 = 10;
+const ACTION_WIDTH:i32 = 70;
+const ACTION_HEIGHT:i32 = 16;
+
+// a notification on screen, already drawn by the window manager
+pub struct Toast {
+    pub notification:PostNotification,
+    pub buffer:GFXBuffer,
+    posted:Instant,
+}
+
+// what a click on a toast did. the toast is gone either way.
+pub struct ToastClick {
+    pub toast:Toast,
+    // set if the click was on one of its actions
+    pub action_id:Option<String>,
+}
+
+// where an action button goes, relative to the toast
+pub fn action_bounds(index:usize) -> Rect {
+    let n = index as i32;
+    Rect::from_ints(TOAST_WIDTH - (n + 1) * (ACTION_WIDTH + 4), TOAST_HEIGHT - ACTION_HEIGHT - 4, ACTION_WIDTH, ACTION_HEIGHT)
+}
+
+// notifications stacked down from the top right corner of the screen, newest at the top
+#[derive(Default)]
+pub struct Toasts {
+    toasts:Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn new() -> Toasts {
+        Toasts { toasts: vec![] }
+    }
+    pub fn add(&mut self, notification:PostNotification, buffer:GFXBuffer) {
+        self.toasts.insert(0, Toast { notification, buffer, posted: Instant::now() });
+    }
+    // take down the ones which have been up long enough. returns them so their buffers can be freed.
+    pub fn expire(&mut self, now:Instant) -> Vec<Toast> {
+        let (gone, kept) = self.toasts.drain(..).partition(|t| {
+            t.notification.timeout_ms > 0 && now.duration_since(t.posted) >= Duration::from_millis(t.notification.timeout_ms as u64)
+        });
+        self.toasts = kept;
+        gone
+    }
+    pub fn layout(&self, screen:&Rect) -> Vec<(Rect, &Toast)> {
+        self.toasts.iter().enumerate().map(|(n, toast)| {
+            let y = screen.y + TOAST_MARGIN + n as i32 * (TOAST_HEIGHT + TOAST_MARGIN);
+            (Rect::from_ints(screen.x + screen.w - TOAST_WIDTH - TOAST_MARGIN, y, TOAST_WIDTH, TOAST_HEIGHT), toast)
+        }).collect()
+    }
+    // None if the point isn't on a toast
+    pub fn click(&mut self, screen:&Rect, pt:Point) -> Option<ToastClick> {
+        let (n, bounds) = self.layout(screen).iter().enumerate()
+            .find(|(_, (bounds, _))| bounds.contains(&pt))
+            .map(|(n, (bounds, _))| (n, *bounds))?;
+        let toast = self.toasts.remove(n);
+        let local = pt.subtract(&bounds.position());
+        let action_id = toast.notification.actions.iter().enumerate()
+            .find(|(i, _)| action_bounds(*i).contains(&local))
+            .map(|(_, action)| action.id.clone());
+        Some(ToastClick { toast, action_id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+    use common::{MAX_NOTIFICATION_ACTIONS, NotificationAction, PostNotification};
+    use gfx::graphics::{GFXBuffer, PixelLayout, Point, Rect};
+    use crate::toasts::{action_bounds, Toasts};
+
+    fn note(title:&str, timeout_ms:u32, actions:Vec<NotificationAction>) -> PostNotification {
+        PostNotification {
+            app_id: Uuid::new_v4(),
+            notification_id: Uuid::new_v4(),
+            title: title.to_string(),
+            body: String::new(),
+            icon: None,
+            timeout_ms,
+            actions,
+        }
+    }
+
+    #[test]
+    fn stack_expire_and_click() {
+        let screen = Rect::from_ints(0, 0, 1024, 768);
+        let mut toasts = Toasts::new();
+        let reply = NotificationAction { id: String::from("reply"), label: String::from("Reply") };
+        toasts.add(note("sticky", 0, vec![reply]), GFXBuffer::new(1, 1, &PixelLayout::ARGB()));
+        toasts.add(note("quick", 100, vec![]), GFXBuffer::new(1, 1, &PixelLayout::ARGB()));
+        let layout = toasts.layout(&screen);
+        assert_eq!(layout[0].1.notification.title, "quick");
+        assert!(layout[0].0.y < layout[1].0.y);
+        assert_eq!(layout[0].0.x + layout[0].0.w, 1014);
+
+        let gone = toasts.expire(Instant::now() + Duration::from_millis(200));
+        assert_eq!(gone.len(), 1);
+        assert_eq!(gone[0].notification.title, "quick");
+
+        // clicking the action says which one
+        let bounds = toasts.layout(&screen)[0].0;
+        let action = action_bounds(0);
+        let click = toasts.click(&screen, Point::init(bounds.x + action.x + 2, bounds.y + action.y + 2)).unwrap();
+        assert_eq!(click.action_id.as_deref(), Some("reply"));
+        assert!(toasts.click(&screen, Point::init(bounds.x + 2, bounds.y + 2)).is_none());
+    }
+
+    #[test]
+    fn every_action_fits() {
+        for n in 0..MAX_NOTIFICATION_ACTIONS {
+            assert!(action_bounds(n).x >= 0, "action {} is off the toast", n);
+        }
+    }
+}
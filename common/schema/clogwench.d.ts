@@ -0,0 +1,720 @@
+// generated by `cargo run -p common --bin gen_schema`. do not edit.
+// u128 and u64 fields are plain numbers. timestamps stay well under 2^53.
+
+export type APICommand =
+    | "SystemShutdown"
+    | { AppConnect: HelloApp }
+    | { AppConnectResponse: HelloAppResponse }
+    | { AppDisconnected: AppDisconnected }
+    | { Debug: DebugMessage }
+    | { WMConnect: HelloWindowManager }
+    | { WMConnectResponse: HelloWindowManagerResponse }
+    | { OpenWindowCommand: OpenWindowCommand }
+    | { OpenWindowResponse: OpenWindowResponse }
+    | { CloseWindowResponse: CloseWindowResponse }
+    | { WindowResized: WindowResized }
+    | { SetWindowTitle: SetWindowTitle }
+    | { SetWindowBounds: SetWindowBounds }
+    | { CloseWindowRequest: CloseWindowRequest }
+    | { RaiseWindow: RaiseWindow }
+    | { SetWindowVisible: SetWindowVisible }
+    | { DrawRectCommand: DrawRectCommand }
+    | { DrawImageCommand: DrawImageCommand }
+    | { DrawBatchCommand: DrawBatchCommand }
+    | { DrawSharedImageCommand: DrawSharedImageCommand }
+    | { RegisterSharedBuffer: RegisterSharedBuffer }
+    | { RegisterSharedBufferResponse: RegisterSharedBufferResponse }
+    | { UnregisterSharedBuffer: UnregisterSharedBuffer }
+    | { KeyDown: KeyDownEvent }
+    | { KeyUp: KeyUpEvent }
+    | { TextInput: TextInputEvent }
+    | { MouseDown: MouseDownEvent }
+    | { MouseMove: MouseMoveEvent }
+    | { MouseUp: MouseUpEvent }
+    | { MouseWheel: MouseWheelEvent }
+    | { DBQueryRequest: DBQueryRequest }
+    | { DBQueryResponse: DBQueryResponse }
+    | { DBAddRequest: DBAddRequest }
+    | { DBAddResponse: DBAddResponse }
+    | { DBUpdateRequest: DBUpdateRequest }
+    | { DBUpdateResponse: DBUpdateResponse }
+    | { DBDeleteRequest: DBDeleteRequest }
+    | { DBDeleteResponse: DBDeleteResponse }
+    | { AudioPlayTrackRequest: AudioPlayTrackRequest }
+    | { AudioPlayTrackResponse: AudioPlayTrackResponse }
+    | { AudioPauseTrackRequest: AudioPauseTrackRequest }
+    | { AudioPauseTrackResponse: AudioPauseTrackResponse }
+    | { ClipboardSet: ClipboardSet }
+    | { ClipboardGet: ClipboardGet }
+    | { ClipboardGetResponse: ClipboardGetResponse }
+    | { ClipboardChanged: ClipboardChanged }
+    | { ClipboardShortcut: ClipboardShortcut }
+    | { StartDrag: StartDrag }
+    | { DragEnter: DragEnterEvent }
+    | { DragOver: DragOverEvent }
+    | { DragLeave: DragLeaveEvent }
+    | { Drop: DropEvent }
+    | { DropResponse: DropResponse }
+    | { DragEnded: DragEnded }
+    | { PostNotification: PostNotification }
+    | { PostNotificationResponse: PostNotificationResponse }
+    | { NotificationActionInvoked: NotificationActionInvoked }
+    | { SetKeyboardLayout: SetKeyboardLayout }
+    | { KeyboardLayoutChanged: KeyboardLayoutChanged };
+
+export interface ARGBColor {
+    a: number;
+    b: number;
+    g: number;
+    r: number;
+}
+
+export interface AppDisconnected {
+    app_id: string;
+}
+
+export interface AudioPauseTrackRequest {
+    app_id: string;
+    track: JObj;
+}
+
+export interface AudioPauseTrackResponse {
+    app_id: string;
+    success: boolean;
+    track: JObj;
+}
+
+export interface AudioPlayTrackRequest {
+    app_id: string;
+    track: JObj;
+}
+
+export interface AudioPlayTrackResponse {
+    app_id: string;
+    success: boolean;
+    track: JObj;
+}
+
+export type ClipboardAction =
+    | "Copy"
+    | "Cut"
+    | "Paste";
+
+export interface ClipboardChanged {
+    app_id: string;
+    mime_types: string[];
+    owner: string;
+}
+
+export type ClipboardData =
+    | { Text: string }
+    | { Image: GFXBuffer }
+    | { ObjectId: string };
+
+export interface ClipboardGet {
+    app_id: string;
+    mime_types: string[];
+}
+
+export interface ClipboardGetResponse {
+    app_id: string;
+    items: ClipboardItem[];
+}
+
+export interface ClipboardItem {
+    data: ClipboardData;
+    mime_type: string;
+}
+
+export interface ClipboardSet {
+    app_id: string;
+    items: ClipboardItem[];
+}
+
+export interface ClipboardShortcut {
+    action: ClipboardAction;
+    app_id: string;
+    window_id: string;
+}
+
+export interface CloseWindowRequest {
+    app_id: string;
+    window_id: string;
+}
+
+export interface CloseWindowResponse {
+    app_id: string;
+    window_id: string;
+}
+
+export interface DBAddRequest {
+    app_id: string;
+    object: JObj;
+}
+
+export interface DBAddResponse {
+    app_id: string;
+    object: JObj;
+    success: boolean;
+}
+
+export interface DBDeleteRequest {
+    app_id: string;
+    object: JObj;
+}
+
+export interface DBDeleteResponse {
+    app_id: string;
+    object: JObj;
+    success: boolean;
+}
+
+export interface DBQueryClause {
+    key: string;
+    kind: DBQueryClauseKind;
+    value: string;
+}
+
+export type DBQueryClauseKind =
+    | "equals"
+    | "equalsi"
+    | "substring"
+    | "substringi";
+
+export interface DBQueryRequest {
+    app_id: string;
+    query: DBQueryClause[];
+}
+
+export interface DBQueryResponse {
+    app_id: string;
+    results: JObj[];
+    success: boolean;
+}
+
+export interface DBUpdateRequest {
+    app_id: string;
+    object: JObj;
+}
+
+export interface DBUpdateResponse {
+    app_id: string;
+    object: JObj;
+    success: boolean;
+}
+
+export interface DebugAppInfo {
+    app_id: string;
+    app_name: string;
+    metadata: { [key: string]: string };
+}
+
+export type DebugMessage =
+    | "HelloDebugger"
+    | "HelloDebuggerResponse"
+    | "ServerStarted"
+    | "ServerStopped"
+    | "WindowManagerConnected"
+    | "WindowManagerDisconnected"
+    | "DebuggerDisconnected"
+    | "BackgroundReceivedMouseEvent"
+    | "RequestServerShutdown"
+    | { AppConnected: DebugAppInfo }
+    | { AppDisconnected: DebugAppInfo }
+    | { WindowOpened: DebugWindowInfo }
+    | { WindowClosed: DebugWindowInfo }
+    | { WindowFocusChanged: DebugWindowInfo }
+    | { AppLog: string }
+    | { FakeMouseEvent: MouseDownEvent }
+    | { ScreenCapture: [Rect, string] }
+    | { ScreenCaptureResponse: [] };
+
+export interface DebugWindowInfo {
+    app_id: string;
+    app_name: string;
+    window_id: string;
+    window_title: string;
+}
+
+export interface DragEnded {
+    accepted: boolean;
+    app_id: string;
+    drag_id: string;
+    window_id: string;
+}
+
+export interface DragEnterEvent {
+    app_id: string;
+    drag_id: string;
+    mime_types: string[];
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface DragLeaveEvent {
+    app_id: string;
+    drag_id: string;
+    window_id: string;
+}
+
+export interface DragOverEvent {
+    app_id: string;
+    drag_id: string;
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface DrawBatchCommand {
+    app_id: string;
+    primitives: DrawPrimitive[];
+    window_id: string;
+}
+
+export interface DrawImageCommand {
+    app_id: string;
+    buffer: GFXBuffer;
+    encoding?: ImageEncoding;
+    rect: Rect;
+    window_id: string;
+}
+
+export type DrawPrimitive =
+    | { FillRect: { color: ARGBColor; rect: Rect } }
+    | { Image: { buffer: GFXBuffer; rect: Rect } };
+
+export interface DrawRectCommand {
+    app_id: string;
+    color: ARGBColor;
+    rect: Rect;
+    window_id: string;
+}
+
+export interface DrawSharedImageCommand {
+    app_id: string;
+    buffer_id: string;
+    rect: Rect;
+    window_id: string;
+}
+
+export interface DropEvent {
+    app_id: string;
+    drag_id: string;
+    items: ClipboardItem[];
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface DropResponse {
+    accepted: boolean;
+    app_id: string;
+    drag_id: string;
+    window_id: string;
+}
+
+export interface GFXBuffer {
+    data: number[];
+    height: number;
+    id: string;
+    layout: PixelLayout;
+    width: number;
+}
+
+export interface HelloApp {
+    capabilities?: string[];
+    client_name?: string;
+    metadata?: { [key: string]: string };
+    protocol_version?: number;
+    wire_format?: WireFormat;
+}
+
+export interface HelloAppResponse {
+    app_id: string;
+    capabilities?: string[];
+    protocol_version?: number;
+    rejected?: string | null;
+    wire_format?: WireFormat;
+}
+
+export interface HelloWindowManager {
+    capabilities?: string[];
+    client_name?: string;
+    protocol_version?: number;
+    wire_format?: WireFormat;
+}
+
+export interface HelloWindowManagerResponse {
+    capabilities?: string[];
+    protocol_version?: number;
+    rejected?: string | null;
+    wire_format?: WireFormat;
+    wm_id: string;
+}
+
+export type ImageEncoding =
+    | "Raw"
+    | "Png"
+    | "Qoi"
+    | "Rle";
+
+export interface JObj {
+    data: { [key: string]: string };
+    deleted: boolean;
+    id: string;
+}
+
+export type KeyCode =
+    | "RESERVED"
+    | "UNKNOWN"
+    | "LETTER_A"
+    | "LETTER_B"
+    | "LETTER_C"
+    | "LETTER_D"
+    | "LETTER_E"
+    | "LETTER_F"
+    | "LETTER_G"
+    | "LETTER_H"
+    | "LETTER_I"
+    | "LETTER_J"
+    | "LETTER_K"
+    | "LETTER_L"
+    | "LETTER_M"
+    | "LETTER_N"
+    | "LETTER_O"
+    | "LETTER_P"
+    | "LETTER_Q"
+    | "LETTER_R"
+    | "LETTER_S"
+    | "LETTER_T"
+    | "LETTER_U"
+    | "LETTER_V"
+    | "LETTER_W"
+    | "LETTER_X"
+    | "LETTER_Y"
+    | "LETTER_Z"
+    | "DIGIT_0"
+    | "DIGIT_1"
+    | "DIGIT_2"
+    | "DIGIT_3"
+    | "DIGIT_4"
+    | "DIGIT_5"
+    | "DIGIT_6"
+    | "DIGIT_7"
+    | "DIGIT_8"
+    | "DIGIT_9"
+    | "ARROW_LEFT"
+    | "ARROW_RIGHT"
+    | "ARROW_UP"
+    | "ARROW_DOWN"
+    | "SHIFT_LEFT"
+    | "SHIFT_RIGHT"
+    | "CONTROL_LEFT"
+    | "CONTROL_RIGHT"
+    | "ALT_LEFT"
+    | "ALT_RIGHT"
+    | "META_LEFT"
+    | "META_RIGHT"
+    | "BACKSPACE"
+    | "DELETE"
+    | "ENTER"
+    | "ESCAPE"
+    | "TAB"
+    | "COMMA"
+    | "LESS_THAN"
+    | "PERIOD"
+    | "GREATER_THAN"
+    | "SLASH"
+    | "QUESTION"
+    | "SEMICOLON"
+    | "COLON"
+    | "QUOTE"
+    | "DOUBLE_QUOTE"
+    | "BACKQUOTE"
+    | "EXCLAIM"
+    | "AT"
+    | "HASH"
+    | "DOLLAR"
+    | "PERCENT"
+    | "CARET"
+    | "AMPERSAND"
+    | "ASTERISK"
+    | "LEFT_PAREN"
+    | "RIGHT_PAREN"
+    | "UNDERSCORE"
+    | "PLUS"
+    | "BRACKET_LEFT"
+    | "BRACKET_RIGHT"
+    | "BRACE_LEFT"
+    | "BRACE_RIGHT"
+    | "BACKSLASH"
+    | "PIPE"
+    | "MINUS"
+    | "EQUALS"
+    | "SPACE"
+    | "INTL_BACKSLASH"
+    | "F1"
+    | "F2"
+    | "F3"
+    | "F4"
+    | "F5"
+    | "F6"
+    | "F7"
+    | "F8"
+    | "F9"
+    | "F10"
+    | "F11"
+    | "F12"
+    | "HOME"
+    | "END"
+    | "PAGE_UP"
+    | "PAGE_DOWN"
+    | "INSERT"
+    | "CAPS_LOCK"
+    | "NUM_LOCK"
+    | "SCROLL_LOCK"
+    | "PRINT_SCREEN"
+    | "PAUSE"
+    | "CONTEXT_MENU"
+    | "NUMPAD_0"
+    | "NUMPAD_1"
+    | "NUMPAD_2"
+    | "NUMPAD_3"
+    | "NUMPAD_4"
+    | "NUMPAD_5"
+    | "NUMPAD_6"
+    | "NUMPAD_7"
+    | "NUMPAD_8"
+    | "NUMPAD_9"
+    | "NUMPAD_DIVIDE"
+    | "NUMPAD_MULTIPLY"
+    | "NUMPAD_SUBTRACT"
+    | "NUMPAD_ADD"
+    | "NUMPAD_DECIMAL"
+    | "NUMPAD_EQUALS"
+    | "NUMPAD_ENTER"
+    | "MEDIA_PLAY_PAUSE"
+    | "MEDIA_STOP"
+    | "MEDIA_NEXT"
+    | "MEDIA_PREVIOUS"
+    | "VOLUME_UP"
+    | "VOLUME_DOWN"
+    | "VOLUME_MUTE"
+    | "MOUSE_PRIMARY";
+
+export interface KeyDownEvent {
+    app_id: string;
+    key: KeyCode;
+    mods: ModifierState;
+    repeat?: boolean;
+    window_id: string;
+}
+
+export interface KeyUpEvent {
+    app_id: string;
+    key: KeyCode;
+    mods: ModifierState;
+    window_id: string;
+}
+
+export interface KeyboardLayoutChanged {
+    name: string;
+    title: string;
+}
+
+export interface ModifierState {
+    alt: boolean;
+    altgr?: boolean;
+    ctrl: boolean;
+    meta: boolean;
+    shift: boolean;
+}
+
+export type MouseButton =
+    | "Primary"
+    | "Secondary"
+    | "Middle"
+    | "Back"
+    | "Forward";
+
+export interface MouseDownEvent {
+    app_id: string;
+    button: MouseButton;
+    clicks?: number;
+    original_timestamp: number;
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface MouseMoveEvent {
+    app_id: string;
+    button: MouseButton;
+    original_timestamp: number;
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface MouseUpEvent {
+    app_id: string;
+    button: MouseButton;
+    original_timestamp: number;
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface MouseWheelEvent {
+    app_id: string;
+    dx: number;
+    dy: number;
+    original_timestamp: number;
+    window_id: string;
+    x: number;
+    y: number;
+}
+
+export interface NotificationAction {
+    id: string;
+    label: string;
+}
+
+export interface NotificationActionInvoked {
+    action_id: string;
+    app_id: string;
+    notification_id: string;
+}
+
+export interface OpenWindowCommand {
+    bounds: Rect;
+    parent?: string | null;
+    window_title: string;
+    window_type: string;
+}
+
+export interface OpenWindowResponse {
+    app_id: string;
+    bounds: Rect;
+    parent?: string | null;
+    window_id: string;
+    window_title: string;
+    window_type: string;
+}
+
+export type PixelLayout =
+    | { RGB565: [] }
+    | { ARGB: [] };
+
+export interface PostNotification {
+    actions: NotificationAction[];
+    app_id: string;
+    body: string;
+    icon?: GFXBuffer | null;
+    notification_id: string;
+    timeout_ms: number;
+    title: string;
+}
+
+export interface PostNotificationResponse {
+    app_id: string;
+    notification_id: string;
+}
+
+export interface RaiseWindow {
+    app_id: string;
+    window_id: string;
+}
+
+export interface Rect {
+    h: number;
+    w: number;
+    x: number;
+    y: number;
+}
+
+export interface RegisterSharedBuffer {
+    app_id: string;
+    info: SharedBufferInfo;
+}
+
+export interface RegisterSharedBufferResponse {
+    app_id: string;
+    buffer_id: string;
+    success: boolean;
+}
+
+export interface SetKeyboardLayout {
+    app_id: string;
+    name: string;
+}
+
+export interface SetWindowBounds {
+    app_id: string;
+    bounds: Rect;
+    window_id: string;
+}
+
+export interface SetWindowTitle {
+    app_id: string;
+    title: string;
+    window_id: string;
+}
+
+export interface SetWindowVisible {
+    app_id: string;
+    visible: boolean;
+    window_id: string;
+}
+
+export interface SharedBufferInfo {
+    buffer_id: string;
+    fd: number;
+    height: number;
+    layout: PixelLayout;
+    pid: number;
+    width: number;
+}
+
+export interface Size {
+    h: number;
+    w: number;
+}
+
+export interface StartDrag {
+    app_id: string;
+    drag_id: string;
+    items: ClipboardItem[];
+    window_id: string;
+}
+
+export interface TextInputEvent {
+    app_id: string;
+    text: string;
+    window_id: string;
+}
+
+export interface UnregisterSharedBuffer {
+    app_id: string;
+    buffer_id: string;
+}
+
+export interface WindowResized {
+    app_id: string;
+    size: Size;
+    window_id: string;
+}
+
+/** How messages are encoded on a connection after the handshake. The AppConnect / WMConnect handshake itself is always plain json. */
+export type WireFormat =
+    | "Json"
+    | "Binary";
+
+export interface IncomingMessage {
+    command: APICommand;
+    request_id?: number | null;
+    source: string;
+    timestamp_usec: number;
+    trace: boolean;
+}
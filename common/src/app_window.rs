@@ -0,0 +1,337 @@
+//! A window with its own backbuffer, on top of the async client.
+//!
+//! ```ignore
+//! let (client, _events) = AsyncClient::connect("my-app").await?;
+//! let client = Arc::new(client);
+//! let mut win = AppWindow::open(&client, "hello", Rect::from_ints(50, 50, 300, 200)).await?;
+//! win.clear(&WHITE);
+//! win.fill_rect(&Rect::from_ints(10, 10, 50, 50), &BLACK);
+//! win.flush()?;
+//! while let Some(event) = win.next_event().await {
+//!     match event {
+//!         WindowEvent::Closed => break,
+//!         _ => {}
+//!     }
+//! }
+//! ```
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect, Size};
+use crate::{APICommand, ClipboardAction, ClipboardItem, DragEnded, DragEnterEvent, DragLeaveEvent, DragOverEvent, DropEvent, OpenWindowResponse, window_types};
+use crate::async_client::{AsyncClient, ClientError};
+use crate::events::{KeyDownEvent, KeyUpEvent, MouseDownEvent, MouseMoveEvent, MouseUpEvent, MouseWheelEvent, TextInputEvent};
+
+/// Something that happened to one window. Mouse coordinates are relative to the window.
+#[derive(Debug, Clone)]
+pub enum WindowEvent {
+    KeyDown(KeyDownEvent),
+    KeyUp(KeyUpEvent),
+    /// what the user typed. use this rather than KeyDown for text fields.
+    TextInput(TextInputEvent),
+    MouseDown(MouseDownEvent),
+    MouseMove(MouseMoveEvent),
+    MouseUp(MouseUpEvent),
+    /// the user scrolled over the window. dx and dy are in lines.
+    MouseWheel(MouseWheelEvent),
+    /// the window manager changed the size. the buffer has already been resized and marked dirty.
+    Resized(Size),
+    /// the user pressed the copy, cut or paste shortcut
+    Clipboard(ClipboardAction),
+    /// something is being dragged over this window
+    DragEnter(DragEnterEvent),
+    DragOver(DragOverEvent),
+    DragLeave(DragLeaveEvent),
+    /// answer with accept_drop
+    Drop(DropEvent),
+    /// a drag started from this window is over
+    DragEnded(DragEnded),
+    /// the user closed the window. no more events will come.
+    Closed,
+}
+
+/// An open window. Draw into it, then call flush to send what changed to central.
+pub struct AppWindow {
+    client: Arc<AsyncClient>,
+    pub window_id: Uuid,
+    pub title: String,
+    buffer: GFXBuffer,
+    // everything drawn since the last flush
+    dirty: Option<Rect>,
+    events: mpsc::UnboundedReceiver<APICommand>,
+}
+
+// a zero sized window still gets a pixel so the buffer is valid
+fn new_buffer(size: Size) -> GFXBuffer {
+    GFXBuffer::new(size.w.max(1) as u32, size.h.max(1) as u32, &PixelLayout::ARGB())
+}
+
+impl AppWindow {
+    pub async fn open(client: &Arc<AsyncClient>, title: &str, bounds: Rect) -> Result<AppWindow, ClientError> {
+        let opened = client.open_routed_window(window_types::PLAIN, None, title, bounds).await?;
+        Ok(AppWindow::opened(client, opened))
+    }
+
+    /// A menu or dropdown over this window, placed relative to its contents. It gets Closed when
+    /// the user clicks anywhere outside it.
+    pub async fn open_popup(&self, bounds: Rect) -> Result<AppWindow, ClientError> {
+        let opened = self.client.open_routed_window(window_types::POPUP, Some(self.window_id), "", bounds).await?;
+        Ok(AppWindow::opened(&self.client, opened))
+    }
+
+    /// A dialog over this window. This window gets no input until the dialog is closed.
+    pub async fn open_modal(&self, title: &str, bounds: Rect) -> Result<AppWindow, ClientError> {
+        let opened = self.client.open_routed_window(window_types::MODAL, Some(self.window_id), title, bounds).await?;
+        Ok(AppWindow::opened(&self.client, opened))
+    }
+
+    fn opened(client: &Arc<AsyncClient>, (resp, events): (OpenWindowResponse, mpsc::UnboundedReceiver<APICommand>)) -> AppWindow {
+        AppWindow {
+            client: client.clone(),
+            window_id: resp.window_id,
+            title: resp.window_title,
+            buffer: new_buffer(resp.bounds.size()),
+            dirty: None,
+            events,
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        self.buffer.bounds().size()
+    }
+
+    pub fn buffer(&self) -> &GFXBuffer {
+        &self.buffer
+    }
+
+    /// For drawing code which wants the buffer itself, like text rendering.
+    /// Call mark_dirty with whatever was changed.
+    pub fn buffer_mut(&mut self) -> &mut GFXBuffer {
+        &mut self.buffer
+    }
+
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let rect = rect.intersect(self.buffer.bounds());
+        if rect.is_empty() {
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+
+    pub fn clear(&mut self, color: &ARGBColor) {
+        self.buffer.clear(color);
+        self.mark_dirty(self.buffer.bounds());
+    }
+
+    pub fn fill_rect(&mut self, rect: &Rect, color: &ARGBColor) {
+        self.buffer.fill_rect(rect, color);
+        self.mark_dirty(*rect);
+    }
+
+    pub fn fill_rect_with_image(&mut self, rect: &Rect, image: &GFXBuffer) {
+        self.buffer.fill_rect_with_image(rect, image);
+        self.mark_dirty(*rect);
+    }
+
+    pub fn draw_image(&mut self, dst_pos: &Point, src_bounds: &Rect, src: &GFXBuffer) {
+        self.buffer.draw_image(dst_pos, src_bounds, src);
+        self.mark_dirty(src_bounds.add(dst_pos));
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), ClientError> {
+        self.client.set_window_title(self.window_id, title)?;
+        self.title = title.to_string();
+        Ok(())
+    }
+
+    /// The buffer keeps its size until the Resized event arrives.
+    pub fn set_bounds(&self, bounds: Rect) -> Result<(), ClientError> {
+        self.client.set_window_bounds(self.window_id, bounds)
+    }
+
+    pub fn raise(&self) -> Result<(), ClientError> {
+        self.client.raise_window(self.window_id)
+    }
+
+    pub fn set_visible(&self, visible: bool) -> Result<(), ClientError> {
+        self.client.set_window_visible(self.window_id, visible)
+    }
+
+    /// Ask for the window to be closed. Keep reading events until Closed comes back.
+    pub fn close(&self) -> Result<(), ClientError> {
+        self.client.close_window(self.window_id)
+    }
+
+    /// Drag something out of this window, like a song by its object id and title. Call it while
+    /// handling a mouse down or move, before the mouse comes up.
+    pub fn start_drag(&self, items: Vec<ClipboardItem>) -> Result<Uuid, ClientError> {
+        self.client.start_drag(self.window_id, items)
+    }
+
+    pub fn accept_drop(&self, drop: &DropEvent, accepted: bool) -> Result<(), ClientError> {
+        self.client.respond_to_drop(drop.window_id, drop.drag_id, accepted)
+    }
+
+    /// Send the part of the buffer that changed since the last flush. Does nothing if nothing changed.
+    pub fn flush(&mut self) -> Result<(), ClientError> {
+        if let Some(dirty) = self.dirty.take() {
+            self.client.draw_image(self.window_id, dirty, self.buffer.sub_rect(dirty))?;
+        }
+        Ok(())
+    }
+
+    /// The next event for this window, or None once the window is closed or the connection is gone.
+    pub async fn next_event(&mut self) -> Option<WindowEvent> {
+        let cmd = self.events.recv().await?;
+        Some(match cmd {
+            APICommand::KeyDown(evt) => WindowEvent::KeyDown(evt),
+            APICommand::KeyUp(evt) => WindowEvent::KeyUp(evt),
+            APICommand::TextInput(evt) => WindowEvent::TextInput(evt),
+            APICommand::MouseDown(evt) => WindowEvent::MouseDown(evt),
+            APICommand::MouseMove(evt) => WindowEvent::MouseMove(evt),
+            APICommand::MouseUp(evt) => WindowEvent::MouseUp(evt),
+            APICommand::MouseWheel(evt) => WindowEvent::MouseWheel(evt),
+            APICommand::WindowResized(evt) => {
+                // keep what was already drawn and send all of it again at the new size
+                let old = std::mem::replace(&mut self.buffer, new_buffer(evt.size));
+                self.buffer.draw_image(&Point::init(0, 0), &old.bounds(), &old);
+                self.dirty = None;
+                self.mark_dirty(self.buffer.bounds());
+                WindowEvent::Resized(evt.size)
+            }
+            APICommand::ClipboardShortcut(evt) => WindowEvent::Clipboard(evt.action),
+            APICommand::DragEnter(evt) => WindowEvent::DragEnter(evt),
+            APICommand::DragOver(evt) => WindowEvent::DragOver(evt),
+            APICommand::DragLeave(evt) => WindowEvent::DragLeave(evt),
+            APICommand::Drop(evt) => WindowEvent::Drop(evt),
+            APICommand::DragEnded(evt) => WindowEvent::DragEnded(evt),
+            APICommand::CloseWindowResponse(_) => {
+                self.client.unroute_window(self.window_id);
+                self.events.close();
+                WindowEvent::Closed
+            }
+            // only window events are routed here
+            _ => return None,
+        })
+    }
+}
+
+impl Drop for AppWindow {
+    fn drop(&mut self) {
+        self.client.unroute_window(self.window_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+    use gfx::graphics::{ARGBColor, Rect};
+    use crate::{APICommand, CloseWindowResponse, HelloAppResponse, IncomingMessage, OpenWindowResponse, WindowResized};
+    use crate::app_window::{AppWindow, WindowEvent};
+    use crate::async_client::AsyncClient;
+    use crate::events::{MouseButton, MouseDownEvent, MouseMoveEvent};
+    use crate::protocol::default_capabilities;
+    use crate::wire::{decode_message, encode_message, WireFormat};
+
+    fn wrap(cmd: APICommand, request_id: Option<u64>) -> IncomingMessage {
+        IncomingMessage { source: Default::default(), command: cmd, trace: false, timestamp_usec: 0, request_id }
+    }
+
+    // opens windows, reports every draw, and sends some events once the first draw arrives
+    async fn fake_central(mut stream: DuplexStream, draws: mpsc::UnboundedSender<Rect>) {
+        let mut buf = vec![];
+        let mut chunk = [0u8; 4096];
+        let mut format = WireFormat::Json;
+        let app_id = Uuid::new_v4();
+        loop {
+            while let Some(msg) = decode_message::<IncomingMessage>(&mut buf, format).unwrap() {
+                let replies = match msg.command {
+                    APICommand::AppConnect(hello) => {
+                        let resp = wrap(APICommand::AppConnectResponse(HelloAppResponse {
+                            app_id,
+                            wire_format: hello.wire_format,
+                            protocol_version: hello.protocol_version,
+                            capabilities: default_capabilities(),
+                            rejected: None,
+                        }), msg.request_id);
+                        stream.write_all(&encode_message(&resp, WireFormat::Json).unwrap()).await.unwrap();
+                        format = hello.wire_format;
+                        continue;
+                    }
+                    APICommand::OpenWindowCommand(cmd) => {
+                        let window_id = Uuid::new_v4();
+                        vec![
+                            wrap(APICommand::OpenWindowResponse(OpenWindowResponse {
+                                app_id,
+                                window_id,
+                                window_type: cmd.window_type,
+                                window_title: cmd.window_title,
+                                bounds: cmd.bounds,
+                                parent: cmd.parent,
+                            }), msg.request_id),
+                            // straight after the reply, before the app has done anything with it
+                            wrap(APICommand::MouseMove(MouseMoveEvent { app_id, window_id, original_timestamp: 0, button: MouseButton::Primary, x: 1, y: 1 }), None),
+                        ]
+                    }
+                    APICommand::DrawImageCommand(cmd) => {
+                        draws.send(cmd.rect).unwrap();
+                        let window_id = cmd.window_id;
+                        vec![
+                            // for some other window. must not show up in ours.
+                            wrap(APICommand::MouseDown(MouseDownEvent { app_id, window_id: Uuid::new_v4(), original_timestamp: 0, button: MouseButton::Primary, x: 0, y: 0, clicks: 1 }), None),
+                            wrap(APICommand::MouseDown(MouseDownEvent { app_id, window_id, original_timestamp: 0, button: MouseButton::Primary, x: 5, y: 6, clicks: 1 }), None),
+                            wrap(APICommand::WindowResized(WindowResized { app_id, window_id, size: gfx::graphics::Size::init(40, 30) }), None),
+                            wrap(APICommand::CloseWindowResponse(CloseWindowResponse { app_id, window_id }), None),
+                        ]
+                    }
+                    _ => continue,
+                };
+                for reply in replies {
+                    stream.write_all(&encode_message(&reply, format).unwrap()).await.unwrap();
+                }
+            }
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn draw_flush_and_events() {
+        let (client_side, central_side) = tokio::io::duplex(64 * 1024);
+        let (draw_tx, mut draws) = mpsc::unbounded_channel();
+        tokio::spawn(fake_central(central_side, draw_tx));
+        let (reader, writer) = tokio::io::split(client_side);
+        let (client, mut events) = AsyncClient::connect_with(reader, writer, "test-app", HashMap::new()).await.unwrap();
+        let client = Arc::new(client);
+        let mut win = AppWindow::open(&client, "test", Rect::from_ints(0, 0, 20, 20)).await.unwrap();
+        assert_eq!(win.title, "test");
+        assert!(matches!(win.next_event().await, Some(WindowEvent::MouseMove(evt)) if evt.x == 1));
+
+        // nothing to send yet
+        win.flush().unwrap();
+        win.fill_rect(&Rect::from_ints(2, 2, 4, 4), &ARGBColor::new_rgb(255, 0, 0));
+        win.fill_rect(&Rect::from_ints(10, 8, 4, 4), &ARGBColor::new_rgb(0, 255, 0));
+        win.flush().unwrap();
+        assert_eq!(draws.recv().await.unwrap(), Rect::from_ints(2, 2, 12, 10));
+
+        assert!(matches!(win.next_event().await, Some(WindowEvent::MouseDown(evt)) if evt.x == 5 && evt.y == 6));
+        assert!(matches!(win.next_event().await, Some(WindowEvent::Resized(size)) if size.w == 40));
+        // the old content is kept and everything gets sent again
+        assert_eq!(win.buffer().get_pixel_vec_argb(3, 3), ARGBColor::new_rgb(255, 0, 0).as_layout(&win.buffer().layout));
+        assert!(matches!(win.next_event().await, Some(WindowEvent::Closed)));
+        assert!(win.next_event().await.is_none());
+
+        // the other window's event went to the app wide stream
+        assert!(matches!(events.next().await, Some(APICommand::MouseDown(evt)) if evt.x == 0));
+    }
+}
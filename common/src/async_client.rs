@@ -0,0 +1,641 @@
+//! An async client for apps, built on tokio.
+//!
+//! ```ignore
+//! let (client, mut events) = AsyncClient::connect("my-app").await?;
+//! let win = client.open_window("hello", Rect::from_ints(50, 50, 300, 200)).await?;
+//! client.draw_rect(win.window_id, Rect::from_ints(0, 0, 300, 200), BLACK)?;
+//! while let Some(event) = events.next().await {
+//!     // handle input
+//! }
+//! ```
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+use db::JObj;
+use gfx::encoding::ImageEncoding;
+use gfx::graphics::{ARGBColor, GFXBuffer, Rect};
+use crate::{APICommand, APP_MANAGER_PORT, AudioPauseTrackRequest, AudioPlayTrackRequest, ClipboardData, ClipboardGet, ClipboardItem, ClipboardSet, clipboard_types, CloseWindowRequest, DBAddRequest, DBDeleteRequest, DBQueryClause, DBQueryClauseKind, DBQueryRequest, DBUpdateRequest, DrawBatchCommand, DrawImageCommand, DrawPrimitive, DrawRectCommand, DrawSharedImageCommand, DropResponse, HelloApp, IncomingMessage, NOTIFICATION_TYPE, NotificationAction, OpenWindowCommand, OpenWindowResponse, PostNotification, RaiseWindow, RegisterSharedBuffer, SetKeyboardLayout, SetWindowBounds, SetWindowTitle, SetWindowVisible, StartDrag, UnregisterSharedBuffer, window_types};
+use crate::client::{PendingRequests, REQUEST_TIMEOUT};
+use crate::shm::SharedBuffer;
+use crate::protocol::{accepts_image_encoding, capability, default_capabilities, preferred_image_encoding, PROTOCOL_VERSION};
+use crate::transport::APP_SOCKET_ENV;
+use crate::wire::{decode_message, encode_message, WireFormat};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// central refused the handshake
+    Rejected(String),
+    /// the connection to central is gone
+    Disconnected,
+    /// central answered with something other than the reply we expected
+    UnexpectedReply(Box<APICommand>),
+    /// central understood the request but couldn't do it
+    Failed(String),
+    /// no reply came within the client's request_timeout
+    TimedOut,
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "io error talking to central: {}", e),
+            ClientError::Rejected(reason) => write!(f, "central rejected the connection: {}", reason),
+            ClientError::Disconnected => write!(f, "disconnected from central"),
+            ClientError::UnexpectedReply(cmd) => write!(f, "unexpected reply from central: {:?}", cmd),
+            ClientError::Failed(reason) => write!(f, "request failed: {}", reason),
+            ClientError::TimedOut => write!(f, "central didn't reply in time"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+// events for windows which have an AppWindow handle go straight to it
+type WindowRoutes = Arc<Mutex<HashMap<Uuid, mpsc::UnboundedSender<APICommand>>>>;
+type WindowEvents = mpsc::UnboundedReceiver<APICommand>;
+
+// a request waiting for its reply
+struct Waiter {
+    reply: oneshot::Sender<(APICommand, Option<WindowEvents>)>,
+    // if the reply opens a window, route its events before reading anything else, so the
+    // events central sends straight after the reply aren't lost
+    route_window: bool,
+}
+type Pending = Arc<Mutex<PendingRequests<Waiter>>>;
+
+/// Everything central sends which isn't the reply to a request: input, resizes, window closes, shutdown.
+pub struct EventStream {
+    rx: mpsc::UnboundedReceiver<APICommand>,
+}
+
+impl EventStream {
+    /// the next event, or None once the connection is gone
+    pub async fn next(&mut self) -> Option<APICommand> {
+        self.rx.recv().await
+    }
+}
+
+pub struct AsyncClient {
+    out: mpsc::UnboundedSender<IncomingMessage>,
+    pending: Pending,
+    windows: WindowRoutes,
+    next_request_id: AtomicU64,
+    pub app_id: Uuid,
+    pub wire_format: WireFormat,
+    pub protocol_version: u32,
+    pub capabilities: Vec<String>,
+    /// how draw_image compresses images. Raw over the unix socket, where compressing only costs time.
+    pub image_encoding: ImageEncoding,
+    /// how long request waits for a reply before giving up with TimedOut
+    pub request_timeout: Duration,
+}
+
+fn wrap(cmd: APICommand, request_id: Option<u64>) -> IncomingMessage {
+    IncomingMessage {
+        source: Default::default(),
+        command: cmd,
+        trace: false,
+        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+        request_id,
+    }
+}
+
+// read until the buffer holds a whole message
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>, format: WireFormat) -> io::Result<IncomingMessage> {
+    let mut chunk = [0u8; 8192];
+    loop {
+        if let Some(msg) = decode_message(buf, format)? {
+            return Ok(msg);
+        }
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "central closed the connection"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+impl AsyncClient {
+    /// Connect to central over the Unix socket named by CLOGWENCH_APP_SOCKET, or tcp on localhost.
+    /// Must be called from inside a tokio runtime.
+    pub async fn connect(name: &str) -> Result<(AsyncClient, EventStream), ClientError> {
+        match std::env::var_os(APP_SOCKET_ENV) {
+            #[cfg(unix)]
+            Some(path) => {
+                let (reader, writer) = tokio::net::UnixStream::connect(path).await?.into_split();
+                let (mut client, events) = AsyncClient::connect_with(reader, writer, name, HashMap::new()).await?;
+                client.image_encoding = ImageEncoding::Raw;
+                Ok((client, events))
+            }
+            #[cfg(not(unix))]
+            Some(_) => Err(ClientError::Io(io::Error::new(io::ErrorKind::Unsupported, "unix sockets aren't supported on this platform"))),
+            None => {
+                let (reader, writer) = tokio::net::TcpStream::connect(format!("localhost:{}", APP_MANAGER_PORT)).await?.into_split();
+                AsyncClient::connect_with(reader, writer, name, HashMap::new()).await
+            }
+        }
+    }
+
+    /// Do the handshake over an already open connection and start the reading and writing tasks.
+    pub async fn connect_with<R, W>(mut reader: R, mut writer: W, name: &str, metadata: HashMap<String, String>) -> Result<(AsyncClient, EventStream), ClientError>
+        where R: AsyncRead + Unpin + Send + 'static,
+              W: AsyncWrite + Unpin + Send + 'static {
+        // the handshake is always json
+        let hello = wrap(APICommand::AppConnect(HelloApp {
+            wire_format: WireFormat::Binary,
+            protocol_version: PROTOCOL_VERSION,
+            client_name: name.to_string(),
+            capabilities: default_capabilities(),
+            metadata,
+        }), None);
+        writer.write_all(&encode_message(&hello, WireFormat::Json)?).await?;
+        let mut buf = vec![];
+        let resp = match read_message(&mut reader, &mut buf, WireFormat::Json).await?.command {
+            APICommand::AppConnectResponse(resp) => resp,
+            cmd => return Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        };
+        if let Some(reason) = resp.rejected {
+            return Err(ClientError::Rejected(reason));
+        }
+        let wire_format = resp.wire_format;
+
+        let pending: Pending = Arc::new(Mutex::new(PendingRequests::new()));
+        let windows: WindowRoutes = Arc::new(Mutex::new(HashMap::new()));
+        let (event_tx, event_rx) = mpsc::unbounded_channel::<APICommand>();
+        tokio::spawn({
+            let pending = pending.clone();
+            let windows = windows.clone();
+            async move {
+                loop {
+                    match read_message(&mut reader, &mut buf, wire_format).await {
+                        Ok(msg) => {
+                            let waiting = msg.request_id.and_then(|id| pending.lock().unwrap().take(id));
+                            match waiting {
+                                Some(waiter) => {
+                                    let route = match &msg.command {
+                                        APICommand::OpenWindowResponse(resp) if waiter.route_window => {
+                                            let (tx, rx) = mpsc::unbounded_channel();
+                                            windows.lock().unwrap().insert(resp.window_id, tx);
+                                            Some((resp.window_id, rx))
+                                        }
+                                        _ => None,
+                                    };
+                                    let (window_id, events) = route.unzip();
+                                    // the caller may have stopped waiting. that's fine, but nobody will read the window's events.
+                                    if waiter.reply.send((msg.command, events)).is_err() {
+                                        if let Some(window_id) = window_id {
+                                            windows.lock().unwrap().remove(&window_id);
+                                        }
+                                    }
+                                }
+                                None => {
+                                    let window_tx = msg.command.event_window_id().and_then(|id| windows.lock().unwrap().get(&id).cloned());
+                                    match window_tx {
+                                        Some(window_tx) => { let _ = window_tx.send(msg.command); }
+                                        None => { let _ = event_tx.send(msg.command); }
+                                    }
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            if e.kind() != io::ErrorKind::UnexpectedEof {
+                                log::error!("error reading from central {}", e);
+                            }
+                            break;
+                        }
+                    }
+                }
+                // wakes up everyone still waiting for a reply or an event
+                pending.lock().unwrap().close();
+                windows.lock().unwrap().clear();
+            }
+        });
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel::<IncomingMessage>();
+        tokio::spawn(async move {
+            while let Some(msg) = out_rx.recv().await {
+                let res = match encode_message(&msg, wire_format) {
+                    Ok(data) => writer.write_all(&data).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    log::error!("error writing to central {}", e);
+                    break;
+                }
+            }
+        });
+        Ok((AsyncClient {
+            out: out_tx,
+            pending,
+            windows,
+            next_request_id: AtomicU64::new(1),
+            app_id: resp.app_id,
+            wire_format,
+            protocol_version: resp.protocol_version,
+            image_encoding: preferred_image_encoding(&resp.capabilities),
+            request_timeout: REQUEST_TIMEOUT,
+            capabilities: resp.capabilities,
+        }, EventStream { rx: event_rx }))
+    }
+
+    /// send a command without waiting for anything to come back
+    pub fn send(&self, cmd: APICommand) -> Result<(), ClientError> {
+        self.out.send(wrap(cmd, None)).map_err(|_| ClientError::Disconnected)
+    }
+
+    /// send a command and wait for central's reply to it
+    pub async fn request(&self, cmd: APICommand) -> Result<APICommand, ClientError> {
+        self.request_with(cmd, false).await.map(|(reply, _)| reply)
+    }
+
+    async fn request_with(&self, cmd: APICommand, route_window: bool) -> Result<(APICommand, Option<WindowEvents>), ClientError> {
+        if !self.capabilities.iter().any(|c| c == capability::REQUEST_IDS) {
+            return Err(ClientError::Failed(String::from("central doesn't tag replies with request ids")));
+        }
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let waiter = Waiter { reply: reply_tx, route_window };
+        if !self.pending.lock().unwrap().add(request_id, waiter, self.request_timeout) {
+            return Err(ClientError::Disconnected);
+        }
+        if self.out.send(wrap(cmd, Some(request_id))).is_err() {
+            self.pending.lock().unwrap().take(request_id);
+            return Err(ClientError::Disconnected);
+        }
+        match tokio::time::timeout(self.request_timeout, reply_rx).await {
+            Ok(reply) => reply.map_err(|_| ClientError::Disconnected),
+            Err(_) => {
+                self.pending.lock().unwrap().take(request_id);
+                Err(ClientError::TimedOut)
+            }
+        }
+    }
+
+    pub async fn open_window(&self, title: &str, bounds: Rect) -> Result<OpenWindowResponse, ClientError> {
+        self.open_child_window(window_types::PLAIN, None, title, bounds).await
+    }
+
+    /// Open a popup or modal on top of another of this app's windows. The bounds are relative to
+    /// the parent's contents.
+    pub async fn open_child_window(&self, window_type: &str, parent: Option<Uuid>, title: &str, bounds: Rect) -> Result<OpenWindowResponse, ClientError> {
+        self.open_window_with(window_type, parent, title, bounds, false).await.map(|(resp, _)| resp)
+    }
+
+    // open a window whose events go to the returned channel instead of the EventStream
+    pub(crate) async fn open_routed_window(&self, window_type: &str, parent: Option<Uuid>, title: &str, bounds: Rect) -> Result<(OpenWindowResponse, WindowEvents), ClientError> {
+        match self.open_window_with(window_type, parent, title, bounds, true).await? {
+            (resp, Some(events)) => Ok((resp, events)),
+            (resp, None) => Err(ClientError::UnexpectedReply(Box::new(APICommand::OpenWindowResponse(resp)))),
+        }
+    }
+
+    async fn open_window_with(&self, window_type: &str, parent: Option<Uuid>, title: &str, bounds: Rect, route_window: bool) -> Result<(OpenWindowResponse, Option<WindowEvents>), ClientError> {
+        let cmd = APICommand::OpenWindowCommand(OpenWindowCommand {
+            window_type: window_type.to_string(),
+            window_title: title.to_string(),
+            bounds,
+            parent,
+        });
+        match self.request_with(cmd, route_window).await? {
+            (APICommand::OpenWindowResponse(resp), events) => Ok((resp, events)),
+            (cmd, _) => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub fn set_window_title(&self, window_id: Uuid, title: &str) -> Result<(), ClientError> {
+        self.send(APICommand::SetWindowTitle(SetWindowTitle { app_id: self.app_id, window_id, title: title.to_string() }))
+    }
+
+    /// Move and resize the window. A WindowResized event follows once the window manager has
+    /// resized it.
+    pub fn set_window_bounds(&self, window_id: Uuid, bounds: Rect) -> Result<(), ClientError> {
+        self.send(APICommand::SetWindowBounds(SetWindowBounds { app_id: self.app_id, window_id, bounds }))
+    }
+
+    pub fn raise_window(&self, window_id: Uuid) -> Result<(), ClientError> {
+        self.send(APICommand::RaiseWindow(RaiseWindow { app_id: self.app_id, window_id }))
+    }
+
+    pub fn set_window_visible(&self, window_id: Uuid, visible: bool) -> Result<(), ClientError> {
+        self.send(APICommand::SetWindowVisible(SetWindowVisible { app_id: self.app_id, window_id, visible }))
+    }
+
+    /// Central answers with a CloseWindowResponse event, the same one the user closing it sends.
+    pub fn close_window(&self, window_id: Uuid) -> Result<(), ClientError> {
+        self.send(APICommand::CloseWindowRequest(CloseWindowRequest { app_id: self.app_id, window_id }))
+    }
+
+    pub(crate) fn unroute_window(&self, window_id: Uuid) {
+        self.windows.lock().unwrap().remove(&window_id);
+    }
+
+    pub fn draw_rect(&self, window_id: Uuid, rect: Rect, color: ARGBColor) -> Result<(), ClientError> {
+        self.send(APICommand::DrawRectCommand(DrawRectCommand {
+            app_id: self.app_id,
+            window_id,
+            rect,
+            color,
+        }))
+    }
+
+    pub fn draw_image(&self, window_id: Uuid, rect: Rect, buffer: GFXBuffer) -> Result<(), ClientError> {
+        self.draw_image_encoded(window_id, rect, buffer, self.image_encoding)
+    }
+
+    /// Like draw_image with a particular encoding, like png for photos. Falls back to raw pixels
+    /// if central didn't agree to the encoding.
+    pub fn draw_image_encoded(&self, window_id: Uuid, rect: Rect, buffer: GFXBuffer, encoding: ImageEncoding) -> Result<(), ClientError> {
+        let mut cmd = DrawImageCommand {
+            app_id: self.app_id,
+            window_id,
+            rect,
+            buffer,
+            encoding: ImageEncoding::Raw,
+        };
+        if accepts_image_encoding(&self.capabilities, encoding) {
+            cmd.encode(encoding).map_err(ClientError::Failed)?;
+        }
+        self.send(APICommand::DrawImageCommand(cmd))
+    }
+
+    /// Draw everything in one message. Centrals which don't know about batches get separate draw commands.
+    pub fn draw_batch(&self, window_id: Uuid, primitives: Vec<DrawPrimitive>) -> Result<(), ClientError> {
+        let batch = DrawBatchCommand {
+            app_id: self.app_id,
+            window_id,
+            primitives,
+        };
+        if self.capabilities.iter().any(|c| c == capability::DRAW_BATCH) {
+            self.send(APICommand::DrawBatchCommand(batch))
+        } else {
+            batch.expand().into_iter().try_for_each(|cmd| self.send(cmd))
+        }
+    }
+
+    /// Let window managers map the buffer. Only works over a unix socket to a central on this machine.
+    pub async fn register_shared_buffer(&self, buffer: &SharedBuffer) -> Result<(), ClientError> {
+        if !self.capabilities.iter().any(|c| c == capability::SHARED_MEMORY) {
+            return Err(ClientError::Failed(String::from("central can't share memory with this app")));
+        }
+        match self.request(APICommand::RegisterSharedBuffer(RegisterSharedBuffer { app_id: self.app_id, info: buffer.info() })).await? {
+            APICommand::RegisterSharedBufferResponse(resp) if resp.success => Ok(()),
+            APICommand::RegisterSharedBufferResponse(_) => Err(ClientError::Failed(String::from("central couldn't open the shared buffer"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    /// draw the rect of a registered buffer to the same spot in the window. fully transparent pixels are skipped.
+    pub fn draw_shared(&self, window_id: Uuid, buffer_id: Uuid, rect: Rect) -> Result<(), ClientError> {
+        self.send(APICommand::DrawSharedImageCommand(DrawSharedImageCommand {
+            app_id: self.app_id,
+            window_id,
+            buffer_id,
+            rect,
+        }))
+    }
+
+    pub fn unregister_shared_buffer(&self, buffer_id: Uuid) -> Result<(), ClientError> {
+        self.send(APICommand::UnregisterSharedBuffer(UnregisterSharedBuffer { app_id: self.app_id, buffer_id }))
+    }
+
+    pub async fn db_query(&self, query: Vec<DBQueryClause>) -> Result<Vec<JObj>, ClientError> {
+        match self.request(APICommand::DBQueryRequest(DBQueryRequest { app_id: self.app_id, query })).await? {
+            APICommand::DBQueryResponse(resp) if resp.success => Ok(resp.results),
+            APICommand::DBQueryResponse(_) => Err(ClientError::Failed(String::from("database query failed"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub async fn db_add(&self, object: JObj) -> Result<JObj, ClientError> {
+        match self.request(APICommand::DBAddRequest(DBAddRequest { app_id: self.app_id, object })).await? {
+            APICommand::DBAddResponse(resp) if resp.success => Ok(resp.object),
+            APICommand::DBAddResponse(_) => Err(ClientError::Failed(String::from("database add failed"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub async fn db_update(&self, object: JObj) -> Result<JObj, ClientError> {
+        match self.request(APICommand::DBUpdateRequest(DBUpdateRequest { app_id: self.app_id, object })).await? {
+            APICommand::DBUpdateResponse(resp) if resp.success => Ok(resp.object),
+            APICommand::DBUpdateResponse(_) => Err(ClientError::Failed(String::from("database update failed"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub async fn db_delete(&self, object: JObj) -> Result<JObj, ClientError> {
+        match self.request(APICommand::DBDeleteRequest(DBDeleteRequest { app_id: self.app_id, object })).await? {
+            APICommand::DBDeleteResponse(resp) if resp.success => Ok(resp.object),
+            APICommand::DBDeleteResponse(_) => Err(ClientError::Failed(String::from("database delete failed"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub async fn play_track(&self, track: JObj) -> Result<(), ClientError> {
+        match self.request(APICommand::AudioPlayTrackRequest(AudioPlayTrackRequest { app_id: self.app_id, track })).await? {
+            APICommand::AudioPlayTrackResponse(resp) if resp.success => Ok(()),
+            APICommand::AudioPlayTrackResponse(_) => Err(ClientError::Failed(String::from("couldn't play the track"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    pub async fn pause_track(&self, track: JObj) -> Result<(), ClientError> {
+        match self.request(APICommand::AudioPauseTrackRequest(AudioPauseTrackRequest { app_id: self.app_id, track })).await? {
+            APICommand::AudioPauseTrackResponse(resp) if resp.success => Ok(()),
+            APICommand::AudioPauseTrackResponse(_) => Err(ClientError::Failed(String::from("couldn't pause the track"))),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    /// Replace the clipboard. Every app, this one too, gets a ClipboardChanged event.
+    pub fn set_clipboard(&self, items: Vec<ClipboardItem>) -> Result<(), ClientError> {
+        self.send(APICommand::ClipboardSet(ClipboardSet { app_id: self.app_id, items }))
+    }
+
+    pub fn set_clipboard_text(&self, text: &str) -> Result<(), ClientError> {
+        self.set_clipboard(vec![ClipboardItem { mime_type: String::from(clipboard_types::TEXT), data: ClipboardData::Text(text.to_string()) }])
+    }
+
+    /// The clipboard items with these types, in the order asked for. No types gets everything.
+    pub async fn get_clipboard(&self, mime_types: &[&str]) -> Result<Vec<ClipboardItem>, ClientError> {
+        let mime_types = mime_types.iter().map(|mt| mt.to_string()).collect();
+        match self.request(APICommand::ClipboardGet(ClipboardGet { app_id: self.app_id, mime_types })).await? {
+            APICommand::ClipboardGetResponse(resp) => Ok(resp.items),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    /// Switch the keyboard layout, by name: "us", "uk", "de" or any other file in
+    /// resources/keyboard. Every app gets a KeyboardLayoutChanged event once it has switched.
+    pub fn set_keyboard_layout(&self, name: &str) -> Result<(), ClientError> {
+        self.send(APICommand::SetKeyboardLayout(SetKeyboardLayout { app_id: self.app_id, name: name.to_string() }))
+    }
+
+    /// Show a toast. The id comes back in NotificationActionInvoked events when the user clicks
+    /// one of its actions. Only the first MAX_NOTIFICATION_ACTIONS actions are shown.
+    pub async fn post_notification(&self, title: &str, body: &str, icon: Option<GFXBuffer>, timeout_ms: u32, actions: Vec<NotificationAction>) -> Result<Uuid, ClientError> {
+        let cmd = APICommand::PostNotification(PostNotification {
+            app_id: self.app_id,
+            notification_id: Uuid::nil(),
+            title: title.to_string(),
+            body: body.to_string(),
+            icon,
+            timeout_ms,
+            actions,
+        });
+        match self.request(cmd).await? {
+            APICommand::PostNotificationResponse(resp) => Ok(resp.notification_id),
+            cmd => Err(ClientError::UnexpectedReply(Box::new(cmd))),
+        }
+    }
+
+    /// Earlier notifications from every app, oldest first
+    pub async fn notification_history(&self) -> Result<Vec<JObj>, ClientError> {
+        let mut history = self.db_query(vec![DBQueryClause {
+            kind: DBQueryClauseKind::equals,
+            key: String::from("type"),
+            value: String::from(NOTIFICATION_TYPE),
+        }]).await?;
+        history.sort_by_key(|obj| obj.data.get("posted").and_then(|p| p.parse::<u64>().ok()).unwrap_or(0));
+        Ok(history)
+    }
+
+    /// Start dragging from a window. Only works while the mouse is down in it. The window gets
+    /// DragEnded with the returned id once the drag is over.
+    pub fn start_drag(&self, window_id: Uuid, items: Vec<ClipboardItem>) -> Result<Uuid, ClientError> {
+        let drag_id = Uuid::new_v4();
+        self.send(APICommand::StartDrag(StartDrag { app_id: self.app_id, window_id, drag_id, items }))?;
+        Ok(drag_id)
+    }
+
+    /// Answer a Drop event on one of this app's windows. The app the drag came from is told.
+    pub fn respond_to_drop(&self, window_id: Uuid, drag_id: Uuid, accepted: bool) -> Result<(), ClientError> {
+        self.send(APICommand::DropResponse(DropResponse { app_id: self.app_id, window_id, drag_id, accepted }))
+    }
+
+    /// None if there's no text on the clipboard
+    pub async fn get_clipboard_text(&self) -> Result<Option<String>, ClientError> {
+        Ok(self.get_clipboard(&[clipboard_types::TEXT]).await?.into_iter().find_map(|item| match item.data {
+            ClipboardData::Text(text) => Some(text),
+            _ => None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use uuid::Uuid;
+    use db::JObj;
+    use crate::{APICommand, AppDisconnected, ClipboardGet, DBQueryResponse, HelloAppResponse, IncomingMessage};
+    use crate::async_client::{AsyncClient, ClientError, wrap};
+    use crate::events::{MouseButton, MouseDownEvent};
+    use crate::protocol::default_capabilities;
+    use crate::wire::{decode_message, encode_message, WireFormat};
+
+    // just enough of central to answer one query
+    async fn fake_central(mut stream: DuplexStream, rejected: Option<String>) {
+        let mut buf = vec![];
+        let mut chunk = [0u8; 4096];
+        let mut format = WireFormat::Json;
+        loop {
+            while let Some(msg) = decode_message::<IncomingMessage>(&mut buf, format).unwrap() {
+                let reply = match msg.command {
+                    APICommand::AppConnect(hello) => {
+                        let resp = wrap(APICommand::AppConnectResponse(HelloAppResponse {
+                            app_id: Uuid::new_v4(),
+                            wire_format: hello.wire_format,
+                            protocol_version: hello.protocol_version,
+                            capabilities: default_capabilities(),
+                            rejected: rejected.clone(),
+                        }), msg.request_id);
+                        stream.write_all(&encode_message(&resp, WireFormat::Json).unwrap()).await.unwrap();
+                        format = hello.wire_format;
+                        continue;
+                    }
+                    APICommand::DBQueryRequest(req) => {
+                        // an event sneaks in ahead of the reply
+                        let evt = wrap(APICommand::MouseDown(MouseDownEvent {
+                            app_id: req.app_id,
+                            window_id: Default::default(),
+                            original_timestamp: 0,
+                            button: MouseButton::Primary,
+                            x: 1,
+                            y: 2,
+                            clicks: 1,
+                        }), None);
+                        stream.write_all(&encode_message(&evt, format).unwrap()).await.unwrap();
+                        wrap(APICommand::DBQueryResponse(DBQueryResponse {
+                            app_id: req.app_id,
+                            success: true,
+                            results: vec![JObj { id: String::from("obj1"), deleted: false, data: HashMap::new() }],
+                        }), msg.request_id)
+                    }
+                    // hang up
+                    APICommand::AppDisconnected(_) => return,
+                    _ => continue,
+                };
+                stream.write_all(&encode_message(&reply, format).unwrap()).await.unwrap();
+            }
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                return;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn query_and_events() {
+        let (client_side, central_side) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(fake_central(central_side, None));
+        let (reader, writer) = tokio::io::split(client_side);
+        let (client, mut events) = AsyncClient::connect_with(reader, writer, "test-app", HashMap::new()).await.unwrap();
+        assert_eq!(client.wire_format, WireFormat::Binary);
+        let results = client.db_query(vec![]).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "obj1");
+        assert!(matches!(events.next().await, Some(APICommand::MouseDown(_))));
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests() {
+        let (client_side, central_side) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(fake_central(central_side, None));
+        let (reader, writer) = tokio::io::split(client_side);
+        let (mut client, _events) = AsyncClient::connect_with(reader, writer, "test-app", HashMap::new()).await.unwrap();
+        client.request_timeout = Duration::from_millis(50);
+        // the fake central never answers this
+        let res = client.request(APICommand::ClipboardGet(ClipboardGet { app_id: client.app_id, mime_types: vec![] })).await;
+        assert!(matches!(res, Err(ClientError::TimedOut)));
+        assert!(client.pending.lock().unwrap().is_empty());
+        // then it hangs up. nothing waits for a reply which can't come.
+        let res = client.request(APICommand::AppDisconnected(AppDisconnected { app_id: client.app_id })).await;
+        assert!(matches!(res, Err(ClientError::Disconnected)));
+        let res = client.request(APICommand::ClipboardGet(ClipboardGet { app_id: client.app_id, mime_types: vec![] })).await;
+        assert!(matches!(res, Err(ClientError::Disconnected)));
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        let (client_side, central_side) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(fake_central(central_side, Some(String::from("go away"))));
+        let (reader, writer) = tokio::io::split(client_side);
+        let res = AsyncClient::connect_with(reader, writer, "test-app", HashMap::new()).await;
+        assert!(matches!(res, Err(ClientError::Rejected(reason)) if reason == "go away"));
+    }
+}
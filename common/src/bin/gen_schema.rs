@@ -0,0 +1,14 @@
+use std::path::PathBuf;
+use common::schema::{default_schema_dir, write_definitions};
+
+// writes the JSON Schema and TypeScript definitions to common/schema, or to the directory given
+fn main() {
+    let dir = std::env::args().nth(1).map(PathBuf::from).unwrap_or_else(default_schema_dir);
+    match write_definitions(&dir) {
+        Ok(_) => println!("wrote definitions to {}", dir.display()),
+        Err(e) => {
+            eprintln!("couldn't write definitions to {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
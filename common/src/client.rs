@@ -1,48 +1,174 @@
-use std::sync::mpsc;
-use std::net::TcpStream;
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc, Mutex};
 use std::thread;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, RecvError, Sender};
-use serde::Deserialize;
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
 
-use crate::APICommand;
+use crate::{APICommand, APP_MANAGER_PORT, HelloApp, IncomingMessage};
+use crate::protocol::{default_capabilities, PROTOCOL_VERSION};
+use crate::transport::{APP_SOCKET_ENV, ClientStream, connect};
+use crate::wire::{MessageReader, WireFormat, write_message};
+
+// how long to wait for a reply before giving up on it
+pub const REQUEST_TIMEOUT:Duration = Duration::from_secs(10);
+
+// requests waiting for their replies. dropping a waiter's sender fails it.
+pub(crate) struct PendingRequests<S> {
+    waiting:HashMap<u64, (Instant, S)>,
+    closed:bool,
+}
+
+impl<S> PendingRequests<S> {
+    pub(crate) fn new() -> PendingRequests<S> {
+        PendingRequests { waiting: HashMap::new(), closed: false }
+    }
+    // false if the connection is already gone, so no reply can come
+    pub(crate) fn add(&mut self, request_id:u64, reply:S, timeout:Duration) -> bool {
+        if self.closed {
+            return false;
+        }
+        // nobody is going to answer these now
+        self.waiting.retain(|_, (sent, _)| sent.elapsed() < timeout);
+        self.waiting.insert(request_id, (Instant::now(), reply));
+        true
+    }
+    pub(crate) fn take(&mut self, request_id:u64) -> Option<S> {
+        self.waiting.remove(&request_id).map(|(_, reply)| reply)
+    }
+    // the connection is gone. fails everyone still waiting.
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+        self.waiting.clear();
+    }
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+}
+
+type SharedPending = Arc<Mutex<PendingRequests<Sender<APICommand>>>>;
 
 pub struct ClientConnection {
-    stream: TcpStream,
-    tx: Sender<APICommand>,
+    stream: ClientStream,
+    tx: Sender<IncomingMessage>,
+    // unsolicited events: input, resizes, window closes, and replies nobody is waiting for
     pub rx: Receiver<APICommand>,
+    pending: SharedPending,
+    next_request_id: AtomicU64,
+    pub app_id: Uuid,
+    pub wire_format: WireFormat,
+    pub protocol_version: u32,
+    // the capabilities central agreed to. see protocol.rs
+    pub capabilities: Vec<String>,
 }
 
 impl ClientConnection {
     pub fn send(&self, cmd: APICommand) {
         // info!("sending: {:?}",cmd);;
-        self.tx.send(cmd).unwrap();
+        self.tx.send(wrap(cmd, None)).unwrap();
+    }
+    // send a command and get a channel which will receive only the reply to it.
+    // the channel fails if the connection goes, or if the reply takes longer than REQUEST_TIMEOUT.
+    pub fn send_request(&self, cmd: APICommand) -> Receiver<APICommand> {
+        self.start_request(cmd).1
     }
     pub fn send_and_wait(&self, cmd:APICommand) -> Result<APICommand, RecvError> {
-        self.send(cmd);
-        self.rx.recv()
+        let (request_id, reply_rx) = self.start_request(cmd);
+        reply_rx.recv_timeout(REQUEST_TIMEOUT).map_err(|_| {
+            self.pending.lock().unwrap().take(request_id);
+            RecvError
+        })
+    }
+    fn start_request(&self, cmd: APICommand) -> (u64, Receiver<APICommand>) {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = mpsc::channel::<APICommand>();
+        // if the connection is gone the sender is dropped here, and the receiver fails straight away
+        if self.pending.lock().unwrap().add(request_id, reply_tx, REQUEST_TIMEOUT) {
+            self.tx.send(wrap(cmd, Some(request_id))).unwrap();
+        }
+        (request_id, reply_rx)
+    }
+}
+
+fn wrap(cmd:APICommand, request_id:Option<u64>) -> IncomingMessage {
+    IncomingMessage {
+        source: Default::default(),
+        command: cmd,
+        trace: false,
+        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+        request_id,
     }
 }
 
+// name the app after its executable until it tells us something better
+fn default_client_name() -> String {
+    std::env::current_exe().ok()
+        .and_then(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
 impl ClientConnection {
     pub fn init() -> Option<ClientConnection> {
+        ClientConnection::init_with_info(default_client_name(), HashMap::new())
+    }
+    // connect with the name and metadata that debugging tools will show for this app
+    pub fn init_with_info(name:String, metadata:HashMap<String,String>) -> Option<ClientConnection> {
         let (in_tx, in_rx) = mpsc::channel::<APICommand>();
-        let (out_tx, out_rx) = mpsc::channel::<APICommand>();
-        match TcpStream::connect("localhost:3333") {
-            Ok(master_stream) => {
+        let (out_tx, out_rx) = mpsc::channel::<IncomingMessage>();
+        let pending:SharedPending = Arc::new(Mutex::new(PendingRequests::new()));
+        match connect(APP_SOCKET_ENV, APP_MANAGER_PORT) {
+            Ok(mut master_stream) => {
                 println!("connected to the linux-wm");
 
+                // the handshake is always json. ask for the binary format and use whatever central picks.
+                let hello = wrap(APICommand::AppConnect(HelloApp {
+                    wire_format: WireFormat::Binary,
+                    protocol_version: PROTOCOL_VERSION,
+                    client_name: name,
+                    capabilities: default_capabilities(),
+                    metadata,
+                }), None);
+                if let Err(e) = write_message(&mut master_stream, &hello, WireFormat::Json) {
+                    println!("CLIENT: error sending hello {:?}", e);
+                    return None;
+                }
+                let mut reader = MessageReader::new(master_stream.try_clone().unwrap(), WireFormat::Json);
+                let resp = match reader.read::<IncomingMessage>() {
+                    Ok(IncomingMessage { command: APICommand::AppConnectResponse(resp), .. }) => resp,
+                    Ok(msg) => {
+                        println!("CLIENT: expected a connect response but got {:?}", msg);
+                        return None;
+                    }
+                    Err(e) => {
+                        println!("CLIENT: error reading hello response {:?}", e);
+                        return None;
+                    }
+                };
+                if let Some(reason) = resp.rejected {
+                    println!("CLIENT: central rejected the connection: {}", reason);
+                    return None;
+                }
+                let wire_format = resp.wire_format;
+                reader.format = wire_format;
+
                 //receiving thread
                 thread::spawn({
-                    let stream = master_stream.try_clone().unwrap();
+                    let pending = pending.clone();
                     move || {
                         println!("receiving thread starting");
-                        let mut de = serde_json::Deserializer::from_reader(stream);
                         loop {
-                            match APICommand::deserialize(&mut de) {
-                                Ok(cmd) => {
-                                    println!("CLIENT: client received command {:?}", cmd);
-                                    in_tx.send(cmd).unwrap();
+                            match reader.read::<IncomingMessage>() {
+                                Ok(msg) => {
+                                    println!("CLIENT: client received command {:?}", msg.command);
+                                    let waiting = msg.request_id.and_then(|id| pending.lock().unwrap().take(id));
+                                    if let Some(reply_tx) = waiting {
+                                        // the waiter may have given up. that's fine.
+                                        let _ = reply_tx.send(msg.command);
+                                    } else {
+                                        in_tx.send(msg.command).unwrap();
+                                    }
                                 }
                                 Err(e) => {
                                     println!("CLIENT: error deserializing from client {:?}", e);
@@ -50,6 +176,8 @@ impl ClientConnection {
                                 }
                             }
                         }
+                        // dropping the senders wakes up anyone still waiting for a reply
+                        pending.lock().unwrap().close();
                     }
                 });
                 //sending thread
@@ -57,10 +185,8 @@ impl ClientConnection {
                     let mut stream = master_stream.try_clone().unwrap();
                     move || {
                         println!("sending thread starting");
-                        for cmd in out_rx {
-                            let data = serde_json::to_string(&cmd).unwrap();
-                            // println!("sending data {:?}", data);
-                            stream.write_all(data.as_ref()).expect("failed to send rect");
+                        for msg in out_rx {
+                            write_message(&mut stream, &msg, wire_format).expect("failed to send rect");
                         }
                     }
                 });
@@ -68,6 +194,12 @@ impl ClientConnection {
                     stream: master_stream,
                     tx: out_tx,
                     rx: in_rx,
+                    pending,
+                    next_request_id: AtomicU64::new(1),
+                    app_id: resp.app_id,
+                    wire_format,
+                    protocol_version: resp.protocol_version,
+                    capabilities: resp.capabilities,
                 })
             }
             Err(e) => {
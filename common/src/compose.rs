@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use log::warn;
+use uuid::Uuid;
+use crate::generated::KeyCode;
+use crate::keymap::KeyboardLayout;
+
+// where a user can add their own sequences: $XDG_CONFIG_HOME/clogwench/compose.json,
+// or ~/.config/clogwench/compose.json. same format as resources/keyboard/compose.json.
+pub fn user_compose_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("clogwench").join("compose.json"))
+}
+
+// a compose table maps what was typed after a dead key or the compose key to what it makes,
+// eg "´e" -> "é" or "oc" -> "©"
+pub fn load_compose_table(path:&Path) -> Result<HashMap<String, String>, String> {
+    let txt = read_to_string(path).map_err(|e| format!("couldn't read {:?}: {}", path, e))?;
+    serde_json::from_str(&txt).map_err(|e| format!("bad compose table {:?}: {}", path, e))
+}
+
+struct Pending {
+    typed:String,
+    // started by a dead key rather than the compose key
+    dead_key:bool,
+}
+
+// turns dead keys and compose key sequences into the characters they make.
+// feed it everything the keyboard types, in order.
+pub struct Composer {
+    table:HashMap<String, String>,
+    dead_keys:Vec<String>,
+    compose_key:Option<KeyCode>,
+    pending:Option<Pending>,
+    // the window being typed into
+    window:Option<Uuid>,
+}
+
+impl Composer {
+    pub fn new(table:HashMap<String, String>, layout:&KeyboardLayout) -> Composer {
+        Composer {
+            table,
+            dead_keys: layout.dead_keys.clone(),
+            compose_key: layout.compose_key,
+            pending: None,
+            window: None,
+        }
+    }
+    // the system table from the layout dir, plus the user's table, which wins
+    pub fn load(dir:&Path, layout:&KeyboardLayout) -> Composer {
+        let mut table = load_compose_table(&dir.join("compose.json")).unwrap_or_else(|e| {
+            warn!("no compose sequences: {}", e);
+            HashMap::new()
+        });
+        if let Some(path) = user_compose_path().filter(|p| p.exists()) {
+            match load_compose_table(&path) {
+                Ok(user) => table.extend(user),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        Composer::new(table, layout)
+    }
+    pub fn set_layout(&mut self, layout:&KeyboardLayout) {
+        self.dead_keys = layout.dead_keys.clone();
+        self.compose_key = layout.compose_key;
+        self.pending = None;
+    }
+    // the key the layout uses to start a sequence, if it has one
+    pub fn is_compose_key(&self, key:KeyCode) -> bool {
+        self.compose_key == Some(key)
+    }
+    // the compose key was pressed. the next characters make up a sequence.
+    pub fn start(&mut self) {
+        self.pending = Some(Pending { typed: String::new(), dead_key: false });
+    }
+    // the window keys go to. a half typed sequence doesn't carry over to another one.
+    pub fn focus(&mut self, window:Option<Uuid>) {
+        if window != self.window {
+            self.pending = None;
+            self.window = window;
+        }
+    }
+    pub fn is_composing(&self) -> bool {
+        self.pending.is_some()
+    }
+    // the text to send on, if any
+    pub fn input(&mut self, text:&str) -> Option<String> {
+        let mut pending = match self.pending.take() {
+            Some(pending) => pending,
+            None if self.dead_keys.iter().any(|d| d == text) => {
+                self.pending = Some(Pending { typed: text.to_string(), dead_key: true });
+                return None;
+            }
+            None => return Some(text.to_string()),
+        };
+        pending.typed.push_str(text);
+        if let Some(composed) = self.table.get(&pending.typed) {
+            return Some(composed.clone());
+        }
+        if self.table.keys().any(|seq| seq.starts_with(&pending.typed)) {
+            self.pending = Some(pending);
+            return None;
+        }
+        if !pending.dead_key {
+            // not a sequence. drop it, like X does.
+            return None;
+        }
+        // a dead key then space types the accent itself. anything else types both.
+        if text == " " {
+            pending.typed.pop();
+        }
+        Some(pending.typed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::compose::{Composer, load_compose_table};
+    use uuid::Uuid;
+    use crate::generated::KeyCode;
+    use crate::keymap::{KeyboardLayout, load_keyboard_layout};
+
+    fn composer(layout:&str) -> Composer {
+        let dir = Path::new("../resources/keyboard");
+        let table = load_compose_table(&dir.join("compose.json")).unwrap();
+        Composer::new(table, &load_keyboard_layout(dir, layout).unwrap())
+    }
+
+    fn type_all(composer:&mut Composer, keys:&[&str]) -> String {
+        keys.iter().filter_map(|k| composer.input(k)).collect()
+    }
+
+    #[test]
+    fn dead_keys() {
+        let mut de = composer("de");
+        assert_eq!(type_all(&mut de, &["J", "o", "s", "´", "e"]), "José");
+        assert_eq!(type_all(&mut de, &["^", "o"]), "ô");
+        // no accented version
+        assert_eq!(type_all(&mut de, &["´", "x"]), "´x");
+        assert_eq!(type_all(&mut de, &["^", " "]), "^");
+        // the us layout has no dead keys
+        let mut us = composer("us");
+        assert_eq!(type_all(&mut us, &["^", "o"]), "^o");
+    }
+
+    #[test]
+    fn compose_key() {
+        let mut us = composer("us");
+        us.start();
+        assert_eq!(type_all(&mut us, &["\"", "u"]), "ü");
+        assert_eq!(type_all(&mut us, &["M", "ü", "l", "l", "e", "r"]), "Müller");
+        us.start();
+        assert!(us.is_composing());
+        assert_eq!(type_all(&mut us, &["-", "-", "-"]), "—");
+        // a sequence that doesn't exist types nothing
+        us.start();
+        assert_eq!(type_all(&mut us, &["q", "x"]), "x");
+        assert!(!us.is_composing());
+    }
+
+    #[test]
+    fn compose_key_comes_from_the_layout() {
+        let mut us = composer("us");
+        assert!(!us.is_compose_key(KeyCode::CONTEXT_MENU));
+        let mut layout = KeyboardLayout::us();
+        layout.compose_key = Some(KeyCode::CONTEXT_MENU);
+        us.set_layout(&layout);
+        assert!(us.is_compose_key(KeyCode::CONTEXT_MENU));
+        us.focus(Some(Uuid::new_v4()));
+        us.start();
+        assert_eq!(type_all(&mut us, &["\""]), "");
+        us.focus(Some(Uuid::new_v4()));
+        assert!(!us.is_composing());
+        assert_eq!(type_all(&mut us, &["u"]), "u");
+    }
+
+    #[test]
+    fn user_sequences() {
+        let mut table = load_compose_table(Path::new("../resources/keyboard/compose.json")).unwrap();
+        table.insert(String::from("<3"), String::from("♥"));
+        let mut composer = Composer::new(table, &KeyboardLayout::us());
+        composer.start();
+        assert_eq!(type_all(&mut composer, &["<", "3"]), "♥");
+    }
+}
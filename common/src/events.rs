@@ -1,14 +1,18 @@
 use std::time::{SystemTime, UNIX_EPOCH};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::generated::KeyCode;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ModifierState {
     pub shift:bool,
     pub ctrl:bool,
     pub alt:bool,
     pub meta:bool,
+    // right alt on layouts which use it to pick a third character
+    #[serde(default)]
+    pub altgr:bool,
 }
 
 impl ModifierState {
@@ -18,19 +22,23 @@ impl ModifierState {
             ctrl:false,
             alt:false,
             meta:false,
+            altgr:false,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct KeyDownEvent {
     pub app_id:Uuid,
     pub window_id:Uuid,
     pub key:KeyCode,
     pub mods:ModifierState,
+    // the key was held down long enough to auto-repeat
+    #[serde(default)]
+    pub repeat:bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct KeyUpEvent {
     pub app_id:Uuid,
     pub window_id:Uuid,
@@ -38,15 +46,29 @@ pub struct KeyUpEvent {
     pub mods:ModifierState,
 }
 
+// the characters a key down typed, from the keymap. comes after the KeyDown.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct TextInputEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub text:String,
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
 pub enum MouseButton {
     Primary,
-    Secondary
+    Secondary,
+    Middle,
+    // the thumb buttons, usually back and forward in browsers
+    Back,
+    Forward,
 }
 
+fn one_click() -> u32 { 1 }
+
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct MouseDownEvent {
     pub app_id:Uuid,
     pub window_id:Uuid,
@@ -54,6 +76,9 @@ pub struct MouseDownEvent {
     pub button:MouseButton,
     pub x:i32,
     pub y:i32,
+    // 2 for a double click, 3 for a triple click. counted by the window manager.
+    #[serde(default="one_click")]
+    pub clicks:u32,
 }
 
 impl MouseDownEvent {
@@ -65,11 +90,12 @@ impl MouseDownEvent {
             button: MouseButton::Primary,
             x,
             y,
+            clicks: 1,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct MouseMoveEvent {
     pub app_id:Uuid,
     pub window_id:Uuid,
@@ -79,7 +105,7 @@ pub struct MouseMoveEvent {
     pub y:i32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct MouseUpEvent {
     pub app_id:Uuid,
     pub window_id:Uuid,
@@ -89,3 +115,15 @@ pub struct MouseUpEvent {
     pub y:i32,
 }
 
+// a scroll wheel or trackpad scroll, in lines. positive dy scrolls down, positive dx right.
+// x and y are where the pointer is.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct MouseWheelEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub original_timestamp:u128,
+    pub x:i32,
+    pub y:i32,
+    pub dx:i32,
+    pub dy:i32,
+}
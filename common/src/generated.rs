@@ -1,7 +1,10 @@
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+// the names are part of the wire format
+#[allow(non_camel_case_types)]
 pub enum KeyCode {
     RESERVED,
     UNKNOWN,
@@ -91,6 +94,59 @@ pub enum KeyCode {
         MINUS,
         EQUALS,
         SPACE,
+    // the extra key next to left shift on iso keyboards
+    INTL_BACKSLASH,
+
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+
+    HOME,
+    END,
+    PAGE_UP,
+    PAGE_DOWN,
+    INSERT,
+    CAPS_LOCK,
+    NUM_LOCK,
+    SCROLL_LOCK,
+    PRINT_SCREEN,
+    PAUSE,
+    CONTEXT_MENU,
+
+    NUMPAD_0,
+    NUMPAD_1,
+    NUMPAD_2,
+    NUMPAD_3,
+    NUMPAD_4,
+    NUMPAD_5,
+    NUMPAD_6,
+    NUMPAD_7,
+    NUMPAD_8,
+    NUMPAD_9,
+    NUMPAD_DIVIDE,
+    NUMPAD_MULTIPLY,
+    NUMPAD_SUBTRACT,
+    NUMPAD_ADD,
+    NUMPAD_DECIMAL,
+    NUMPAD_EQUALS,
+    NUMPAD_ENTER,
+
+    MEDIA_PLAY_PAUSE,
+    MEDIA_STOP,
+    MEDIA_NEXT,
+    MEDIA_PREVIOUS,
+    VOLUME_UP,
+    VOLUME_DOWN,
+    VOLUME_MUTE,
 
     MOUSE_PRIMARY,
 }
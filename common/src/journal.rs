@@ -0,0 +1,193 @@
+//! Recordings of everything central sends and receives, as binary frames like the ones on the
+//! wire, so pixel payloads stay raw bytes. Central writes them with `--record <file>` and the
+//! replay tool plays them back.
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::sync::OnceLock;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Instant;
+use log::error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::IncomingMessage;
+use crate::wire::{encode_message, MessageReader, WireFormat};
+
+/// which way a message went, from central's point of view
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PeerKind {
+    App,
+    WindowManager,
+    Debugger,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    // microseconds since the first entry
+    pub offset_usec:u64,
+    pub direction:Direction,
+    pub peer:PeerKind,
+    // the id central gave the connection. for apps and window managers this is also the app_id or wm_id.
+    pub connection:Uuid,
+    pub message:IncomingMessage,
+}
+
+// serializes exactly like JournalEntry without copying the message
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    offset_usec:u64,
+    direction:Direction,
+    peer:PeerKind,
+    connection:Uuid,
+    message:&'a IncomingMessage,
+}
+
+/// A recording in progress. Safe to share between the router and the client writer threads.
+/// Entries are encoded by the thread recording them and written out by a thread of its own,
+/// so recording never waits on the disk.
+pub struct Journal {
+    start:OnceLock<Instant>,
+    out:Option<Sender<Vec<u8>>>,
+    writer:Option<JoinHandle<()>>,
+}
+
+impl Journal {
+    pub fn create(path:&Path) -> io::Result<Journal> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (out, rx) = channel::<Vec<u8>>();
+        let writer = thread::spawn(move || {
+            while let Ok(data) = rx.recv() {
+                let mut res = file.write_all(&data);
+                // flush whenever there is nothing waiting so a crash still leaves a usable recording
+                loop {
+                    match rx.try_recv() {
+                        Ok(data) => res = res.and_then(|_| file.write_all(&data)),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => break,
+                    }
+                }
+                if let Err(e) = res.and_then(|_| file.flush()) {
+                    error!("error writing to the journal {}",e);
+                }
+            }
+        });
+        Ok(Journal {
+            start: OnceLock::new(),
+            out: Some(out),
+            writer: Some(writer),
+        })
+    }
+    pub fn record(&self, direction:Direction, peer:PeerKind, connection:Uuid, message:&IncomingMessage) {
+        let start = self.start.get_or_init(Instant::now);
+        let entry = EntryRef {
+            offset_usec: start.elapsed().as_micros() as u64,
+            direction,
+            peer,
+            connection,
+            message,
+        };
+        match encode_message(&entry, WireFormat::Binary) {
+            Ok(data) => {
+                if let Some(out) = &self.out {
+                    let _ = out.send(data);
+                }
+            }
+            Err(e) => error!("error writing to the journal {}",e),
+        }
+    }
+}
+
+impl Drop for Journal {
+    // write out whatever is still queued
+    fn drop(&mut self) {
+        self.out.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+pub fn read_journal(path:&Path) -> io::Result<Vec<JournalEntry>> {
+    let mut reader = MessageReader::new(BufReader::new(File::open(path)?), WireFormat::Binary);
+    let mut entries:Vec<JournalEntry> = vec![];
+    loop {
+        match reader.read() {
+            Ok(entry) => entries.push(entry),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    // threads record at the same time, so the file can be a little out of order
+    entries.sort_by_key(|e| e.offset_usec);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use gfx::graphics::{GFXBuffer, PixelLayout, Rect};
+    use crate::{APICommand, DrawImageCommand, IncomingMessage};
+    use crate::journal::{Direction, Journal, PeerKind, read_journal};
+
+    #[test]
+    fn write_and_read_back() {
+        let path = std::env::temp_dir().join(format!("journal-test-{}.rec", Uuid::new_v4()));
+        let journal = Journal::create(&path).unwrap();
+        let app = Uuid::new_v4();
+        let msg = IncomingMessage {
+            source: app,
+            command: APICommand::SystemShutdown,
+            trace: false,
+            timestamp_usec: 0,
+            request_id: Some(3),
+        };
+        journal.record(Direction::Inbound, PeerKind::App, app, &msg);
+        journal.record(Direction::Outbound, PeerKind::WindowManager, Uuid::new_v4(), &msg);
+        drop(journal);
+        let entries = read_journal(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].connection, app);
+        assert_eq!(entries[0].message.request_id, Some(3));
+        assert_eq!(entries[1].direction, Direction::Outbound);
+        assert!(entries[0].offset_usec <= entries[1].offset_usec);
+        assert_eq!(entries[0].offset_usec, 0);
+    }
+
+    #[test]
+    fn pixels_are_stored_as_bytes() {
+        let path = std::env::temp_dir().join(format!("journal-test-{}.rec", Uuid::new_v4()));
+        let journal = Journal::create(&path).unwrap();
+        let app = Uuid::new_v4();
+        let msg = IncomingMessage {
+            source: app,
+            command: APICommand::DrawImageCommand(DrawImageCommand {
+                app_id: app,
+                window_id: Default::default(),
+                rect: Rect::from_ints(0, 0, 64, 64),
+                buffer: GFXBuffer::new(64, 64, &PixelLayout::ARGB()),
+                encoding: Default::default(),
+            }),
+            trace: false,
+            timestamp_usec: 0,
+            request_id: None,
+        };
+        journal.record(Direction::Inbound, PeerKind::App, app, &msg);
+        drop(journal);
+        let size = std::fs::metadata(&path).unwrap().len();
+        let entries = read_journal(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // one byte per channel and not much else
+        assert!(size < 64 * 64 * 4 + 256, "recorded {} bytes", size);
+        assert!(matches!(&entries[0].message.command, APICommand::DrawImageCommand(di) if di.buffer.width == 64));
+    }
+}
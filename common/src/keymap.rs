@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use serde::Deserialize;
+use crate::events::ModifierState;
+use crate::generated::KeyCode;
+
+// where the window managers look for layouts, relative to where they run
+pub const KEYBOARD_LAYOUT_DIR:&str = "./resources/keyboard";
+
+// what a key does at one level: the code the app sees and the text it types.
+// empty text types nothing.
+type LayoutKey = (KeyCode, String);
+
+// a keyboard layout, from a json file in resources/keyboard. keys are named by the
+// code the platform gives for that position on a US keyboard. each key has up to four
+// levels: plain, shift, altgr, altgr+shift. a layout can start from a base layout and
+// only list the keys which are different. dead keys are the accents which don't type
+// anything themselves, but change the next letter (see compose.rs). the compose key starts
+// a compose sequence instead of going to the app. there is none unless the layout names one.
+#[derive(Deserialize, Debug, Clone)]
+pub struct KeyboardLayout {
+    pub name:String,
+    pub title:String,
+    #[serde(default)]
+    base:Option<String>,
+    #[serde(default)]
+    pub dead_keys:Vec<String>,
+    #[serde(default)]
+    pub compose_key:Option<KeyCode>,
+    keys:HashMap<KeyCode, Vec<Option<LayoutKey>>>,
+}
+
+impl KeyboardLayout {
+    pub fn from_json(txt:&str) -> Result<KeyboardLayout, String> {
+        serde_json::from_str(txt).map_err(|e| e.to_string())
+    }
+    // the layout every platform starts with
+    pub fn us() -> KeyboardLayout {
+        KeyboardLayout::from_json(include_str!("../../resources/keyboard/us.json")).unwrap()
+    }
+    // what pressing the key at this position does, with these modifiers held.
+    // keys the layout doesn't know keep their code and type nothing.
+    pub fn translate(&self, key:KeyCode, mods:&ModifierState) -> (KeyCode, Option<String>) {
+        let levels = match self.keys.get(&key) {
+            Some(levels) => levels,
+            None => return (key, None),
+        };
+        let level = |n:usize| levels.get(n).and_then(|l| l.as_ref());
+        let shift = if mods.shift { 1 } else { 0 };
+        // no altgr level falls back to the plain ones, and no shift level to the unshifted one
+        let found = (if mods.altgr { level(2 + shift) } else { None })
+            .or_else(|| level(shift))
+            .or_else(|| level(0));
+        match found {
+            Some((code, text)) => {
+                // keys held with ctrl or meta are shortcuts, not text
+                let text = if text.is_empty() || mods.ctrl || mods.meta { None } else { Some(text.clone()) };
+                (*code, text)
+            }
+            None => (key, None),
+        }
+    }
+}
+
+// the code each physical key went down as. it comes back up as the same code, whatever
+// modifiers are held by then, so apps always see a KeyUp that matches the KeyDown.
+#[derive(Debug, Default)]
+pub struct PressedKeys {
+    codes:HashMap<KeyCode, KeyCode>,
+}
+
+impl PressedKeys {
+    pub fn new() -> PressedKeys {
+        PressedKeys::default()
+    }
+    // the code to send for a key going down. repeats keep the code of the first press.
+    pub fn press(&mut self, key:KeyCode, code:KeyCode) -> KeyCode {
+        *self.codes.entry(key).or_insert(code)
+    }
+    // the code to send for a key coming up, if it was seen going down
+    pub fn release(&mut self, key:KeyCode) -> Option<KeyCode> {
+        self.codes.remove(&key)
+    }
+}
+
+// load a layout by name, along with the layouts it is based on
+pub fn load_keyboard_layout(dir:&Path, name:&str) -> Result<KeyboardLayout, String> {
+    load_based_on(dir, name, &mut vec![])
+}
+
+fn load_based_on(dir:&Path, name:&str, loading:&mut Vec<String>) -> Result<KeyboardLayout, String> {
+    if loading.iter().any(|n| n == name) {
+        return Err(format!("keyboard layout {} is based on itself", name));
+    }
+    loading.push(name.to_string());
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("bad keyboard layout name {:?}", name));
+    }
+    let path = dir.join(format!("{}.json", name));
+    let txt = read_to_string(&path).map_err(|e| format!("couldn't read {:?}: {}", path, e))?;
+    let mut layout = KeyboardLayout::from_json(&txt)?;
+    if let Some(base) = layout.base.take() {
+        let base = load_based_on(dir, &base, loading)?;
+        let mut keys = base.keys;
+        keys.extend(layout.keys.drain());
+        layout.keys = keys;
+        layout.dead_keys.extend(base.dead_keys);
+        layout.compose_key = layout.compose_key.or(base.compose_key);
+    }
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::events::ModifierState;
+    use crate::generated::KeyCode;
+    use crate::keymap::{KeyboardLayout, load_keyboard_layout, PressedKeys};
+
+    #[test]
+    fn shifted_text() {
+        let us = KeyboardLayout::us();
+        let mut mods = ModifierState::empty();
+        assert_eq!(us.translate(KeyCode::LETTER_A, &mods), (KeyCode::LETTER_A, Some(String::from("a"))));
+        assert_eq!(us.translate(KeyCode::DIGIT_2, &mods).1.as_deref(), Some("2"));
+        assert_eq!(us.translate(KeyCode::ARROW_LEFT, &mods), (KeyCode::ARROW_LEFT, None));
+        mods.shift = true;
+        assert_eq!(us.translate(KeyCode::LETTER_A, &mods).1.as_deref(), Some("A"));
+        assert_eq!(us.translate(KeyCode::DIGIT_2, &mods), (KeyCode::AT, Some(String::from("@"))));
+        assert_eq!(us.translate(KeyCode::SLASH, &mods), (KeyCode::QUESTION, Some(String::from("?"))));
+        assert_eq!(us.translate(KeyCode::SPACE, &mods).1.as_deref(), Some(" "));
+        // ctrl-c is a shortcut
+        mods.shift = false;
+        mods.ctrl = true;
+        assert_eq!(us.translate(KeyCode::LETTER_C, &mods), (KeyCode::LETTER_C, None));
+    }
+
+    #[test]
+    fn released_as_pressed() {
+        let us = KeyboardLayout::us();
+        let mut pressed = PressedKeys::new();
+        let mut mods = ModifierState::empty();
+        mods.shift = true;
+        let (code, _) = us.translate(KeyCode::DIGIT_2, &mods);
+        assert_eq!(pressed.press(KeyCode::DIGIT_2, code), KeyCode::AT);
+        // shift comes up before the key does
+        mods.shift = false;
+        let (code, _) = us.translate(KeyCode::DIGIT_2, &mods);
+        assert_eq!(pressed.press(KeyCode::DIGIT_2, code), KeyCode::AT);
+        assert_eq!(pressed.release(KeyCode::DIGIT_2), Some(KeyCode::AT));
+        assert_eq!(pressed.release(KeyCode::DIGIT_2), None);
+    }
+
+    #[test]
+    fn uk_and_german() {
+        let dir = Path::new("../resources/keyboard");
+        let mut mods = ModifierState::empty();
+        let uk = load_keyboard_layout(dir, "uk").unwrap();
+        let de = load_keyboard_layout(dir, "de").unwrap();
+        // the keys a layout doesn't list come from its base
+        assert_eq!(uk.translate(KeyCode::LETTER_Q, &mods).1.as_deref(), Some("q"));
+        assert_eq!(de.translate(KeyCode::LETTER_Y, &mods), (KeyCode::LETTER_Z, Some(String::from("z"))));
+        assert_eq!(de.translate(KeyCode::SEMICOLON, &mods).1.as_deref(), Some("ö"));
+        mods.shift = true;
+        assert_eq!(uk.translate(KeyCode::DIGIT_3, &mods).1.as_deref(), Some("£"));
+        assert_eq!(uk.translate(KeyCode::DIGIT_2, &mods), (KeyCode::DOUBLE_QUOTE, Some(String::from("\""))));
+        assert_eq!(de.translate(KeyCode::DIGIT_7, &mods), (KeyCode::SLASH, Some(String::from("/"))));
+        mods.shift = false;
+        mods.altgr = true;
+        assert_eq!(de.translate(KeyCode::LETTER_Q, &mods), (KeyCode::AT, Some(String::from("@"))));
+        // no altgr level types the plain character
+        assert_eq!(de.translate(KeyCode::LETTER_A, &mods).1.as_deref(), Some("a"));
+
+        assert!(load_keyboard_layout(dir, "../us").is_err());
+        assert!(load_keyboard_layout(dir, "nope").is_err());
+    }
+}
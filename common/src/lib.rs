@@ -1,39 +1,90 @@
+use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::time::{SystemTime, UNIX_EPOCH};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use db::JObj;
-use gfx::graphics::{ARGBColor, GFXBuffer, Rect, Size};
-use crate::events::{KeyDownEvent, KeyUpEvent, MouseDownEvent};
+use gfx::encoding::{decode, encode, ImageEncoding};
+use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Rect, Size};
+use crate::events::{KeyDownEvent, KeyUpEvent, MouseDownEvent, TextInputEvent};
+use crate::wire::WireFormat;
 
 
+pub mod app_window;
+pub mod async_client;
+pub mod compose;
 pub mod client;
 pub mod events;
 pub mod generated;
+pub mod journal;
+pub mod keymap;
+pub mod protocol;
+pub mod schema;
+pub mod shm;
+pub mod transport;
+pub mod wire;
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct HelloApp {
-
+    // the format the app wants to use after the handshake. old clients leave it out.
+    #[serde(default)]
+    pub wire_format:WireFormat,
+    // see protocol.rs. old clients leave these out and are treated as version 0.
+    #[serde(default)]
+    pub protocol_version:u32,
+    #[serde(default)]
+    pub client_name:String,
+    #[serde(default)]
+    pub capabilities:Vec<String>,
+    // free form details about the app, like its version or author. shown in debugging tools.
+    #[serde(default)]
+    pub metadata:HashMap<String,String>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct HelloAppResponse {
     pub app_id:Uuid,
+    #[serde(default)]
+    pub wire_format:WireFormat,
+    #[serde(default)]
+    pub protocol_version:u32,
+    // the subset of the requested capabilities central agreed to
+    #[serde(default)]
+    pub capabilities:Vec<String>,
+    // set when central refused the connection. it hangs up right after sending this.
+    #[serde(default)]
+    pub rejected:Option<String>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AppDisconnected {
     pub app_id:Uuid,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct HelloWindowManager {
-
+    #[serde(default)]
+    pub wire_format:WireFormat,
+    #[serde(default)]
+    pub protocol_version:u32,
+    #[serde(default)]
+    pub client_name:String,
+    #[serde(default)]
+    pub capabilities:Vec<String>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct HelloWindowManagerResponse {
     pub wm_id:Uuid,
+    #[serde(default)]
+    pub wire_format:WireFormat,
+    #[serde(default)]
+    pub protocol_version:u32,
+    #[serde(default)]
+    pub capabilities:Vec<String>,
+    #[serde(default)]
+    pub rejected:Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DrawRectCommand {
     pub app_id:Uuid,
     pub window_id:Uuid,
@@ -41,43 +92,208 @@ pub struct DrawRectCommand {
     pub color:ARGBColor,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DrawImageCommand {
     pub app_id:Uuid,
     pub window_id:Uuid,
     pub rect:Rect,
     pub buffer:GFXBuffer,
+    // when this isn't Raw the buffer's data holds the compressed pixels. its size and layout
+    // are those of the image once it is decoded.
+    #[serde(default)]
+    pub encoding:ImageEncoding,
+}
+
+impl DrawImageCommand {
+    // compress the image. only peers with the matching image capability can read the result.
+    pub fn encode(&mut self, encoding:ImageEncoding) -> Result<(),String> {
+        if self.encoding != ImageEncoding::Raw || encoding == ImageEncoding::Raw {
+            return Ok(());
+        }
+        self.buffer.data = encode(&self.buffer, encoding)?;
+        self.encoding = encoding;
+        Ok(())
+    }
+    // turn a compressed image back into plain pixels
+    pub fn decode(&mut self) -> Result<(),String> {
+        if self.encoding == ImageEncoding::Raw {
+            return Ok(());
+        }
+        let mut buffer = decode(&self.buffer.data, self.buffer.width, self.buffer.height, &self.buffer.layout, self.encoding)?;
+        buffer.id = self.buffer.id;
+        self.buffer = buffer;
+        self.encoding = ImageEncoding::Raw;
+        Ok(())
+    }
+}
+
+// one drawing operation in a batch. coordinates are relative to the window.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub enum DrawPrimitive {
+    FillRect { rect:Rect, color:ARGBColor },
+    // like DrawImageCommand: the image is tiled over the rect and fully transparent pixels are skipped
+    Image { rect:Rect, buffer:GFXBuffer },
+}
+
+// many draw operations for one window, applied in order and all at once.
+// only sent to window managers with the draw-batch capability.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DrawBatchCommand {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub primitives:Vec<DrawPrimitive>,
+}
+
+impl DrawBatchCommand {
+    pub fn apply_to(&self, buf:&mut GFXBuffer) {
+        for prim in &self.primitives {
+            match prim {
+                DrawPrimitive::FillRect { rect, color } => buf.fill_rect(rect, color),
+                DrawPrimitive::Image { rect, buffer } => buf.fill_rect_with_image(rect, buffer),
+            }
+        }
+    }
+    // the same drawing as separate commands, for peers which don't understand batches
+    pub fn expand(self) -> Vec<APICommand> {
+        let (app_id, window_id) = (self.app_id, self.window_id);
+        self.primitives.into_iter().map(|prim| match prim {
+            DrawPrimitive::FillRect { rect, color } => APICommand::DrawRectCommand(DrawRectCommand { app_id, window_id, rect, color }),
+            DrawPrimitive::Image { rect, buffer } => APICommand::DrawImageCommand(DrawImageCommand { app_id, window_id, rect, buffer, encoding: ImageEncoding::Raw }),
+        }).collect()
+    }
+}
+
+// how to find a shared buffer: the memfd of the app process which made it. see shm.rs.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SharedBufferInfo {
+    pub buffer_id:Uuid,
+    pub pid:u32,
+    pub fd:i32,
+    pub width:u32,
+    pub height:u32,
+    pub layout:PixelLayout,
+}
+
+// only accepted from apps with the shared-memory capability
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct RegisterSharedBuffer {
+    pub app_id:Uuid,
+    pub info:SharedBufferInfo,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct RegisterSharedBufferResponse {
+    pub app_id:Uuid,
+    pub buffer_id:Uuid,
+    pub success:bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct UnregisterSharedBuffer {
+    pub app_id:Uuid,
+    pub buffer_id:Uuid,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// draw the rect of a shared buffer to the same spot in the window. the buffer is placed
+// at the window's origin. like DrawImageCommand, fully transparent pixels are skipped.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DrawSharedImageCommand {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub buffer_id:Uuid,
+    pub rect:Rect,
+}
+
+impl DrawSharedImageCommand {
+    // the same drawing as an ordinary image, for window managers which can't map the buffer
+    pub fn to_image(&self, buf:&shm::SharedBuffer) -> DrawImageCommand {
+        DrawImageCommand {
+            app_id: self.app_id,
+            window_id: self.window_id,
+            rect: self.rect,
+            buffer: buf.to_gfx(self.rect),
+            encoding: ImageEncoding::Raw,
+        }
+    }
+}
+
+// the window_type of an OpenWindowCommand. window managers treat anything else as plain.
+pub mod window_types {
+    pub const PLAIN:&str = "plain";
+    // menus and dropdowns. no title bar, and it closes when the user clicks anywhere else.
+    pub const POPUP:&str = "popup";
+    // a dialog. its parent gets no input until it closes.
+    pub const MODAL:&str = "modal";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct OpenWindowCommand {
     pub window_type:String,
     pub window_title:String,
+    // relative to the parent's contents if there is a parent
     pub bounds:Rect,
+    // another window of the same app. it closes along with its parent.
+    #[serde(default)]
+    pub parent:Option<Uuid>,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct OpenWindowResponse {
     pub app_id:Uuid,
     pub window_id:Uuid,
     pub window_type:String,
     pub window_title:String,
     pub bounds:Rect,
+    #[serde(default)]
+    pub parent:Option<Uuid>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct CloseWindowResponse {
     pub app_id:Uuid,
     pub window_id:Uuid,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// window operations an app asks for. central checks the app owns the window, then passes them on
+// to the window managers.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SetWindowTitle {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub title:String,
+}
+// like OpenWindowCommand, the position of the window and the size of its contents. the window
+// manager sends WindowResized once the window really has the new size.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SetWindowBounds {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub bounds:Rect,
+}
+// the app gets a CloseWindowResponse, the same as when the user closes the window
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct CloseWindowRequest {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct RaiseWindow {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+}
+// hidden windows keep their contents and can still be drawn to
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SetWindowVisible {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub visible:bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct WindowResized {
     pub app_id:Uuid,
     pub window_id:Uuid,
     pub size:Size,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub enum DBQueryClauseKind {
     equals,
     equalsi,
@@ -85,86 +301,262 @@ pub enum DBQueryClauseKind {
     substringi,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBQueryClause {
     pub kind:DBQueryClauseKind,
     pub key:String,
     pub value:String,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBQueryRequest {
     pub app_id:Uuid,
     pub query:Vec<DBQueryClause>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBQueryResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub results: Vec<JObj>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBAddRequest {
     pub app_id:Uuid,
     pub object:JObj,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBAddResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub object:JObj,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBUpdateRequest {
     pub app_id:Uuid,
     pub object:JObj,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBUpdateResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub object:JObj,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBDeleteRequest {
     pub app_id:Uuid,
     pub object:JObj,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct DBDeleteResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub object:JObj,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AudioPlayTrackRequest {
     pub app_id:Uuid,
     pub track:JObj,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AudioPlayTrackResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub track:JObj,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AudioPauseTrackRequest {
     pub app_id:Uuid,
     pub track:JObj,
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct AudioPauseTrackResponse {
     pub app_id:Uuid,
     pub success:bool,
     pub track:JObj,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// the mime_type of a ClipboardItem
+pub mod clipboard_types {
+    pub const TEXT:&str = "text/plain";
+    // pixels as a GFXBuffer
+    pub const IMAGE:&str = "image/x-gfxbuffer";
+    // the id of a JObj in the database
+    pub const DB_OBJECT:&str = "application/x-jobj-id";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub enum ClipboardData {
+    Text(String),
+    Image(GFXBuffer),
+    ObjectId(String),
+}
+// one way of representing what was copied. an app can put several on the clipboard, like an
+// object reference along with its text, and the app that pastes picks the one it understands.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardItem {
+    pub mime_type:String,
+    pub data:ClipboardData,
+}
+// replaces everything on the clipboard
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardSet {
+    pub app_id:Uuid,
+    pub items:Vec<ClipboardItem>,
+}
+// the items with these types, in the order asked for. no types means everything.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardGet {
+    pub app_id:Uuid,
+    pub mime_types:Vec<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardGetResponse {
+    pub app_id:Uuid,
+    pub items:Vec<ClipboardItem>,
+}
+// sent to every app when something new is copied. get the data with ClipboardGet.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardChanged {
+    pub app_id:Uuid,
+    // the app which set it
+    pub owner:Uuid,
+    pub mime_types:Vec<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, JsonSchema)]
+pub enum ClipboardAction {
+    Copy,
+    Cut,
+    Paste,
+}
+// the window manager saw the copy, cut or paste shortcut. it goes to the focused window instead
+// of the key press.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ClipboardShortcut {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub action:ClipboardAction,
+}
+
+// drag and drop. the app starts a drag while the mouse is down in one of its windows. the window
+// manager follows the mouse and tells whichever window is under it. the payload is the same as a
+// clipboard's, so a drop can offer an object reference along with its text.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct StartDrag {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    // made up by the app, so it can tell its drags apart
+    pub drag_id:Uuid,
+    pub items:Vec<ClipboardItem>,
+}
+// the positions in these are relative to the target window, like mouse events
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DragEnterEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+    pub mime_types:Vec<String>,
+    pub x:i32,
+    pub y:i32,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DragOverEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+    pub x:i32,
+    pub y:i32,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DragLeaveEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+}
+// the target answers with a DropResponse
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DropEvent {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+    pub items:Vec<ClipboardItem>,
+    pub x:i32,
+    pub y:i32,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DropResponse {
+    pub app_id:Uuid,
+    // the window the drop was on
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+    pub accepted:bool,
+}
+// tells the app which started the drag how it ended. not accepted if it was dropped on nothing,
+// or the mouse was already up when the drag started.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct DragEnded {
+    pub app_id:Uuid,
+    pub window_id:Uuid,
+    pub drag_id:Uuid,
+    pub accepted:bool,
+}
+
+// notifications. central keeps each one in the database as an object of this type, so apps can
+// show the history with an ordinary query.
+pub const NOTIFICATION_TYPE:&str = "notification";
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct NotificationAction {
+    pub id:String,
+    pub label:String,
+}
+// as many action buttons as fit across a toast. central drops any after these.
+pub const MAX_NOTIFICATION_ACTIONS:usize = 3;
+// the window managers show it as a toast. central fills in the id and replies with it.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct PostNotification {
+    pub app_id:Uuid,
+    pub notification_id:Uuid,
+    pub title:String,
+    pub body:String,
+    #[serde(default)]
+    pub icon:Option<GFXBuffer>,
+    // 0 keeps it up until the user clicks it
+    pub timeout_ms:u32,
+    pub actions:Vec<NotificationAction>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct PostNotificationResponse {
+    pub app_id:Uuid,
+    pub notification_id:Uuid,
+}
+// the user clicked one of the actions on a toast
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct NotificationActionInvoked {
+    pub app_id:Uuid,
+    pub notification_id:Uuid,
+    pub action_id:String,
+}
+
+// switch every window manager to the named layout from resources/keyboard
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SetKeyboardLayout {
+    pub app_id:Uuid,
+    pub name:String,
+}
+
+// a window manager switched layouts. sent to every app.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct KeyboardLayoutChanged {
+    pub name:String,
+    pub title:String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub enum APICommand {
     AppConnect(HelloApp),
     AppConnectResponse(HelloAppResponse),
@@ -178,15 +570,27 @@ pub enum APICommand {
     OpenWindowResponse(OpenWindowResponse),
     CloseWindowResponse(CloseWindowResponse),
     WindowResized(WindowResized),
+    SetWindowTitle(SetWindowTitle),
+    SetWindowBounds(SetWindowBounds),
+    CloseWindowRequest(CloseWindowRequest),
+    RaiseWindow(RaiseWindow),
+    SetWindowVisible(SetWindowVisible),
 
     DrawRectCommand(DrawRectCommand),
     DrawImageCommand(DrawImageCommand),
+    DrawBatchCommand(DrawBatchCommand),
+    DrawSharedImageCommand(DrawSharedImageCommand),
+    RegisterSharedBuffer(RegisterSharedBuffer),
+    RegisterSharedBufferResponse(RegisterSharedBufferResponse),
+    UnregisterSharedBuffer(UnregisterSharedBuffer),
 
     KeyDown(KeyDownEvent),
     KeyUp(KeyUpEvent),
+    TextInput(TextInputEvent),
     MouseDown(crate::events::MouseDownEvent),
     MouseMove(crate::events::MouseMoveEvent),
     MouseUp(crate::events::MouseUpEvent),
+    MouseWheel(crate::events::MouseWheelEvent),
 
     DBQueryRequest(DBQueryRequest),
     DBQueryResponse(DBQueryResponse),
@@ -202,15 +606,63 @@ pub enum APICommand {
     AudioPauseTrackRequest(AudioPauseTrackRequest),
     AudioPauseTrackResponse(AudioPauseTrackResponse),
 
+    ClipboardSet(ClipboardSet),
+    ClipboardGet(ClipboardGet),
+    ClipboardGetResponse(ClipboardGetResponse),
+    ClipboardChanged(ClipboardChanged),
+    ClipboardShortcut(ClipboardShortcut),
+
+    StartDrag(StartDrag),
+    DragEnter(DragEnterEvent),
+    DragOver(DragOverEvent),
+    DragLeave(DragLeaveEvent),
+    Drop(DropEvent),
+    DropResponse(DropResponse),
+    DragEnded(DragEnded),
+
+    PostNotification(PostNotification),
+    PostNotificationResponse(PostNotificationResponse),
+    NotificationActionInvoked(NotificationActionInvoked),
+
+    SetKeyboardLayout(SetKeyboardLayout),
+    KeyboardLayoutChanged(KeyboardLayoutChanged),
+
     SystemShutdown,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl APICommand {
+    // the window an event from central is aimed at, for routing it to that window's handler
+    pub fn event_window_id(&self) -> Option<Uuid> {
+        match self {
+            APICommand::CloseWindowResponse(evt) => Some(evt.window_id),
+            APICommand::WindowResized(evt) => Some(evt.window_id),
+            APICommand::KeyDown(evt) => Some(evt.window_id),
+            APICommand::KeyUp(evt) => Some(evt.window_id),
+            APICommand::TextInput(evt) => Some(evt.window_id),
+            APICommand::MouseDown(evt) => Some(evt.window_id),
+            APICommand::MouseMove(evt) => Some(evt.window_id),
+            APICommand::MouseUp(evt) => Some(evt.window_id),
+            APICommand::MouseWheel(evt) => Some(evt.window_id),
+            APICommand::ClipboardShortcut(evt) => Some(evt.window_id),
+            APICommand::DragEnter(evt) => Some(evt.window_id),
+            APICommand::DragOver(evt) => Some(evt.window_id),
+            APICommand::DragLeave(evt) => Some(evt.window_id),
+            APICommand::Drop(evt) => Some(evt.window_id),
+            APICommand::DragEnded(evt) => Some(evt.window_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct IncomingMessage {
     pub source:Uuid,
     pub command:APICommand,
     pub trace:bool,
     pub timestamp_usec:u128,
+    // set by the sender of a request. central copies it into the matching response.
+    #[serde(default)]
+    pub request_id:Option<u64>,
 }
 
 
@@ -220,7 +672,22 @@ pub const APP_MANAGER_PORT:i32 = 3333;
 
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// which app a debug event is about. central fills in the name from the app's hello.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct DebugAppInfo {
+    pub app_id:Uuid,
+    pub app_name:String,
+    pub metadata:HashMap<String,String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct DebugWindowInfo {
+    pub app_id:Uuid,
+    pub app_name:String,
+    pub window_id:Uuid,
+    pub window_title:String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub enum DebugMessage {
     HelloDebugger,
     HelloDebuggerResponse,
@@ -228,12 +695,15 @@ pub enum DebugMessage {
     ServerStopped,
     WindowManagerConnected,
     WindowManagerDisconnected,
-    AppConnected(String),
-    AppDisconnected(String),
-    WindowOpened(String),
-    WindowClosed(String),
+    // central sends this to itself when a debugger hangs up
+    DebuggerDisconnected,
+    AppConnected(DebugAppInfo),
+    AppDisconnected(DebugAppInfo),
+    WindowOpened(DebugWindowInfo),
+    WindowClosed(DebugWindowInfo),
     BackgroundReceivedMouseEvent,
-    WindowFocusChanged(String),
+    // window managers only know the ids and title. central fills in the app name.
+    WindowFocusChanged(DebugWindowInfo),
     RequestServerShutdown,
     AppLog(String),
     FakeMouseEvent(MouseDownEvent),
@@ -249,3 +719,24 @@ fn test_rect_intersect() {
     println!("r3 {}",r3);
     assert_eq!(r3,Rect::from_ints(0,0,250,250));
 }
+
+#[test]
+fn test_draw_batch_in_order() {
+    let red = ARGBColor::new_rgb(255,0,0);
+    let blue = ARGBColor::new_rgb(0,0,255);
+    let batch = DrawBatchCommand {
+        app_id: Default::default(),
+        window_id: Default::default(),
+        primitives: vec![
+            DrawPrimitive::FillRect { rect: Rect::from_ints(0,0,10,10), color: red.clone() },
+            DrawPrimitive::FillRect { rect: Rect::from_ints(5,5,10,10), color: blue.clone() },
+        ],
+    };
+    let mut buf = GFXBuffer::new(20,20,&PixelLayout::ARGB());
+    batch.apply_to(&mut buf);
+    assert_eq!(buf.get_pixel_vec_argb(2,2), red.as_layout(&buf.layout));
+    assert_eq!(buf.get_pixel_vec_argb(6,6), blue.as_layout(&buf.layout));
+    let cmds = batch.expand();
+    assert_eq!(cmds.len(), 2);
+    assert!(matches!(&cmds[1], APICommand::DrawRectCommand(cmd) if cmd.rect == Rect::from_ints(5,5,10,10)));
+}
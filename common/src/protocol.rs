@@ -0,0 +1,130 @@
+use gfx::encoding::ImageEncoding;
+
+/// The protocol revision spoken by this version of common.
+/// Bump it whenever APICommand changes in a way older peers can't understand.
+pub const PROTOCOL_VERSION:u32 = 1;
+/// The oldest revision central still accepts. Apps written before the handshake
+/// carried a version leave the field out, which reads as 0.
+pub const MIN_PROTOCOL_VERSION:u32 = 0;
+
+/// Optional features a peer can ask for in its hello message.
+/// Unknown names are ignored so newer peers can still talk to an older central.
+pub mod capability {
+    /// replies carry the request_id of the request that caused them
+    pub const REQUEST_IDS:&str = "request-ids";
+    /// window managers can apply DrawBatchCommand. others get the batch as separate draw commands.
+    pub const DRAW_BATCH:&str = "draw-batch";
+    /// apps can register memfd buffers and draw from them. window managers can map them.
+    /// central only grants it over the unix sockets, since the peer has to be on the same machine.
+    pub const SHARED_MEMORY:&str = "shared-memory";
+    /// DrawImageCommand can carry its image compressed with one of these
+    pub const IMAGE_PNG:&str = "image-png";
+    pub const IMAGE_QOI:&str = "image-qoi";
+    pub const IMAGE_RLE:&str = "image-rle";
+}
+
+/// everything this version of central knows how to do
+pub const SUPPORTED_CAPABILITIES:&[&str] = &[
+    capability::REQUEST_IDS,
+    capability::DRAW_BATCH,
+    capability::SHARED_MEMORY,
+    capability::IMAGE_PNG,
+    capability::IMAGE_QOI,
+    capability::IMAGE_RLE,
+];
+
+/// the result of a successful handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub protocol_version:u32,
+    pub capabilities:Vec<String>,
+}
+
+/// Work out what a peer and central will use, or why they can't talk at all.
+pub fn negotiate(protocol_version:u32, requested:&[String]) -> Result<Negotiated, String> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        return Err(format!("protocol version {} is not supported. central speaks versions {} to {}",
+                           protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION));
+    }
+    let capabilities = requested.iter()
+        .filter(|cap| SUPPORTED_CAPABILITIES.contains(&cap.as_str()))
+        .cloned()
+        .collect();
+    Ok(Negotiated {
+        protocol_version,
+        capabilities,
+    })
+}
+
+/// the capability a peer needs before it can be sent images in this encoding
+pub fn image_capability(encoding:ImageEncoding) -> Option<&'static str> {
+    match encoding {
+        ImageEncoding::Raw => None,
+        ImageEncoding::Png => Some(capability::IMAGE_PNG),
+        ImageEncoding::Qoi => Some(capability::IMAGE_QOI),
+        ImageEncoding::Rle => Some(capability::IMAGE_RLE),
+    }
+}
+
+/// True if images in this encoding can be sent to a peer with these capabilities
+pub fn accepts_image_encoding(capabilities:&[String], encoding:ImageEncoding) -> bool {
+    match image_capability(encoding) {
+        None => true,
+        Some(cap) => capabilities.iter().any(|c| c == cap),
+    }
+}
+
+/// The best encoding for sending images to a peer with these capabilities. qoi is nearly as
+/// small as png for screen contents and much cheaper to encode.
+pub fn preferred_image_encoding(capabilities:&[String]) -> ImageEncoding {
+    [ImageEncoding::Qoi, ImageEncoding::Png, ImageEncoding::Rle].into_iter()
+        .find(|enc| accepts_image_encoding(capabilities, *enc))
+        .unwrap_or(ImageEncoding::Raw)
+}
+
+/// the capability list a client built from this crate should ask for
+pub fn default_capabilities() -> Vec<String> {
+    SUPPORTED_CAPABILITIES.iter().map(|cap| cap.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx::encoding::ImageEncoding;
+    use crate::protocol::{accepts_image_encoding, capability, negotiate, preferred_image_encoding, PROTOCOL_VERSION};
+    use crate::{APICommand, HelloApp, IncomingMessage};
+
+    #[test]
+    fn drops_unknown_capabilities() {
+        let requested = vec![capability::REQUEST_IDS.to_string(), "teleportation".to_string()];
+        let neg = negotiate(PROTOCOL_VERSION, &requested).unwrap();
+        assert_eq!(neg.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(neg.capabilities, vec![capability::REQUEST_IDS.to_string()]);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let err = negotiate(PROTOCOL_VERSION + 1, &[]).unwrap_err();
+        assert!(err.contains("not supported"));
+    }
+
+    #[test]
+    fn picks_an_image_encoding() {
+        let caps = vec![capability::IMAGE_RLE.to_string(), capability::IMAGE_PNG.to_string()];
+        assert_eq!(preferred_image_encoding(&caps), ImageEncoding::Png);
+        assert_eq!(preferred_image_encoding(&[]), ImageEncoding::Raw);
+        assert!(!accepts_image_encoding(&caps, ImageEncoding::Qoi));
+        assert!(accepts_image_encoding(&[], ImageEncoding::Raw));
+    }
+
+    #[test]
+    fn old_hello_still_parses() {
+        // what the node apps sent before the handshake had any fields
+        let msg: IncomingMessage = serde_json::from_str(r#"{"source":"00000000-0000-0000-0000-000000000000","command":{"AppConnect":{}},"trace":false,"timestamp_usec":0}"#).unwrap();
+        if let APICommand::AppConnect(HelloApp { protocol_version, capabilities, .. }) = msg.command {
+            assert_eq!(protocol_version, 0);
+            assert!(negotiate(protocol_version, &capabilities).unwrap().capabilities.is_empty());
+        } else {
+            panic!("expected an AppConnect");
+        }
+    }
+}
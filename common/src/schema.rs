@@ -0,0 +1,190 @@
+//! JSON Schema and TypeScript definitions for the json wire format, so apps written in
+//! other languages don't have to declare every message by hand.
+//!
+//! The checked in copies live in common/schema. Regenerate them with
+//! `cargo run -p common --bin gen_schema` after changing any message type.
+use std::io;
+use std::path::{Path, PathBuf};
+use schemars::gen::SchemaSettings;
+use serde_json::{Map, Value};
+use crate::IncomingMessage;
+
+pub const SCHEMA_FILE:&str = "clogwench.schema.json";
+pub const TYPESCRIPT_FILE:&str = "clogwench.d.ts";
+
+/// where the checked in definitions live
+pub fn default_schema_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("schema")
+}
+
+/// The schema for one IncomingMessage. Every type it can carry, including APICommand,
+/// DebugMessage, KeyCode and the gfx types, is in its definitions.
+pub fn json_schema() -> Value {
+    let gen = SchemaSettings::draft07().into_generator();
+    serde_json::to_value(gen.into_root_schema_for::<IncomingMessage>()).unwrap()
+}
+
+pub fn json_schema_text() -> String {
+    let mut text = serde_json::to_string_pretty(&json_schema()).unwrap();
+    text.push('\n');
+    text
+}
+
+/// TypeScript declarations for every type in the schema
+pub fn typescript() -> String {
+    let schema = json_schema();
+    let mut out = String::new();
+    out.push_str("// generated by `cargo run -p common --bin gen_schema`. do not edit.\n");
+    out.push_str("// u128 and u64 fields are plain numbers. timestamps stay well under 2^53.\n");
+    if let Some(Value::Object(defs)) = schema.get("definitions") {
+        for (name, def) in defs {
+            out.push('\n');
+            write_declaration(&mut out, name, def);
+        }
+    }
+    out.push('\n');
+    write_declaration(&mut out, "IncomingMessage", &schema);
+    out
+}
+
+pub fn write_definitions(dir:&Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(SCHEMA_FILE), json_schema_text())?;
+    std::fs::write(dir.join(TYPESCRIPT_FILE), typescript())?;
+    Ok(())
+}
+
+fn write_doc(out:&mut String, schema:&Value, indent:&str) {
+    if let Some(Value::String(desc)) = schema.get("description") {
+        out.push_str(&format!("{}/** {} */\n", indent, desc));
+    }
+}
+
+fn write_declaration(out:&mut String, name:&str, schema:&Value) {
+    write_doc(out, schema, "");
+    if let Some(Value::Object(props)) = schema.get("properties") {
+        out.push_str(&format!("export interface {} {{\n", name));
+        write_properties(out, schema, props, "    ");
+        out.push_str("}\n");
+        return;
+    }
+    let variants = union_members(schema);
+    if variants.len() > 1 {
+        out.push_str(&format!("export type {} =\n", name));
+        for variant in variants {
+            out.push_str(&format!("    | {}\n", variant));
+        }
+        out.truncate(out.len() - 1);
+        out.push_str(";\n");
+    } else {
+        out.push_str(&format!("export type {} = {};\n", name, ts_type(schema)));
+    }
+}
+
+fn write_properties(out:&mut String, schema:&Value, props:&Map<String, Value>, indent:&str) {
+    let required:Vec<&str> = match schema.get("required") {
+        Some(Value::Array(names)) => names.iter().filter_map(|n| n.as_str()).collect(),
+        _ => vec![],
+    };
+    for (prop, prop_schema) in props {
+        write_doc(out, prop_schema, indent);
+        let optional = if required.contains(&prop.as_str()) { "" } else { "?" };
+        out.push_str(&format!("{}{}{}: {};\n", indent, prop, optional, ts_type(prop_schema)));
+    }
+}
+
+// the alternatives of a oneOf, anyOf or string enum, each as a type
+fn union_members(schema:&Value) -> Vec<String> {
+    if let Some(Value::Array(options)) = schema.get("oneOf").or_else(|| schema.get("anyOf")) {
+        return options.iter().flat_map(union_members).collect();
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return values.iter().map(|v| v.to_string()).collect();
+    }
+    vec![ts_type(schema)]
+}
+
+fn ts_type(schema:&Value) -> String {
+    if let Some(Value::String(path)) = schema.get("$ref") {
+        return path.rsplit('/').next().unwrap_or("unknown").to_string();
+    }
+    if let Some(Value::Array(parts)) = schema.get("allOf") {
+        return parts.iter().map(ts_type).collect::<Vec<_>>().join(" & ");
+    }
+    if schema.get("oneOf").is_some() || schema.get("anyOf").is_some() || schema.get("enum").is_some() {
+        return union_members(schema).join(" | ");
+    }
+    match schema.get("type") {
+        Some(Value::String(t)) => simple_type(t, schema),
+        Some(Value::Array(types)) => types.iter()
+            .filter_map(|t| t.as_str())
+            .map(|t| simple_type(t, schema))
+            .collect::<Vec<_>>()
+            .join(" | "),
+        _ => String::from("unknown"),
+    }
+}
+
+fn simple_type(t:&str, schema:&Value) -> String {
+    match t {
+        "integer" | "number" => String::from("number"),
+        "string" => String::from("string"),
+        "boolean" => String::from("boolean"),
+        "null" => String::from("null"),
+        "array" => match schema.get("items") {
+            // tuples, including the empty ones serde uses for unit variants like PixelLayout::ARGB()
+            Some(Value::Array(items)) => format!("[{}]", items.iter().map(ts_type).collect::<Vec<_>>().join(", ")),
+            Some(item) => {
+                let item = ts_type(item);
+                if item.contains(' ') { format!("({})[]", item) } else { format!("{}[]", item) }
+            }
+            None => String::from("unknown[]"),
+        },
+        "object" => {
+            if let Some(Value::Object(props)) = schema.get("properties") {
+                let mut inline = String::new();
+                write_properties(&mut inline, schema, props, "");
+                // one line, without the doc comments
+                let fields:Vec<&str> = inline.lines()
+                    .filter(|line| !line.starts_with("/**"))
+                    .map(|line| line.trim_end_matches(';'))
+                    .collect();
+                return format!("{{ {} }}", fields.join("; "));
+            }
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => String::from("{}"),
+                Some(value @ Value::Object(_)) => format!("{{ [key: string]: {} }}", ts_type(value)),
+                _ => String::from("{ [key: string]: unknown }"),
+            }
+        }
+        _ => String::from("unknown"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{default_schema_dir, json_schema, json_schema_text, SCHEMA_FILE, typescript, TYPESCRIPT_FILE};
+
+    #[test]
+    fn covers_the_wire_types() {
+        let schema = json_schema();
+        let defs = schema.get("definitions").unwrap();
+        for name in ["APICommand", "DebugMessage", "KeyCode", "Rect", "ARGBColor", "GFXBuffer"] {
+            assert!(defs.get(name).is_some(), "{} is missing from the schema", name);
+        }
+        let ts = typescript();
+        assert!(ts.contains("export interface IncomingMessage {"));
+        assert!(ts.contains("    | { DrawRectCommand: DrawRectCommand }"));
+        // serde(default) fields are optional
+        assert!(ts.contains("    request_id?: number | null;"));
+    }
+
+    #[test]
+    fn checked_in_definitions_are_current() {
+        let dir = default_schema_dir();
+        for (file, expected) in [(SCHEMA_FILE, json_schema_text()), (TYPESCRIPT_FILE, typescript())] {
+            let current = std::fs::read_to_string(dir.join(file)).unwrap_or_default();
+            assert!(current == expected, "common/schema/{} is stale. run `cargo run -p common --bin gen_schema`", file);
+        }
+    }
+}
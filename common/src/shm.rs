@@ -0,0 +1,248 @@
+//! Images shared through memory instead of sent down the socket.
+//!
+//! An app creates a SharedBuffer, registers it with central once, then draws into it and sends
+//! DrawSharedImageCommand with just the changed rect. Window managers on the same machine map the
+//! same memory and draw straight out of it. Only works over the Unix sockets on Linux.
+use std::fs::File;
+use std::io;
+use uuid::Uuid;
+use gfx::graphics::{GFXBuffer, PixelLayout, Point, Rect};
+use crate::SharedBufferInfo;
+
+pub struct SharedBuffer {
+    pub id:Uuid,
+    pub width:u32,
+    pub height:u32,
+    pub layout:PixelLayout,
+    // keeps the memfd open so other processes can reach it through /proc
+    file:File,
+    ptr:*mut u8,
+    len:usize,
+    writable:bool,
+}
+
+// the mapping is plain memory owned by this struct
+unsafe impl Send for SharedBuffer {}
+
+impl SharedBuffer {
+    /// Make a new buffer in a memfd. Only the creator can write to it.
+    pub fn create(width:u32, height:u32, layout:&PixelLayout) -> io::Result<SharedBuffer> {
+        let len = byte_length(width, height, layout);
+        let file = sys::create(len)?;
+        let ptr = sys::map(&file, len, true)?;
+        Ok(SharedBuffer { id: Uuid::new_v4(), width, height, layout: layout.clone(), file, ptr, len, writable: true })
+    }
+
+    /// Map a buffer another process registered. The mapping is read only.
+    pub fn open(info:&SharedBufferInfo) -> io::Result<SharedBuffer> {
+        let len = byte_length(info.width, info.height, &info.layout);
+        let file = File::open(format!("/proc/{}/fd/{}", info.pid, info.fd))?;
+        // a buffer which could shrink under us would crash us when we read it
+        sys::check_sealed(&file, len)?;
+        let ptr = sys::map(&file, len, false)?;
+        Ok(SharedBuffer { id: info.buffer_id, width: info.width, height: info.height, layout: info.layout.clone(), file, ptr, len, writable: false })
+    }
+
+    /// what central and the window managers need to open this buffer
+    pub fn info(&self) -> SharedBufferInfo {
+        SharedBufferInfo {
+            buffer_id: self.id,
+            pid: std::process::id(),
+            fd: sys::raw_fd(&self.file),
+            width: self.width,
+            height: self.height,
+            layout: self.layout.clone(),
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::from_ints(0, 0, self.width as i32, self.height as i32)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// None if this process only opened the buffer
+    pub fn data_mut(&mut self) -> Option<&mut [u8]> {
+        if self.writable {
+            Some(unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) })
+        } else {
+            None
+        }
+    }
+
+    /// Copy part of a local image into the buffer at the same position. Returns false if the buffer is read only.
+    pub fn copy_from(&mut self, src:&GFXBuffer, rect:Rect) -> bool {
+        if !self.writable {
+            return false;
+        }
+        let rect = rect.intersect(self.bounds()).intersect(src.bounds());
+        if rect.is_empty() {
+            return true;
+        }
+        let converted;
+        let src = if src.layout == self.layout {
+            src
+        } else {
+            converted = src.to_layout(&self.layout);
+            &converted
+        };
+        let bpp = self.layout.bytes_per_pixel() as usize;
+        let width = self.width as usize;
+        let dst = unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) };
+        copy_rows(&src.data, src.width as usize, rect, dst, width, Point::init(rect.x, rect.y), bpp);
+        true
+    }
+
+    /// Copy part of the buffer into an image, like GFXBuffer::draw_image without any blending.
+    pub fn copy_rect_to(&self, rect:Rect, dst:&mut GFXBuffer, dst_pos:&Point) {
+        if dst.layout != self.layout {
+            let img = self.to_gfx(rect).to_layout(&dst.layout);
+            dst.draw_image(dst_pos, &img.bounds(), &img);
+            return;
+        }
+        let rect = rect.intersect(self.bounds());
+        // clip the destination too
+        let target = Rect::from_ints(dst_pos.x, dst_pos.y, rect.w, rect.h).intersect(dst.bounds());
+        if rect.is_empty() || target.is_empty() {
+            return;
+        }
+        let src_rect = Rect::from_ints(rect.x + target.x - dst_pos.x, rect.y + target.y - dst_pos.y, target.w, target.h);
+        let bpp = self.layout.bytes_per_pixel() as usize;
+        let dst_width = dst.width as usize;
+        copy_rows(self.data(), self.width as usize, src_rect, &mut dst.data, dst_width, Point::init(target.x, target.y), bpp);
+    }
+
+    /// part of the buffer as an ordinary image, for peers which can't map it
+    pub fn to_gfx(&self, rect:Rect) -> GFXBuffer {
+        let rect = rect.intersect(self.bounds());
+        let mut img = GFXBuffer::new(rect.w.max(1) as u32, rect.h.max(1) as u32, &self.layout);
+        if !rect.is_empty() {
+            let bpp = self.layout.bytes_per_pixel() as usize;
+            copy_rows(self.data(), self.width as usize, rect, &mut img.data, rect.w as usize, Point::init(0, 0), bpp);
+        }
+        img
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        sys::unmap(self.ptr, self.len);
+    }
+}
+
+fn byte_length(width:u32, height:u32, layout:&PixelLayout) -> usize {
+    width as usize * height as usize * layout.bytes_per_pixel() as usize
+}
+
+// copy a rect of pixels between two images with the same layout. the rect must fit in both.
+fn copy_rows(src:&[u8], src_width:usize, rect:Rect, dst:&mut [u8], dst_width:usize, dst_pos:Point, bpp:usize) {
+    let row_len = rect.w as usize * bpp;
+    for row in 0..rect.h as usize {
+        let src_start = ((rect.y as usize + row) * src_width + rect.x as usize) * bpp;
+        let dst_start = ((dst_pos.y as usize + row) * dst_width + dst_pos.x as usize) * bpp;
+        dst[dst_start..dst_start + row_len].copy_from_slice(&src[src_start..src_start + row_len]);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    pub fn create(len:usize) -> io::Result<File> {
+        let fd = unsafe { libc::memfd_create(c"clogwench-image".as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(len as u64)?;
+        // promise the size won't change, so the other side can map it safely
+        if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+
+    pub fn check_sealed(file:&File, len:usize) -> io::Result<()> {
+        let seals = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if seals & libc::F_SEAL_SHRINK == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "shared buffer isn't sealed against shrinking"));
+        }
+        if (file.metadata()?.len() as usize) < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "shared buffer is smaller than its size says"));
+        }
+        Ok(())
+    }
+
+    pub fn map(file:&File, len:usize, writable:bool) -> io::Result<*mut u8> {
+        let prot = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len.max(1), prot, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    pub fn unmap(ptr:*mut u8, len:usize) {
+        unsafe { libc::munmap(ptr as *mut libc::c_void, len.max(1)); }
+    }
+
+    pub fn raw_fd(file:&File) -> i32 {
+        file.as_raw_fd()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "shared buffers need linux")
+    }
+    pub fn create(_len:usize) -> io::Result<File> { Err(unsupported()) }
+    pub fn check_sealed(_file:&File, _len:usize) -> io::Result<()> { Err(unsupported()) }
+    pub fn map(_file:&File, _len:usize, _writable:bool) -> io::Result<*mut u8> { Err(unsupported()) }
+    pub fn unmap(_ptr:*mut u8, _len:usize) {}
+    pub fn raw_fd(_file:&File) -> i32 { -1 }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect, WHITE};
+    use crate::shm::SharedBuffer;
+
+    #[test]
+    fn share_and_copy_back() {
+        let red = ARGBColor::new_rgb(255,0,0);
+        let mut local = GFXBuffer::new(32, 32, &PixelLayout::ARGB());
+        local.clear(&WHITE);
+        local.fill_rect(&Rect::from_ints(8, 8, 4, 4), &red);
+        let mut shared = SharedBuffer::create(32, 32, &PixelLayout::ARGB()).unwrap();
+        assert!(shared.copy_from(&local, local.bounds()));
+
+        // what a window manager does with the registration
+        let mut opened = SharedBuffer::open(&shared.info()).unwrap();
+        assert!(opened.data_mut().is_none());
+        assert!(!opened.copy_from(&local, local.bounds()));
+        let mut window = GFXBuffer::new(40, 40, &PixelLayout::ARGB());
+        opened.copy_rect_to(Rect::from_ints(8, 8, 8, 8), &mut window, &Point::init(8, 8));
+        assert_eq!(window.get_pixel_vec_argb(9, 9), red.as_layout(&window.layout));
+        assert_eq!(window.get_pixel_vec_argb(14, 14), WHITE.as_layout(&window.layout));
+        // outside the damage nothing was copied
+        assert_eq!(window.get_pixel_vec_argb(2, 2), vec![0, 0, 0, 0]);
+
+        // writes by the app show up in the other mapping without registering again
+        shared.copy_from(&GFXBuffer::new(32, 32, &PixelLayout::ARGB()), Rect::from_ints(8, 8, 1, 1));
+        let img = opened.to_gfx(Rect::from_ints(8, 8, 2, 2));
+        assert_eq!((img.width, img.height), (2, 2));
+        assert_eq!(img.get_pixel_vec_argb(0, 0), vec![0, 0, 0, 0]);
+        assert_eq!(img.get_pixel_vec_argb(1, 1), red.as_layout(&img.layout));
+    }
+}
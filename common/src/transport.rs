@@ -0,0 +1,101 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Clients set these to the path of one of central's Unix sockets to use it instead of tcp.
+pub const APP_SOCKET_ENV:&str = "CLOGWENCH_APP_SOCKET";
+pub const WM_SOCKET_ENV:&str = "CLOGWENCH_WM_SOCKET";
+
+/// A connection between central and one of its clients, over tcp or a Unix socket.
+#[derive(Debug)]
+pub enum ClientStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    pub fn try_clone(&self) -> io::Result<ClientStream> {
+        match self {
+            ClientStream::Tcp(s) => s.try_clone().map(ClientStream::Tcp),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.try_clone().map(ClientStream::Unix),
+        }
+    }
+    pub fn shutdown(&self, how:Shutdown) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.shutdown(how),
+        }
+    }
+    pub fn set_nonblocking(&self, nonblocking:bool) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+    pub fn set_write_timeout(&self, timeout:Option<Duration>) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+    pub fn set_read_timeout(&self, timeout:Option<Duration>) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            ClientStream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Connect to central. Uses the Unix socket named by the environment variable if it is set,
+/// otherwise tcp on localhost.
+pub fn connect(socket_env:&str, port:i32) -> io::Result<ClientStream> {
+    match std::env::var_os(socket_env) {
+        #[cfg(unix)]
+        Some(path) => UnixStream::connect(path).map(ClientStream::Unix),
+        #[cfg(not(unix))]
+        Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets aren't supported on this platform")),
+        None => TcpStream::connect(format!("localhost:{}", port)).map(ClientStream::Tcp),
+    }
+}
+
+/// where central puts its sockets by default: $XDG_RUNTIME_DIR/clogwench
+pub fn default_socket_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("clogwench"))
+}
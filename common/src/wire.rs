@@ -0,0 +1,226 @@
+use std::io;
+use std::io::{Read, Write};
+use serde::de::DeserializeOwned;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How messages are encoded on a connection after the handshake.
+/// The AppConnect / WMConnect handshake itself is always plain json.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default, JsonSchema)]
+pub enum WireFormat {
+    /// back to back serde_json values. This is what the node apps speak.
+    #[default]
+    Json,
+    /// a u32 big endian length header followed by a bincode payload.
+    Binary,
+}
+
+/// refuse frames bigger than this so a corrupt header can't make us allocate gigabytes
+pub const MAX_FRAME_LENGTH:u32 = 64*1024*1024;
+
+fn invalid_data<E>(e:E) -> io::Error where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// encode a message into the exact bytes that should be written to the stream
+pub fn encode_message<T:Serialize>(msg:&T, format:WireFormat) -> io::Result<Vec<u8>> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(msg).map_err(invalid_data),
+        WireFormat::Binary => {
+            let payload = bincode::serialize(msg).map_err(invalid_data)?;
+            if payload.len() > MAX_FRAME_LENGTH as usize {
+                return Err(invalid_data(format!("frame of {} bytes is too large", payload.len())));
+            }
+            let mut data = Vec::with_capacity(4 + payload.len());
+            data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            data.extend_from_slice(&payload);
+            Ok(data)
+        }
+    }
+}
+
+pub fn write_message<W:Write, T:Serialize>(stream:&mut W, msg:&T, format:WireFormat) -> io::Result<()> {
+    let data = encode_message(msg, format)?;
+    stream.write_all(&data)
+}
+
+/// Pull one complete message off the front of a buffer of received bytes.
+/// Returns None if the buffer doesn't hold a whole message yet. For readers
+/// which can't block, like the async client.
+pub fn decode_message<T:DeserializeOwned>(buf:&mut Vec<u8>, format:WireFormat) -> io::Result<Option<T>> {
+    match format {
+        WireFormat::Json => {
+            let mut stream = serde_json::Deserializer::from_slice(buf).into_iter::<T>();
+            match stream.next() {
+                Some(Ok(msg)) => {
+                    let used = stream.byte_offset();
+                    buf.drain(..used);
+                    Ok(Some(msg))
+                }
+                Some(Err(e)) if e.is_eof() => Ok(None),
+                Some(Err(e)) => Err(invalid_data(e)),
+                None => Ok(None),
+            }
+        }
+        WireFormat::Binary => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            if len > MAX_FRAME_LENGTH {
+                return Err(invalid_data(format!("frame of {} bytes is too large", len)));
+            }
+            let end = 4 + len as usize;
+            if buf.len() < end {
+                return Ok(None);
+            }
+            let msg = bincode::deserialize(&buf[4..end]).map_err(invalid_data)?;
+            buf.drain(..end);
+            Ok(Some(msg))
+        }
+    }
+}
+
+/// reads messages off of a stream. The format can be switched once the handshake is done.
+pub struct MessageReader<R:Read> {
+    reader:R,
+    pub format:WireFormat,
+}
+
+impl<R:Read> MessageReader<R> {
+    pub fn new(reader:R, format:WireFormat) -> MessageReader<R> {
+        MessageReader {
+            reader,
+            format,
+        }
+    }
+    pub fn read<T:DeserializeOwned>(&mut self) -> io::Result<T> {
+        match self.format {
+            WireFormat::Json => {
+                // serde_json doesn't read past the closing brace of an object,
+                // so a fresh deserializer per message never swallows the next one
+                let mut de = serde_json::Deserializer::from_reader(&mut self.reader);
+                T::deserialize(&mut de).map_err(io::Error::from)
+            }
+            WireFormat::Binary => {
+                let mut header = [0u8; 4];
+                self.reader.read_exact(&mut header)?;
+                let len = u32::from_be_bytes(header);
+                if len > MAX_FRAME_LENGTH {
+                    return Err(invalid_data(format!("frame of {} bytes is too large", len)));
+                }
+                let mut payload = vec![0u8; len as usize];
+                self.reader.read_exact(&mut payload)?;
+                bincode::deserialize(&payload).map_err(invalid_data)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use gfx::encoding::ImageEncoding;
+    use gfx::graphics::{GFXBuffer, PixelLayout, Rect};
+    use crate::{APICommand, DrawImageCommand, IncomingMessage};
+    use crate::wire::{decode_message, encode_message, MAX_FRAME_LENGTH, MessageReader, WireFormat};
+
+    fn make_image_message() -> IncomingMessage {
+        make_sized_image_message(64)
+    }
+    fn make_sized_image_message(size:u32) -> IncomingMessage {
+        IncomingMessage {
+            source: Default::default(),
+            command: APICommand::DrawImageCommand(DrawImageCommand {
+                app_id: Default::default(),
+                window_id: Default::default(),
+                rect: Rect::from_ints(0, 0, size as i32, size as i32),
+                buffer: GFXBuffer::new(size, size, &PixelLayout::ARGB()),
+                encoding: Default::default(),
+            }),
+            trace: false,
+            timestamp_usec: 42,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn compressed_images_survive_both_formats() {
+        for format in [WireFormat::Json, WireFormat::Binary] {
+            let mut msg = make_sized_image_message(256);
+            let original = match &msg.command {
+                APICommand::DrawImageCommand(cmd) => cmd.buffer.data.clone(),
+                _ => unreachable!(),
+            };
+            if let APICommand::DrawImageCommand(cmd) = &mut msg.command {
+                cmd.encode(ImageEncoding::Qoi).unwrap();
+            }
+            let raw_len = encode_message(&make_sized_image_message(256), format).unwrap().len();
+            let data = encode_message(&msg, format).unwrap();
+            assert!(data.len() < raw_len / 100, "{:?} sent {} bytes", format, data.len());
+            match decode_message(&mut data.clone(), format).unwrap().map(|m: IncomingMessage| m.command) {
+                Some(APICommand::DrawImageCommand(mut cmd)) => {
+                    assert_eq!(cmd.encoding, ImageEncoding::Qoi);
+                    cmd.decode().unwrap();
+                    assert_eq!(cmd.buffer.data, original);
+                }
+                other => panic!("expected an image, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_both_formats() {
+        for format in [WireFormat::Json, WireFormat::Binary] {
+            let mut data = encode_message(&make_image_message(), format).unwrap();
+            data.extend(encode_message(&make_image_message(), format).unwrap());
+            let mut reader = MessageReader::new(Cursor::new(data), format);
+            for _ in 0..2 {
+                let msg: IncomingMessage = reader.read().unwrap();
+                assert_eq!(msg.timestamp_usec, 42);
+                assert!(matches!(msg.command, APICommand::DrawImageCommand(_)));
+            }
+            assert!(reader.read::<IncomingMessage>().is_err());
+        }
+    }
+
+    #[test]
+    fn binary_is_smaller() {
+        let json = encode_message(&make_image_message(), WireFormat::Json).unwrap();
+        let binary = encode_message(&make_image_message(), WireFormat::Binary).unwrap();
+        assert!(binary.len() * 2 < json.len());
+    }
+
+    #[test]
+    fn switch_after_handshake() {
+        let mut data = encode_message(&make_image_message(), WireFormat::Json).unwrap();
+        data.extend(encode_message(&make_image_message(), WireFormat::Binary).unwrap());
+        let mut reader = MessageReader::new(Cursor::new(data), WireFormat::Json);
+        let _first: IncomingMessage = reader.read().unwrap();
+        reader.format = WireFormat::Binary;
+        let second: IncomingMessage = reader.read().unwrap();
+        assert_eq!(second.timestamp_usec, 42);
+    }
+
+    #[test]
+    fn decode_partial_buffers() {
+        for format in [WireFormat::Json, WireFormat::Binary] {
+            let data = encode_message(&make_sized_image_message(4), format).unwrap();
+            let mut buf = vec![];
+            // feed it a byte at a time, the way a slow socket might
+            for (i, b) in data.iter().enumerate() {
+                buf.push(*b);
+                let msg = decode_message::<IncomingMessage>(&mut buf, format).unwrap();
+                assert_eq!(msg.is_some(), i == data.len() - 1);
+            }
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn reject_huge_frames() {
+        let data = (MAX_FRAME_LENGTH + 1).to_be_bytes().to_vec();
+        let mut reader = MessageReader::new(Cursor::new(data), WireFormat::Binary);
+        assert!(reader.read::<IncomingMessage>().is_err());
+    }
+}
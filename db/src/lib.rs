@@ -1,5 +1,6 @@
 extern crate core;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::collections::HashMap;
@@ -235,7 +236,7 @@ impl JDB {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct JObj {
     pub id:String,
     pub deleted:bool,
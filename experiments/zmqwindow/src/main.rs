@@ -152,6 +152,7 @@ fn create_mod(keymod: Mod) -> ModifierState {
         ctrl: false,
         alt: false,
         meta: false,
+        altgr: false,
     };
     if (keymod == Mod::LSHIFTMOD || keymod == Mod::RSHIFTMOD) {
         mods.shift = true
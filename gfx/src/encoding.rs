@@ -0,0 +1,342 @@
+//! Compressed forms of a GFXBuffer's pixels, for sending images over slow connections.
+//! Every encoding works on ARGB pixels, whatever the layout of the buffer.
+use std::io::Cursor;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::graphics::{GFXBuffer, PixelLayout};
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default, JsonSchema)]
+pub enum ImageEncoding {
+    // the bytes of the buffer as they are
+    #[default]
+    Raw,
+    Png,
+    // the Quite OK Image format. almost as small as png for screenshots and much faster.
+    Qoi,
+    // runs of identical pixels. good for flat ui graphics and nothing else.
+    Rle,
+}
+
+// a pixel as [a, r, g, b]
+type Pixel = [u8; 4];
+
+/// The most bytes a decoded image may take. The same as the largest frame central accepts, so
+/// a few bytes of compressed data can't be blown up past what could have been sent raw.
+pub const MAX_DECODED_LENGTH:usize = 64*1024*1024;
+
+fn argb_pixels(buf:&GFXBuffer) -> Vec<Pixel> {
+    let argb;
+    let buf = if buf.layout == PixelLayout::ARGB() {
+        buf
+    } else {
+        argb = buf.to_layout(&PixelLayout::ARGB());
+        &argb
+    };
+    buf.data.chunks_exact(4).map(|px| [px[0], px[1], px[2], px[3]]).collect()
+}
+
+fn from_argb_pixels(pixels:&[Pixel], width:u32, height:u32, layout:&PixelLayout) -> GFXBuffer {
+    let mut buf = GFXBuffer::new(width, height, &PixelLayout::ARGB());
+    for (dst, px) in buf.data.chunks_exact_mut(4).zip(pixels) {
+        dst.copy_from_slice(px);
+    }
+    if *layout == PixelLayout::ARGB() {
+        buf
+    } else {
+        buf.to_layout(layout)
+    }
+}
+
+/// Compress the pixels of an image. Raw returns the data unchanged.
+pub fn encode(buf:&GFXBuffer, encoding:ImageEncoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        ImageEncoding::Raw => Ok(buf.data.clone()),
+        ImageEncoding::Png => encode_png(buf),
+        ImageEncoding::Qoi => Ok(encode_qoi(&argb_pixels(buf), buf.width, buf.height)),
+        ImageEncoding::Rle => Ok(encode_rle(&argb_pixels(buf))),
+    }
+}
+
+/// Turn bytes made by encode back into an image of the given size and layout.
+pub fn decode(data:&[u8], width:u32, height:u32, layout:&PixelLayout, encoding:ImageEncoding) -> Result<GFXBuffer, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("can't decode an image of size {}x{}", width, height));
+    }
+    let count = width as usize * height as usize;
+    // the size comes from whoever sent the image, so check it before allocating anything
+    if count.checked_mul(4).is_none_or(|len| len > MAX_DECODED_LENGTH) {
+        return Err(format!("image of size {}x{} is too large", width, height));
+    }
+    let pixels = match encoding {
+        ImageEncoding::Raw => {
+            let mut buf = GFXBuffer::new(width, height, layout);
+            if data.len() != buf.data.len() {
+                return Err(format!("raw image is {} bytes instead of {}", data.len(), buf.data.len()));
+            }
+            buf.data.copy_from_slice(data);
+            return Ok(buf);
+        }
+        ImageEncoding::Png => decode_png(data, width, height)?,
+        ImageEncoding::Qoi => decode_qoi(data, width, height)?,
+        ImageEncoding::Rle => decode_rle(data, count)?,
+    };
+    if pixels.len() != count {
+        return Err(format!("image has {} pixels instead of {}", pixels.len(), count));
+    }
+    Ok(from_argb_pixels(&pixels, width, height, layout))
+}
+
+fn encode_png(buf:&GFXBuffer) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    let mut encoder = png::Encoder::new(&mut out, buf.width, buf.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    let rgba:Vec<u8> = argb_pixels(buf).iter().flat_map(|px| [px[1], px[2], px[3], px[0]]).collect();
+    writer.write_image_data(&rgba).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+fn decode_png(data:&[u8], width:u32, height:u32) -> Result<Vec<Pixel>, String> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    // palettes and small bit depths come out as 8 bit rgb or rgba
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    // the output buffer is sized from the png's own header
+    let (w, h) = (reader.info().width, reader.info().height);
+    if w != width || h != height {
+        return Err(format!("png is {}x{} instead of {}x{}", w, h, width, height));
+    }
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let bytes = &buf[..info.buffer_size()];
+    match (info.color_type, info.bit_depth) {
+        (png::ColorType::Rgba, png::BitDepth::Eight) => Ok(bytes.chunks_exact(4).map(|p| [p[3], p[0], p[1], p[2]]).collect()),
+        (png::ColorType::Rgb, png::BitDepth::Eight) => Ok(bytes.chunks_exact(3).map(|p| [255, p[0], p[1], p[2]]).collect()),
+        (color, depth) => Err(format!("unsupported png format {:?} {:?}", color, depth)),
+    }
+}
+
+// see https://qoiformat.org/qoi-specification.pdf
+const QOI_OP_INDEX:u8 = 0x00;
+const QOI_OP_DIFF:u8 = 0x40;
+const QOI_OP_LUMA:u8 = 0x80;
+const QOI_OP_RUN:u8 = 0xc0;
+const QOI_OP_RGB:u8 = 0xfe;
+const QOI_OP_RGBA:u8 = 0xff;
+const QOI_MASK:u8 = 0xc0;
+const QOI_END:[u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+const QOI_HEADER_LEN:usize = 14;
+
+fn qoi_hash(px:&Pixel) -> usize {
+    let [a, r, g, b] = *px;
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+fn encode_qoi(pixels:&[Pixel], width:u32, height:u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(QOI_HEADER_LEN + pixels.len() + QOI_END.len());
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&height.to_be_bytes());
+    // four channels, srgb
+    out.extend_from_slice(&[4, 0]);
+    let mut index = [[0u8; 4]; 64];
+    let mut prev:Pixel = [255, 0, 0, 0];
+    let mut run = 0u8;
+    for (n, px) in pixels.iter().enumerate() {
+        if *px == prev {
+            run += 1;
+            if run == 62 || n == pixels.len() - 1 {
+                out.push(QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(QOI_OP_RUN | (run - 1));
+            run = 0;
+        }
+        let hash = qoi_hash(px);
+        if index[hash] == *px {
+            out.push(QOI_OP_INDEX | hash as u8);
+        } else {
+            index[hash] = *px;
+            let [a, r, g, b] = *px;
+            if a == prev[0] {
+                let dr = r.wrapping_sub(prev[1]) as i8;
+                let dg = g.wrapping_sub(prev[2]) as i8;
+                let db = b.wrapping_sub(prev[3]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(QOI_OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                    out.push(QOI_OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[QOI_OP_RGB, r, g, b]);
+                }
+            } else {
+                out.extend_from_slice(&[QOI_OP_RGBA, r, g, b, a]);
+            }
+        }
+        prev = *px;
+    }
+    out.extend_from_slice(&QOI_END);
+    out
+}
+
+fn decode_qoi(data:&[u8], width:u32, height:u32) -> Result<Vec<Pixel>, String> {
+    if data.len() < QOI_HEADER_LEN + QOI_END.len() || &data[0..4] != b"qoif" {
+        return Err(String::from("not a qoi image"));
+    }
+    let w = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let h = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    if w != width || h != height {
+        return Err(format!("qoi image is {}x{} instead of {}x{}", w, h, width, height));
+    }
+    let count = width as usize * height as usize;
+    let mut pixels = Vec::with_capacity(count);
+    let mut index = [[0u8; 4]; 64];
+    let mut px:Pixel = [255, 0, 0, 0];
+    let mut pos = QOI_HEADER_LEN;
+    let end = data.len() - QOI_END.len();
+    let truncated = || String::from("qoi image is truncated");
+    while pixels.len() < count {
+        let b1 = *data.get(pos).filter(|_| pos < end).ok_or_else(truncated)?;
+        pos += 1;
+        if b1 == QOI_OP_RGB || b1 == QOI_OP_RGBA {
+            let len = if b1 == QOI_OP_RGB { 3 } else { 4 };
+            let bytes = data.get(pos..pos + len).filter(|_| pos + len <= end).ok_or_else(truncated)?;
+            px[1] = bytes[0];
+            px[2] = bytes[1];
+            px[3] = bytes[2];
+            if len == 4 {
+                px[0] = bytes[3];
+            }
+            pos += len;
+        } else {
+            match b1 & QOI_MASK {
+                QOI_OP_INDEX => px = index[b1 as usize],
+                QOI_OP_DIFF => {
+                    px[1] = px[1].wrapping_add((b1 >> 4) & 0x03).wrapping_sub(2);
+                    px[2] = px[2].wrapping_add((b1 >> 2) & 0x03).wrapping_sub(2);
+                    px[3] = px[3].wrapping_add(b1 & 0x03).wrapping_sub(2);
+                }
+                QOI_OP_LUMA => {
+                    let b2 = *data.get(pos).filter(|_| pos < end).ok_or_else(truncated)?;
+                    pos += 1;
+                    let dg = (b1 & 0x3f).wrapping_sub(32);
+                    px[1] = px[1].wrapping_add(dg).wrapping_sub(8).wrapping_add(b2 >> 4);
+                    px[2] = px[2].wrapping_add(dg);
+                    px[3] = px[3].wrapping_add(dg).wrapping_sub(8).wrapping_add(b2 & 0x0f);
+                }
+                _ => {
+                    // QOI_OP_RUN. the run includes this pixel.
+                    let run = (b1 & 0x3f) as usize + 1;
+                    if pixels.len() + run > count {
+                        return Err(String::from("qoi run goes past the end of the image"));
+                    }
+                    pixels.extend(std::iter::repeat_n(px, run));
+                    continue;
+                }
+            }
+        }
+        index[qoi_hash(&px)] = px;
+        pixels.push(px);
+    }
+    Ok(pixels)
+}
+
+// each run is a count from 1 to 255 followed by the pixel as a r g b
+fn encode_rle(pixels:&[Pixel]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut iter = pixels.iter().peekable();
+    while let Some(px) = iter.next() {
+        let mut count = 1u8;
+        while count < 255 && iter.peek() == Some(&px) {
+            iter.next();
+            count += 1;
+        }
+        out.push(count);
+        out.extend_from_slice(px);
+    }
+    out
+}
+
+fn decode_rle(data:&[u8], count:usize) -> Result<Vec<Pixel>, String> {
+    if !data.len().is_multiple_of(5) {
+        return Err(String::from("rle image is truncated"));
+    }
+    let mut pixels = Vec::with_capacity(count);
+    for run in data.chunks_exact(5) {
+        let n = run[0] as usize;
+        if n == 0 || pixels.len() + n > count {
+            return Err(String::from("rle run goes past the end of the image"));
+        }
+        pixels.extend(std::iter::repeat_n([run[1], run[2], run[3], run[4]], n));
+    }
+    if pixels.len() != count {
+        return Err(format!("rle image has {} pixels instead of {}", pixels.len(), count));
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{decode, encode, ImageEncoding};
+    use crate::graphics::{ARGBColor, draw_test_pattern, GFXBuffer, PixelLayout, Rect, WHITE};
+
+    fn test_image() -> GFXBuffer {
+        let mut buf = GFXBuffer::new(64, 48, &PixelLayout::ARGB());
+        buf.clear(&WHITE);
+        draw_test_pattern(&mut buf);
+        // a gradient, so qoi uses its diff and luma ops too
+        for i in 0..64 {
+            buf.fill_rect(&Rect::from_ints(i, 40, 1, 8), &ARGBColor::new_argb(200, i as u8 * 3, 100, 255 - i as u8));
+        }
+        buf
+    }
+
+    #[test]
+    fn round_trips() {
+        let buf = test_image();
+        for encoding in [ImageEncoding::Raw, ImageEncoding::Png, ImageEncoding::Qoi, ImageEncoding::Rle] {
+            let data = encode(&buf, encoding).unwrap();
+            let back = decode(&data, buf.width, buf.height, &buf.layout, encoding).unwrap();
+            assert_eq!(back.data, buf.data, "{:?} changed the image", encoding);
+        }
+    }
+
+    #[test]
+    fn flat_images_shrink() {
+        let mut buf = GFXBuffer::new(200, 100, &PixelLayout::ARGB());
+        buf.clear(&WHITE);
+        buf.fill_rect(&Rect::from_ints(10, 10, 50, 20), &ARGBColor::new_rgb(0, 0, 255));
+        for encoding in [ImageEncoding::Png, ImageEncoding::Qoi, ImageEncoding::Rle] {
+            let data = encode(&buf, encoding).unwrap();
+            assert!(data.len() < buf.data.len() / 20, "{:?} is {} bytes", encoding, data.len());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let buf = test_image();
+        let data = encode(&buf, ImageEncoding::Qoi).unwrap();
+        assert!(decode(&data[..data.len() / 2], buf.width, buf.height, &buf.layout, ImageEncoding::Qoi).is_err());
+        assert!(decode(&data, buf.width + 1, buf.height, &buf.layout, ImageEncoding::Qoi).is_err());
+        assert!(decode(&[3, 1, 2, 3, 4], 2, 1, &buf.layout, ImageEncoding::Rle).is_err());
+        assert!(decode(&[1, 2, 3], 2, 1, &buf.layout, ImageEncoding::Png).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_sizes() {
+        // a single run, which claims to be a 4 gigapixel image
+        let data = [255, 255, 0, 0, 0];
+        assert!(decode(&data, 65536, 65536, &PixelLayout::ARGB(), ImageEncoding::Rle).is_err());
+        assert!(decode(&data, u32::MAX, u32::MAX, &PixelLayout::ARGB(), ImageEncoding::Raw).is_err());
+        let png = encode(&test_image(), ImageEncoding::Png).unwrap();
+        assert!(decode(&png, 1 << 20, 1 << 20, &PixelLayout::ARGB(), ImageEncoding::Png).is_err());
+    }
+}
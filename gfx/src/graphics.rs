@@ -5,10 +5,11 @@ use std::io::BufWriter;
 use std::path::{PathBuf};
 use png;
 use uuid::Uuid;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, JsonSchema)]
 pub struct Point {
     pub x:i32,
     pub y:i32,
@@ -37,7 +38,7 @@ impl Point {
 }
 
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, JsonSchema)]
 pub struct Size {
     pub w:i32,
     pub h:i32,
@@ -74,7 +75,7 @@ impl Padding {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, JsonSchema)]
 pub struct Rect {
     pub x:i32,
     pub y:i32,
@@ -177,10 +178,29 @@ impl Rect {
             h:r2y-ry,
         }
     }
+    // the smallest rect which covers both
+    pub fn union(&self, r2: Rect) -> Rect {
+        if r2.is_empty() {
+            return *self
+        }
+        if self.is_empty() {
+            return r2
+        }
+        let c1 = self.lower_right_corner();
+        let c2 = r2.lower_right_corner();
+        let x = self.x.min(r2.x);
+        let y = self.y.min(r2.y);
+        Rect {
+            x,
+            y,
+            w:c1.x.max(c2.x)-x,
+            h:c1.y.max(c2.y)-y,
+        }
+    }
     fn lower_right_corner(&self) -> Point {
         Point::init(self.x+self.w,self.y+self.h)
     }
-    pub(crate) fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         if self.w <= 0 {
             return true
         }
@@ -209,7 +229,7 @@ impl std::fmt::Display for Rect {
 
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ARGBColor {
     pub r:u8,
     pub g:u8,
@@ -263,7 +283,7 @@ impl ARGBColor {
 pub const BLACK:ARGBColor = ARGBColor { r: 0, g: 0, b: 0, a: 255 };
 pub const WHITE:ARGBColor = ARGBColor { r: 255, g: 255, b: 255, a: 255 };
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 pub enum PixelLayout {
     RGB565(),
     // RGB(),
@@ -282,7 +302,7 @@ impl PixelLayout {
 }
 
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct GFXBuffer {
     pub layout:PixelLayout,
     pub id:Uuid,
@@ -804,6 +824,16 @@ mod tests {
     }
     */
 
+    #[test]
+    fn rect_union() {
+        let a = Rect::from_ints(10, 10, 20, 20);
+        let b = Rect::from_ints(25, 5, 10, 10);
+        assert_eq!(a.union(b), Rect::from_ints(10, 5, 25, 25));
+        // empty rects don't grow anything
+        assert_eq!(a.union(Rect::from_ints(100, 100, 0, 0)), a);
+        assert_eq!(Rect::from_ints(0, 0, 0, 0).union(b), b);
+    }
+
     #[test]
     fn test_font_load() {
         println!("current dir = {:?}",std::env::current_dir());
@@ -11,7 +11,8 @@ use minifb::{Key, MouseButton, MouseMode, Scale, Window, WindowOptions};
 use std::sync::mpsc::Sender;
 use std::time::{SystemTime, UNIX_EPOCH};
 use log::info;
-use common::events::{KeyDownEvent, KeyUpEvent, ModifierState, MouseDownEvent, MouseMoveEvent, MouseUpEvent};
+use common::events::{KeyDownEvent, KeyUpEvent, ModifierState, MouseDownEvent, MouseMoveEvent, MouseUpEvent, MouseWheelEvent, TextInputEvent};
+use common::keymap::{KeyboardLayout, PressedKeys};
 use common::generated::KeyCode;
 use common::{APICommand, IncomingMessage};
 use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect};
@@ -25,10 +26,13 @@ pub struct Plat {
     screen_size:Rect,
     layout:PixelLayout,
     pub window: Window,
-    mouse_down:bool,
+    // left, middle, right
+    mouse_down:[bool;3],
     pub buffer: Vec<u32>,
     pub keys_data2: KeyVec,
     pub mod_state: ModifierState,
+    pub keyboard: KeyboardLayout,
+    pressed_keys: PressedKeys,
 }
 
 impl Plat {
@@ -90,48 +94,53 @@ impl Plat {
             let x = x.floor() as i32;
             let y = y.floor() as i32;
             // println!("mouse pos is {}x{}",x,y);
-            let current_mouse_down = self.window.get_mouse_down(MouseButton::Left);
-            if current_mouse_down != self.mouse_down {
-                if current_mouse_down {
-                    self.mouse_down = current_mouse_down;
-                    let cmd = IncomingMessage {
-                        source: Default::default(),
-                        trace: true,
-                        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
-                        command: APICommand::MouseDown(MouseDownEvent {
-                            app_id: Default::default(),
-                            window_id: Default::default(),
-                            original_timestamp: 0,
-                            button: common::events::MouseButton::Primary,
-                            x,
-                            y,
-                        })
-                    };
-                    // info!("about to send out {:?}",cmd);
-                    if let Err(e) = self.sender.send(cmd) {
-                        println!("error sending mouse down out {:?}",e);
-                    }
+            // minifb only knows about these three
+            let buttons = [
+                (MouseButton::Left, common::events::MouseButton::Primary),
+                (MouseButton::Middle, common::events::MouseButton::Middle),
+                (MouseButton::Right, common::events::MouseButton::Secondary),
+            ];
+            let mut changed = false;
+            for (n, (btn, button)) in buttons.into_iter().enumerate() {
+                let current_mouse_down = self.window.get_mouse_down(btn);
+                if current_mouse_down == self.mouse_down[n] {
+                    continue;
+                }
+                self.mouse_down[n] = current_mouse_down;
+                changed = true;
+                let command = if current_mouse_down {
+                    APICommand::MouseDown(MouseDownEvent {
+                        app_id: Default::default(),
+                        window_id: Default::default(),
+                        original_timestamp: 0,
+                        button,
+                        x,
+                        y,
+                        clicks: 1,
+                    })
                 } else {
-                    self.mouse_down = current_mouse_down;
-                    let cmd = IncomingMessage {
-                        trace: false,
-                        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
-                        source: Default::default(),
-                        command: APICommand::MouseUp(MouseUpEvent {
-                            app_id: Default::default(),
-                            window_id: Default::default(),
-                            original_timestamp: 0,
-                            button: common::events::MouseButton::Primary,
-                            x,
-                            y,
-                        })
-                    };
-                    // info!("about to send out {:?}",cmd);
-                    if let Err(e) = self.sender.send(cmd) {
-                        println!("error sending mouse up out {:?}",e);
-                    }
+                    APICommand::MouseUp(MouseUpEvent {
+                        app_id: Default::default(),
+                        window_id: Default::default(),
+                        original_timestamp: 0,
+                        button,
+                        x,
+                        y,
+                    })
+                };
+                let cmd = IncomingMessage {
+                    source: Default::default(),
+                    trace: current_mouse_down,
+                    timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                    command,
+                    request_id: None,
+                };
+                // info!("about to send out {:?}",cmd);
+                if let Err(e) = self.sender.send(cmd) {
+                    println!("error sending mouse button out {:?}",e);
                 }
-            } else {
+            }
+            if !changed {
                 let cmd = IncomingMessage {
                     trace: false,
                     timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
@@ -143,25 +152,59 @@ impl Plat {
                         button: common::events::MouseButton::Primary,
                         x,
                         y,
-                    })
+                    }),
+                    request_id: None,
                 };
                 // info!("about to send out {:?}",cmd);
                 if let Err(e) = self.sender.send(cmd) {
                     println!("error sending mouse motion out {:?}", e);
                 }
             }
+            // minifb counts scrolling up as positive
+            if let Some((wx, wy)) = self.window.get_scroll_wheel() {
+                let (dx, dy) = (wx.round() as i32, -wy.round() as i32);
+                if dx != 0 || dy != 0 {
+                    let cmd = IncomingMessage {
+                        trace: false,
+                        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                        source: Default::default(),
+                        command: APICommand::MouseWheel(MouseWheelEvent {
+                            app_id: Default::default(),
+                            window_id: Default::default(),
+                            original_timestamp: 0,
+                            x,
+                            y,
+                            dx,
+                            dy,
+                        }),
+                        request_id: None,
+                    };
+                    if let Err(e) = self.sender.send(cmd) {
+                        println!("error sending mouse wheel out {:?}", e);
+                    }
+                }
+            }
         }
         for (key, down) in self.keys_data2.borrow_mut().iter() {
-            let keycode = minifb_to_KeyCode(key);
             let mut mod_state:ModifierState = ModifierState::empty();
             mod_state.shift = self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
             mod_state.ctrl  = self.window.is_key_down(Key::LeftCtrl)  || self.window.is_key_down(Key::RightCtrl);
+            mod_state.altgr = self.window.is_key_down(Key::RightAlt);
+            let physical = minifb_to_KeyCode(key);
+            let (keycode, text) = self.keyboard.translate(physical, &mod_state);
+            let keycode = if *down {
+                self.pressed_keys.press(physical, keycode)
+            } else {
+                self.pressed_keys.release(physical).unwrap_or(keycode)
+            };
+            let text = if *down { text } else { None };
             let command:APICommand = if *down {
                 APICommand::KeyDown(KeyDownEvent {
                     app_id: Default::default(),
                     window_id: Default::default(),
                     key: keycode,
                     mods: mod_state,
+                    repeat: false,
                 })
             } else {
                 APICommand::KeyUp(KeyUpEvent {
@@ -176,13 +219,33 @@ impl Plat {
                 timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                 source: Default::default(),
                 command,
+                request_id: None,
             };
             if let Err(e) = self.sender.send(cmd) {
                 println!("error sending key down out {:?}",e);
             }
+            if let Some(text) = text {
+                let cmd = IncomingMessage {
+                    trace:false,
+                    timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                    source: Default::default(),
+                    command: APICommand::TextInput(TextInputEvent {
+                        app_id: Default::default(),
+                        window_id: Default::default(),
+                        text,
+                    }),
+                    request_id: None,
+                };
+                if let Err(e) = self.sender.send(cmd) {
+                    println!("error sending text input out {:?}",e);
+                }
+            }
         }
         self.keys_data2.borrow_mut().clear();
     }
+    pub fn set_keyboard_layout(&mut self, keyboard: KeyboardLayout) {
+        self.keyboard = keyboard;
+    }
     pub fn get_preferred_pixel_layout(&self) -> &PixelLayout {
         return &self.layout
     }
@@ -268,6 +331,66 @@ fn minifb_to_KeyCode(id: &Key) -> KeyCode {
         Key::Backslash => KeyCode::BACKSLASH,
         Key::Comma => KeyCode::COMMA,
         Key::Equal => KeyCode::EQUALS,
+        Key::LeftBracket => KeyCode::BRACKET_LEFT,
+        Key::RightBracket => KeyCode::BRACKET_RIGHT,
+        Key::Minus => KeyCode::MINUS,
+        Key::Period => KeyCode::PERIOD,
+        Key::Semicolon => KeyCode::SEMICOLON,
+        Key::Slash => KeyCode::SLASH,
+        Key::Space => KeyCode::SPACE,
+        Key::Tab => KeyCode::TAB,
+        Key::Enter => KeyCode::ENTER,
+        Key::Escape => KeyCode::ESCAPE,
+
+        Key::LeftShift => KeyCode::SHIFT_LEFT,
+        Key::RightShift => KeyCode::SHIFT_RIGHT,
+        Key::LeftCtrl => KeyCode::CONTROL_LEFT,
+        Key::RightCtrl => KeyCode::CONTROL_RIGHT,
+        Key::LeftAlt => KeyCode::ALT_LEFT,
+        Key::RightAlt => KeyCode::ALT_RIGHT,
+        Key::LeftSuper => KeyCode::META_LEFT,
+        Key::RightSuper => KeyCode::META_RIGHT,
+
+        Key::F1 => KeyCode::F1,
+        Key::F2 => KeyCode::F2,
+        Key::F3 => KeyCode::F3,
+        Key::F4 => KeyCode::F4,
+        Key::F5 => KeyCode::F5,
+        Key::F6 => KeyCode::F6,
+        Key::F7 => KeyCode::F7,
+        Key::F8 => KeyCode::F8,
+        Key::F9 => KeyCode::F9,
+        Key::F10 => KeyCode::F10,
+        Key::F11 => KeyCode::F11,
+        Key::F12 => KeyCode::F12,
+
+        Key::Home => KeyCode::HOME,
+        Key::End => KeyCode::END,
+        Key::PageUp => KeyCode::PAGE_UP,
+        Key::PageDown => KeyCode::PAGE_DOWN,
+        Key::Insert => KeyCode::INSERT,
+        Key::CapsLock => KeyCode::CAPS_LOCK,
+        Key::NumLock => KeyCode::NUM_LOCK,
+        Key::ScrollLock => KeyCode::SCROLL_LOCK,
+        Key::Pause => KeyCode::PAUSE,
+        Key::Menu => KeyCode::CONTEXT_MENU,
+
+        Key::NumPad0 => KeyCode::NUMPAD_0,
+        Key::NumPad1 => KeyCode::NUMPAD_1,
+        Key::NumPad2 => KeyCode::NUMPAD_2,
+        Key::NumPad3 => KeyCode::NUMPAD_3,
+        Key::NumPad4 => KeyCode::NUMPAD_4,
+        Key::NumPad5 => KeyCode::NUMPAD_5,
+        Key::NumPad6 => KeyCode::NUMPAD_6,
+        Key::NumPad7 => KeyCode::NUMPAD_7,
+        Key::NumPad8 => KeyCode::NUMPAD_8,
+        Key::NumPad9 => KeyCode::NUMPAD_9,
+        Key::NumPadSlash => KeyCode::NUMPAD_DIVIDE,
+        Key::NumPadAsterisk => KeyCode::NUMPAD_MULTIPLY,
+        Key::NumPadMinus => KeyCode::NUMPAD_SUBTRACT,
+        Key::NumPadPlus => KeyCode::NUMPAD_ADD,
+        Key::NumPadDot => KeyCode::NUMPAD_DECIMAL,
+        Key::NumPadEnter => KeyCode::NUMPAD_ENTER,
 
         _ => KeyCode::UNKNOWN,
     }
@@ -329,8 +452,10 @@ pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, widt
         window:window,
         screen_size: screen_size,
         layout:PixelLayout::ARGB(),
-        mouse_down:false,
+        mouse_down:[false;3],
         keys_data2,
         mod_state:ModifierState::empty(),
+        keyboard: KeyboardLayout::us(),
+        pressed_keys: PressedKeys::new(),
     });
 }
@@ -1,6 +1,8 @@
 use std::cmp::max;
-use evdev::{AbsoluteAxisType, Device, EventType, InputEventKind, Key, RelativeAxisType};
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::time::UNIX_EPOCH;
+use evdev::{AbsoluteAxisType, Device, EventType, InputEvent, InputEventKind, Key, RelativeAxisType};
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use common::{APICommand, IncomingMessage, Rect};
@@ -10,6 +12,7 @@ use std::thread;
 use log::{info, warn};
 use common::events::{MouseButton, MouseMoveEvent};
 use common::generated::KeyCode;
+use common::keymap::{KeyboardLayout, PressedKeys};
 
 pub fn find_keyboard() -> Option<evdev::Device> {
     let mut devices = evdev::enumerate().collect::<Vec<_>>();
@@ -103,14 +106,162 @@ fn linuxkernel_to_KeyCode(code:u16) -> KeyCode {
         Key::KEY_RIGHTALT => KeyCode::ALT_RIGHT,
         Key::KEY_LEFTCTRL => KeyCode::CONTROL_LEFT,
         Key::KEY_RIGHTCTRL => KeyCode::CONTROL_RIGHT,
+        Key::KEY_LEFTMETA => KeyCode::META_LEFT,
+        Key::KEY_RIGHTMETA => KeyCode::META_RIGHT,
+
+        Key::KEY_TAB => KeyCode::TAB,
+        Key::KEY_BACKSPACE => KeyCode::BACKSPACE,
+        Key::KEY_DELETE => KeyCode::DELETE,
+        Key::KEY_COMMA => KeyCode::COMMA,
+        Key::KEY_DOT => KeyCode::PERIOD,
+        Key::KEY_SLASH => KeyCode::SLASH,
+        Key::KEY_SEMICOLON => KeyCode::SEMICOLON,
+        Key::KEY_APOSTROPHE => KeyCode::QUOTE,
+        Key::KEY_GRAVE => KeyCode::BACKQUOTE,
+        Key::KEY_LEFTBRACE => KeyCode::BRACKET_LEFT,
+        Key::KEY_RIGHTBRACE => KeyCode::BRACKET_RIGHT,
+        Key::KEY_BACKSLASH => KeyCode::BACKSLASH,
+        Key::KEY_MINUS => KeyCode::MINUS,
+        Key::KEY_EQUAL => KeyCode::EQUALS,
+        Key::KEY_102ND => KeyCode::INTL_BACKSLASH,
+
+        Key::KEY_F1 => KeyCode::F1,
+        Key::KEY_F2 => KeyCode::F2,
+        Key::KEY_F3 => KeyCode::F3,
+        Key::KEY_F4 => KeyCode::F4,
+        Key::KEY_F5 => KeyCode::F5,
+        Key::KEY_F6 => KeyCode::F6,
+        Key::KEY_F7 => KeyCode::F7,
+        Key::KEY_F8 => KeyCode::F8,
+        Key::KEY_F9 => KeyCode::F9,
+        Key::KEY_F10 => KeyCode::F10,
+        Key::KEY_F11 => KeyCode::F11,
+        Key::KEY_F12 => KeyCode::F12,
+
+        Key::KEY_HOME => KeyCode::HOME,
+        Key::KEY_END => KeyCode::END,
+        Key::KEY_PAGEUP => KeyCode::PAGE_UP,
+        Key::KEY_PAGEDOWN => KeyCode::PAGE_DOWN,
+        Key::KEY_INSERT => KeyCode::INSERT,
+        Key::KEY_CAPSLOCK => KeyCode::CAPS_LOCK,
+        Key::KEY_NUMLOCK => KeyCode::NUM_LOCK,
+        Key::KEY_SCROLLLOCK => KeyCode::SCROLL_LOCK,
+        Key::KEY_SYSRQ => KeyCode::PRINT_SCREEN,
+        Key::KEY_PAUSE => KeyCode::PAUSE,
+        Key::KEY_COMPOSE => KeyCode::CONTEXT_MENU,
+
+        Key::KEY_KP0 => KeyCode::NUMPAD_0,
+        Key::KEY_KP1 => KeyCode::NUMPAD_1,
+        Key::KEY_KP2 => KeyCode::NUMPAD_2,
+        Key::KEY_KP3 => KeyCode::NUMPAD_3,
+        Key::KEY_KP4 => KeyCode::NUMPAD_4,
+        Key::KEY_KP5 => KeyCode::NUMPAD_5,
+        Key::KEY_KP6 => KeyCode::NUMPAD_6,
+        Key::KEY_KP7 => KeyCode::NUMPAD_7,
+        Key::KEY_KP8 => KeyCode::NUMPAD_8,
+        Key::KEY_KP9 => KeyCode::NUMPAD_9,
+        Key::KEY_KPSLASH => KeyCode::NUMPAD_DIVIDE,
+        Key::KEY_KPASTERISK => KeyCode::NUMPAD_MULTIPLY,
+        Key::KEY_KPMINUS => KeyCode::NUMPAD_SUBTRACT,
+        Key::KEY_KPPLUS => KeyCode::NUMPAD_ADD,
+        Key::KEY_KPDOT => KeyCode::NUMPAD_DECIMAL,
+        Key::KEY_KPEQUAL => KeyCode::NUMPAD_EQUALS,
+        Key::KEY_KPENTER => KeyCode::NUMPAD_ENTER,
+
+        Key::KEY_PLAYPAUSE => KeyCode::MEDIA_PLAY_PAUSE,
+        Key::KEY_STOPCD => KeyCode::MEDIA_STOP,
+        Key::KEY_NEXTSONG => KeyCode::MEDIA_NEXT,
+        Key::KEY_PREVIOUSSONG => KeyCode::MEDIA_PREVIOUS,
+        Key::KEY_VOLUMEUP => KeyCode::VOLUME_UP,
+        Key::KEY_VOLUMEDOWN => KeyCode::VOLUME_DOWN,
+        Key::KEY_MUTE => KeyCode::VOLUME_MUTE,
         _ => KeyCode::UNKNOWN
     }
 }
 
-pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender<IncomingMessage>, screen_size: Rect) {
+// the modifier keys held down. left and right are tracked separately so letting go
+// of one shift while still holding the other keeps shift on.
+struct HeldModifiers {
+    keys:HashSet<KeyCode>,
+}
+
+impl HeldModifiers {
+    fn new() -> HeldModifiers {
+        HeldModifiers { keys: HashSet::new() }
+    }
+    fn update(&mut self, key:KeyCode, pressed:bool) {
+        match key {
+            KeyCode::SHIFT_LEFT | KeyCode::SHIFT_RIGHT |
+            KeyCode::CONTROL_LEFT | KeyCode::CONTROL_RIGHT |
+            KeyCode::ALT_LEFT | KeyCode::ALT_RIGHT |
+            KeyCode::META_LEFT | KeyCode::META_RIGHT => {
+                if pressed {
+                    self.keys.insert(key);
+                } else {
+                    self.keys.remove(&key);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn state(&self) -> ModifierState {
+        let held = |left, right| self.keys.contains(&left) || self.keys.contains(&right);
+        ModifierState {
+            shift: held(KeyCode::SHIFT_LEFT, KeyCode::SHIFT_RIGHT),
+            ctrl: held(KeyCode::CONTROL_LEFT, KeyCode::CONTROL_RIGHT),
+            alt: held(KeyCode::ALT_LEFT, KeyCode::ALT_RIGHT),
+            meta: held(KeyCode::META_LEFT, KeyCode::META_RIGHT),
+            altgr: self.keys.contains(&KeyCode::ALT_RIGHT),
+        }
+    }
+}
+
+fn linuxkernel_to_mouse_button(key:Key) -> Option<MouseButton> {
+    match key {
+        Key::BTN_LEFT => Some(MouseButton::Primary),
+        Key::BTN_RIGHT => Some(MouseButton::Secondary),
+        Key::BTN_MIDDLE => Some(MouseButton::Middle),
+        // mice send either of these for their thumb buttons
+        Key::BTN_SIDE | Key::BTN_BACK => Some(MouseButton::Back),
+        Key::BTN_EXTRA | Key::BTN_FORWARD => Some(MouseButton::Forward),
+        _ => None,
+    }
+}
+
+// a hi-res wheel counts fractions of a notch, 120 to a notch. it sends the plain wheel
+// events as well, so once a wheel has sent hi-res ones its plain ones are skipped.
+const HI_RES_NOTCH:i32 = 120;
+
+#[derive(Default)]
+struct HiResWheel {
+    seen:bool,
+    rest:i32,
+}
+
+impl HiResWheel {
+    // the whole notches turned. what's left over waits for the next event.
+    fn turn(&mut self, amount:i32) -> i32 {
+        self.seen = true;
+        self.rest += amount;
+        let notches = self.rest / HI_RES_NOTCH;
+        self.rest -= notches * HI_RES_NOTCH;
+        notches
+    }
+}
+
+// when the kernel saw the event, in microseconds
+fn event_usec(ev:&InputEvent) -> u128 {
+    ev.timestamp().duration_since(UNIX_EPOCH).map(|d| d.as_micros()).unwrap_or(0)
+}
+
+pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender<IncomingMessage>, screen_size: Rect, keyboard: Arc<Mutex<KeyboardLayout>>) {
     thread::spawn(move || {
         let mut cx = 0.0;
         let mut cy = 0.0;
+        let mut held = HeldModifiers::new();
+        let mut pressed_keys = PressedKeys::new();
+        let mut vwheel = HiResWheel::default();
+        let mut hwheel = HiResWheel::default();
         loop {
             if stop.load(Ordering::Relaxed) == true {
                 println!("keyboard thread stopping");
@@ -125,61 +276,135 @@ pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender
                         //pressed is value=1
                         //repeat is value = 2
                         //released is value = 0
+                        let pressed = ev.value() != 0;
+                        let usec = event_usec(&ev);
+                        if let Some(button) = linuxkernel_to_mouse_button(key) {
+                            let appcmd = if pressed {
+                                APICommand::MouseDown(MouseDownEvent {
+                                    app_id: Default::default(),
+                                    window_id: Default::default(),
+                                    original_timestamp: usec / 1000,
+                                    button,
+                                    x: cx as i32,
+                                    y: cy as i32,
+                                    clicks: 1,
+                                })
+                            } else {
+                                APICommand::MouseUp(MouseUpEvent {
+                                    app_id: Default::default(),
+                                    window_id: Default::default(),
+                                    original_timestamp: usec / 1000,
+                                    button,
+                                    x: cx as i32,
+                                    y: cy as i32,
+                                })
+                            };
+                            tx.send(IncomingMessage {
+                                source: Default::default(),
+                                command: appcmd,
+                                trace: false,
+                                timestamp_usec: usec,
+                                request_id: None,
+                            }).unwrap();
+                            continue;
+                        }
                         let keycode = linuxkernel_to_KeyCode(key.code());
-                        let appcmd = match keycode {
-                            KeyCode::MOUSE_PRIMARY => {
-                                if ev.value() == 1 {
-                                    APICommand::MouseDown(MouseDownEvent {
-                                        app_id: Default::default(),
-                                        window_id: Default::default(),
-                                        original_timestamp: 0,
-                                        button: MouseButton::Primary,
-                                        x: cx as i32,
-                                        y: cy as i32,
-                                    })
-                                } else {
-                                    APICommand::MouseUp(MouseUpEvent {
-                                        app_id: Default::default(),
-                                        window_id: Default::default(),
-                                        original_timestamp: 0,
-                                        button: MouseButton::Primary,
-                                        x: cx as i32,
-                                        y: cy as i32,
-                                    })
-                                }
-                            },
-                            _ => {
-                                if ev.value() == 1 {
-                                    APICommand::KeyDown(KeyDownEvent {
-                                        app_id: Default::default(),
-                                        window_id: Default::default(),
-                                        key: keycode,
-                                        mods: ModifierState::empty(),
-                                    })
-                                } else {
-                                    APICommand::KeyUp(KeyUpEvent {
-                                        app_id: Default::default(),
-                                        window_id: Default::default(),
-                                        key: keycode,
-                                        mods: ModifierState::empty(),
-                                    })
-                                }
-                            }
+                        held.update(keycode, pressed);
+                        let mods = held.state();
+                        // the layout picks what the key at this position means
+                        let (code, text) = keyboard.lock().unwrap().translate(keycode, &mods);
+                        let code = if pressed {
+                            pressed_keys.press(keycode, code)
+                        } else {
+                            pressed_keys.release(keycode).unwrap_or(code)
+                        };
+                        // repeats type the character again
+                        let text = if pressed { text } else { None };
+                        let appcmd = if pressed {
+                            APICommand::KeyDown(KeyDownEvent {
+                                app_id: Default::default(),
+                                window_id: Default::default(),
+                                key: code,
+                                mods: mods.clone(),
+                                repeat: ev.value() == 2,
+                            })
+                        } else {
+                            APICommand::KeyUp(KeyUpEvent {
+                                app_id: Default::default(),
+                                window_id: Default::default(),
+                                key: code,
+                                mods: mods.clone(),
+                            })
                         };
                         let cmd = IncomingMessage {
                             source: Default::default(),
                             command: appcmd,
                             trace: false,
-                            timestamp_usec: 0
+                            timestamp_usec: usec,
+                            request_id: None,
                         };
-                        tx.send(cmd).unwrap()
+                        tx.send(cmd).unwrap();
+                        if let Some(text) = text {
+                            tx.send(IncomingMessage {
+                                source: Default::default(),
+                                command: APICommand::TextInput(TextInputEvent {
+                                    app_id: Default::default(),
+                                    window_id: Default::default(),
+                                    text,
+                                }),
+                                trace: false,
+                                timestamp_usec: usec,
+                                request_id: None,
+                            }).unwrap()
+                        }
                     },
                     InputEventKind::RelAxis(rel) => {
                         // info!("mouse event {:?} {}",rel, ev.value());
                         let v = ev.value() as f32;
+                        let usec = event_usec(&ev);
+                        let (x, y) = (cx as i32, cy as i32);
+                        let wheel = |dx, dy| IncomingMessage {
+                            source: Default::default(),
+                            command: APICommand::MouseWheel(MouseWheelEvent {
+                                app_id: Default::default(),
+                                window_id: Default::default(),
+                                original_timestamp: usec / 1000,
+                                x,
+                                y,
+                                dx,
+                                dy,
+                            }),
+                            trace: false,
+                            timestamp_usec: usec,
+                            request_id: None,
+                        };
                         match rel {
                             RelativeAxisType::REL_X => cx += v,
                             RelativeAxisType::REL_Y => cy += v,
+                            // the kernel counts wheel clicks away from the user as positive
+                            RelativeAxisType::REL_WHEEL if !vwheel.seen => {
+                                tx.send(wheel(0, -ev.value())).unwrap();
+                                continue;
+                            }
+                            RelativeAxisType::REL_HWHEEL if !hwheel.seen => {
+                                tx.send(wheel(ev.value(), 0)).unwrap();
+                                continue;
+                            }
+                            RelativeAxisType::REL_WHEEL | RelativeAxisType::REL_HWHEEL => continue,
+                            RelativeAxisType::REL_WHEEL_HI_RES => {
+                                let notches = vwheel.turn(ev.value());
+                                if notches != 0 {
+                                    tx.send(wheel(0, -notches)).unwrap();
+                                }
+                                continue;
+                            }
+                            RelativeAxisType::REL_HWHEEL_HI_RES => {
+                                let notches = hwheel.turn(ev.value());
+                                if notches != 0 {
+                                    tx.send(wheel(notches, 0)).unwrap();
+                                }
+                                continue;
+                            }
                             _ => {
                                 warn!("unknown relative axis type");
                             }
@@ -196,11 +421,14 @@ pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender
                             command: APICommand::MouseMove(MouseMoveEvent{
                                 app_id: Default::default(),
                                 window_id: Default::default(),
-                                original_timestamp: 0,
+                                original_timestamp: usec / 1000,
                                 button: MouseButton::Primary,
                                 x:cx as i32,
                                 y:cy as i32
-                            })
+                            }),
+                            trace: false,
+                            timestamp_usec: usec,
+                            request_id: None,
                         };
                         tx.send(cmd).unwrap()
                     },
@@ -222,16 +450,20 @@ pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender
                             }
                         }
                         // info!("cursor {} , {}",cx, cy);
+                        let usec = event_usec(&ev);
                         let cmd = IncomingMessage {
                             source: Default::default(),
                             command: APICommand::MouseMove(MouseMoveEvent {
                                 app_id: Default::default(),
                                 window_id: Default::default(),
-                                original_timestamp: 0,
+                                original_timestamp: usec / 1000,
                                 button: MouseButton::Primary,
                                 x: cx as i32,
                                 y: cy as i32
                             }),
+                            trace: false,
+                            timestamp_usec: usec,
+                            request_id: None,
                         };
                         if was_y {
                             tx.send(cmd).unwrap();
@@ -243,3 +475,77 @@ pub fn setup_evdev_watcher(mut device: Device, stop: Arc<AtomicBool>, tx: Sender
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use evdev::Key;
+    use common::events::MouseButton;
+    use common::generated::KeyCode;
+    use crate::input::{HeldModifiers, HiResWheel, linuxkernel_to_KeyCode, linuxkernel_to_mouse_button};
+
+    // every key on a full pc keyboard, plus the media keys
+    const KEYS:&[Key] = &[
+        Key::KEY_A, Key::KEY_B, Key::KEY_C, Key::KEY_D, Key::KEY_E, Key::KEY_F, Key::KEY_G, Key::KEY_H, Key::KEY_I,
+        Key::KEY_J, Key::KEY_K, Key::KEY_L, Key::KEY_M, Key::KEY_N, Key::KEY_O, Key::KEY_P, Key::KEY_Q, Key::KEY_R,
+        Key::KEY_S, Key::KEY_T, Key::KEY_U, Key::KEY_V, Key::KEY_W, Key::KEY_X, Key::KEY_Y, Key::KEY_Z,
+        Key::KEY_0, Key::KEY_1, Key::KEY_2, Key::KEY_3, Key::KEY_4, Key::KEY_5, Key::KEY_6, Key::KEY_7, Key::KEY_8, Key::KEY_9,
+        Key::KEY_ESC, Key::KEY_TAB, Key::KEY_SPACE, Key::KEY_ENTER, Key::KEY_BACKSPACE, Key::KEY_DELETE,
+        Key::KEY_LEFT, Key::KEY_RIGHT, Key::KEY_UP, Key::KEY_DOWN,
+        Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT, Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL,
+        Key::KEY_LEFTALT, Key::KEY_RIGHTALT, Key::KEY_LEFTMETA, Key::KEY_RIGHTMETA,
+        Key::KEY_COMMA, Key::KEY_DOT, Key::KEY_SLASH, Key::KEY_SEMICOLON, Key::KEY_APOSTROPHE, Key::KEY_GRAVE,
+        Key::KEY_LEFTBRACE, Key::KEY_RIGHTBRACE, Key::KEY_BACKSLASH, Key::KEY_MINUS, Key::KEY_EQUAL, Key::KEY_102ND,
+        Key::KEY_F1, Key::KEY_F2, Key::KEY_F3, Key::KEY_F4, Key::KEY_F5, Key::KEY_F6, Key::KEY_F7, Key::KEY_F8, Key::KEY_F9, Key::KEY_F10, Key::KEY_F11, Key::KEY_F12,
+        Key::KEY_HOME, Key::KEY_END, Key::KEY_PAGEUP, Key::KEY_PAGEDOWN, Key::KEY_INSERT,
+        Key::KEY_CAPSLOCK, Key::KEY_NUMLOCK, Key::KEY_SCROLLLOCK, Key::KEY_SYSRQ, Key::KEY_PAUSE, Key::KEY_COMPOSE,
+        Key::KEY_KP0, Key::KEY_KP1, Key::KEY_KP2, Key::KEY_KP3, Key::KEY_KP4, Key::KEY_KP5, Key::KEY_KP6, Key::KEY_KP7, Key::KEY_KP8, Key::KEY_KP9,
+        Key::KEY_KPSLASH, Key::KEY_KPASTERISK, Key::KEY_KPMINUS, Key::KEY_KPPLUS, Key::KEY_KPDOT, Key::KEY_KPEQUAL, Key::KEY_KPENTER,
+        Key::KEY_PLAYPAUSE, Key::KEY_STOPCD, Key::KEY_NEXTSONG, Key::KEY_PREVIOUSSONG,
+        Key::KEY_VOLUMEUP, Key::KEY_VOLUMEDOWN, Key::KEY_MUTE,
+    ];
+
+    #[test]
+    fn every_key_is_distinct() {
+        let mut seen = HashSet::new();
+        for key in KEYS {
+            let code = linuxkernel_to_KeyCode(key.code());
+            assert_ne!(code, KeyCode::UNKNOWN, "{:?} isn't mapped", key);
+            assert!(seen.insert(code), "{:?} maps to {:?}, which another key already uses", key, code);
+        }
+    }
+    #[test]
+    fn both_shift_keys() {
+        let mut held = HeldModifiers::new();
+        held.update(KeyCode::SHIFT_LEFT, true);
+        held.update(KeyCode::SHIFT_RIGHT, true);
+        held.update(KeyCode::SHIFT_LEFT, false);
+        assert!(held.state().shift);
+        held.update(KeyCode::SHIFT_RIGHT, false);
+        assert!(!held.state().shift);
+        // ctrl-s
+        held.update(KeyCode::CONTROL_LEFT, true);
+        held.update(KeyCode::LETTER_S, true);
+        let mods = held.state();
+        assert!(mods.ctrl && !mods.shift && !mods.alt && !mods.meta);
+    }
+    #[test]
+    fn hi_res_wheel() {
+        let mut wheel = HiResWheel::default();
+        assert_eq!(wheel.turn(60), 0);
+        assert_eq!(wheel.turn(60), 1);
+        assert_eq!(wheel.turn(240), 2);
+        assert_eq!(wheel.turn(-30), 0);
+        assert_eq!(wheel.turn(-90), -1);
+        assert!(wheel.seen);
+    }
+    #[test]
+    fn mouse_buttons() {
+        assert_eq!(linuxkernel_to_mouse_button(Key::BTN_LEFT), Some(MouseButton::Primary));
+        assert_eq!(linuxkernel_to_mouse_button(Key::BTN_RIGHT), Some(MouseButton::Secondary));
+        assert_eq!(linuxkernel_to_mouse_button(Key::BTN_MIDDLE), Some(MouseButton::Middle));
+        assert_eq!(linuxkernel_to_mouse_button(Key::BTN_SIDE), Some(MouseButton::Back));
+        assert_eq!(linuxkernel_to_mouse_button(Key::BTN_EXTRA), Some(MouseButton::Forward));
+        assert_eq!(linuxkernel_to_mouse_button(Key::KEY_A), None);
+    }
+}
@@ -1,7 +1,8 @@
 use std::sync::mpsc::Sender;
 use common::{ARGBColor, IncomingMessage, Rect, BLACK, Point};
 use gfx::graphics::{GFXBuffer, PixelLayout};
-use std::sync::{Arc, mpsc};
+use common::keymap::KeyboardLayout;
+use std::sync::{Arc, Mutex, mpsc};
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use framebuffer::{Framebuffer, KdMode, VarScreeninfo};
@@ -17,6 +18,8 @@ pub struct Plat {
     surf:Surf,
     screen_size:Rect,
     layout:PixelLayout,
+    // shared with the input threads
+    keyboard:Arc<Mutex<KeyboardLayout>>,
 }
 
 pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, width:u32, height:u32, scale:u32) -> Result<Plat, String> {
@@ -31,14 +34,15 @@ pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, widt
     surf.buf.clear(&ARGBColor::new_rgb(0,255,200));
     surf.sync();
     let layout = surf.buf.layout.clone();
+    let keyboard = Arc::new(Mutex::new(KeyboardLayout::us()));
 
-    if let Some(mut keyboard) = input::find_keyboard() {
-        input::setup_evdev_watcher(keyboard, stop.clone(), sender.clone(), screen_size);
+    if let Some(mut keyboard_device) = input::find_keyboard() {
+        input::setup_evdev_watcher(keyboard_device, stop.clone(), sender.clone(), screen_size, keyboard.clone());
     } else {
         println!("Couldn't find the keyboard. skipping!");
     }
     if let Some(mut mouse) = input::find_mouse() {
-        input::setup_evdev_watcher(mouse, stop.clone(), sender.clone(), screen_size);
+        input::setup_evdev_watcher(mouse, stop.clone(), sender.clone(), screen_size, keyboard.clone());
     } else {
         println!("Couldn't find the mouse. skipping!");
     }
@@ -49,6 +53,7 @@ pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, widt
         surf:surf,
         screen_size: screen_size,
         layout:layout,
+        keyboard,
     });
 }
 
@@ -103,5 +108,8 @@ impl Plat {
     pub fn get_preferred_pixel_layout(&self) -> &PixelLayout{
         &self.layout
     }
+    pub fn set_keyboard_layout(&mut self, keyboard:KeyboardLayout) {
+        *self.keyboard.lock().unwrap() = keyboard;
+    }
 }
 
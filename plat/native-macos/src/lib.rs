@@ -5,14 +5,17 @@ use log::{error};
 use std::sync::mpsc::Sender;
 use std::time::{SystemTime, UNIX_EPOCH};
 use sdl2::event::Event;
+use sdl2::mouse::MouseWheelDirection;
 use sdl2::render::{Texture, TextureAccess, TextureCreator, WindowCanvas};
 use sdl2::video::{WindowContext};
 use sdl2::{EventPump};
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect as SDLRect;
 use uuid::Uuid;
-use common::events::{KeyDownEvent, ModifierState, MouseButton, MouseDownEvent, MouseMoveEvent, MouseUpEvent};
+use common::events::{KeyDownEvent, MouseButton, MouseDownEvent, MouseMoveEvent, MouseUpEvent, MouseWheelEvent};
 use common::{APICommand, IncomingMessage};
+use common::events::TextInputEvent;
+use common::keymap::KeyboardLayout;
 use gfx::graphics::{ARGBColor, GFXBuffer, PixelLayout, Point, Rect};
 use gfx::graphics::Rect as CommonRect;
 
@@ -26,6 +29,7 @@ pub struct Plat {
     pub creator: TextureCreator<WindowContext>,
     pub sender: Sender<IncomingMessage>,
     pub stop: Arc<AtomicBool>,
+    pub keyboard: KeyboardLayout,
 }
 
 pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, w:u32, h:u32, scale:u32) -> Result<Plat, String> {
@@ -47,6 +51,7 @@ pub fn make_plat<'a>(stop:Arc<AtomicBool>, sender: Sender<IncomingMessage>, w:u3
         canvas,
         event_pump:sdl_context.event_pump()?,
         sender,
+        keyboard: KeyboardLayout::us(),
     })
 }
 
@@ -55,6 +60,10 @@ impl Plat {
         &PixelLayout::ARGB()
     }
 
+    pub fn set_keyboard_layout(&mut self, keyboard: KeyboardLayout) {
+        self.keyboard = keyboard;
+    }
+
     pub fn get_screen_bounds(&self) -> CommonRect {
         let r2 = self.canvas.viewport();
         CommonRect {
@@ -78,11 +87,13 @@ impl Plat {
                 //     self.stop.store(true, Ordering::Relaxed);
                 //     break;
                 // },
-                Event::KeyDown {keycode,keymod,scancode,..} => {
-                    if let Some(kk) = keycode {
-                        // println!("keycode is {}",kk);
+                Event::KeyDown {keymod,scancode,repeat,..} => {
+                    if let Some(sc) = scancode {
                         // println!("scancode is {:?}",scancode);
                         // println!("mod is {}",keymod);
+                        // sdl's keycodes already have the OS layout applied. use our own.
+                        let mods = sdl_util::sdl_to_mods(keymod);
+                        let (key, text) = self.keyboard.translate(sdl_util::sdl_scancode_to_common(sc), &mods);
                         let cmd = IncomingMessage {
                             trace: false,
                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
@@ -90,16 +101,35 @@ impl Plat {
                             command: APICommand::KeyDown(KeyDownEvent{
                                 app_id: Default::default(),
                                 window_id: Default::default(),
-                                key: sdl_util::sdl_to_common(kk, keymod),
-                                mods: ModifierState::empty(),
-                            })
+                                key,
+                                mods,
+                                repeat,
+                            }),
+                            request_id: None,
                         };
                         if let Err(e) = self.sender.send(cmd) {
                             error!("error sending {}",e);
                         }
+                        if let Some(text) = text {
+                            let cmd = IncomingMessage {
+                                trace: false,
+                                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                source: Default::default(),
+                                command: APICommand::TextInput(TextInputEvent {
+                                    app_id: Default::default(),
+                                    window_id: Default::default(),
+                                    text,
+                                }),
+                                request_id: None,
+                            };
+                            if let Err(e) = self.sender.send(cmd) {
+                                error!("error sending {}",e);
+                            }
+                        }
                     }
                 },
                 Event::MouseButtonDown { x, y,mouse_btn, .. } => {
+                    let Some(button) = sdl_util::sdl_to_mouse_button(mouse_btn) else { continue };
                     let (x, y) = scale_mouse_to_canvas(&self.canvas,x,y);
                     let cmd = IncomingMessage {
                         trace: false,
@@ -109,16 +139,19 @@ impl Plat {
                             app_id: Default::default(),
                             window_id: Default::default(),
                             original_timestamp: 0,
-                            button: MouseButton::Primary,
+                            button,
                             x,
-                            y
-                        })
+                            y,
+                            clicks: 1,
+                        }),
+                        request_id: None,
                     };
                     if let Err(e) = self.sender.send(cmd) {
                         error!("error sending {}",e);
                     }
                 },
                 Event::MouseButtonUp {x,y,mouse_btn,..} =>  {
+                    let Some(button) = sdl_util::sdl_to_mouse_button(mouse_btn) else { continue };
                     let (x, y) = scale_mouse_to_canvas(&self.canvas,x,y);
                     let cmd = IncomingMessage {
                         trace: false,
@@ -128,10 +161,11 @@ impl Plat {
                             app_id: Default::default(),
                             window_id: Default::default(),
                             original_timestamp: 0,
-                            button: MouseButton::Primary,
+                            button,
                             x,
                             y
-                        })
+                        }),
+                        request_id: None,
                     };
                     if let Err(e) = self.sender.send(cmd) {
                         error!("error sending {}",e);
@@ -152,13 +186,40 @@ impl Plat {
                             button: MouseButton::Primary,
                             x,
                             y,
-                        })
+                        }),
+                        request_id: None,
                     };
                     // info!("about to send out {:?}",cmd);
                     if let Err(e) = self.sender.send(cmd) {
                         error!("error sending mouse motion out {:?}",e);
                     }
                 }
+                Event::MouseWheel { x: dx, y: dy, direction, mouse_x, mouse_y, .. } => {
+                    let (x, y) = scale_mouse_to_canvas(&self.canvas,mouse_x,mouse_y);
+                    // sdl counts scrolling up as positive, unless the system flips it
+                    let (dx, dy) = match direction {
+                        MouseWheelDirection::Flipped => (-dx, dy),
+                        _ => (dx, -dy),
+                    };
+                    let cmd = IncomingMessage {
+                        trace: false,
+                        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                        source: Default::default(),
+                        command: APICommand::MouseWheel(MouseWheelEvent {
+                            app_id: Default::default(),
+                            window_id: Default::default(),
+                            original_timestamp: 0,
+                            x,
+                            y,
+                            dx,
+                            dy,
+                        }),
+                        request_id: None,
+                    };
+                    if let Err(e) = self.sender.send(cmd) {
+                        error!("error sending {}",e);
+                    }
+                }
                 _ => {}
             }
         }
@@ -1,5 +1,7 @@
+use common::events::{ModifierState, MouseButton};
 use common::generated::KeyCode;
-use sdl2::keyboard::{Keycode, Mod};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::mouse::MouseButton as SDLMouseButton;
 
 
 pub fn add(left: u64, right: u64) -> u64 {
@@ -106,6 +108,53 @@ pub fn sdl_to_common(kc: Keycode, keymod: Mod) -> KeyCode {
         Keycode::Minus => KeyCode::MINUS,
         Keycode::Equals => KeyCode::EQUALS,
         Keycode::Space => KeyCode::SPACE,
+        Keycode::F1 => KeyCode::F1,
+        Keycode::F2 => KeyCode::F2,
+        Keycode::F3 => KeyCode::F3,
+        Keycode::F4 => KeyCode::F4,
+        Keycode::F5 => KeyCode::F5,
+        Keycode::F6 => KeyCode::F6,
+        Keycode::F7 => KeyCode::F7,
+        Keycode::F8 => KeyCode::F8,
+        Keycode::F9 => KeyCode::F9,
+        Keycode::F10 => KeyCode::F10,
+        Keycode::F11 => KeyCode::F11,
+        Keycode::F12 => KeyCode::F12,
+        Keycode::Home => KeyCode::HOME,
+        Keycode::End => KeyCode::END,
+        Keycode::PageUp => KeyCode::PAGE_UP,
+        Keycode::PageDown => KeyCode::PAGE_DOWN,
+        Keycode::Insert => KeyCode::INSERT,
+        Keycode::CapsLock => KeyCode::CAPS_LOCK,
+        Keycode::NumLockClear => KeyCode::NUM_LOCK,
+        Keycode::ScrollLock => KeyCode::SCROLL_LOCK,
+        Keycode::PrintScreen => KeyCode::PRINT_SCREEN,
+        Keycode::Pause => KeyCode::PAUSE,
+        Keycode::Application => KeyCode::CONTEXT_MENU,
+        Keycode::Kp0 => KeyCode::NUMPAD_0,
+        Keycode::Kp1 => KeyCode::NUMPAD_1,
+        Keycode::Kp2 => KeyCode::NUMPAD_2,
+        Keycode::Kp3 => KeyCode::NUMPAD_3,
+        Keycode::Kp4 => KeyCode::NUMPAD_4,
+        Keycode::Kp5 => KeyCode::NUMPAD_5,
+        Keycode::Kp6 => KeyCode::NUMPAD_6,
+        Keycode::Kp7 => KeyCode::NUMPAD_7,
+        Keycode::Kp8 => KeyCode::NUMPAD_8,
+        Keycode::Kp9 => KeyCode::NUMPAD_9,
+        Keycode::KpDivide => KeyCode::NUMPAD_DIVIDE,
+        Keycode::KpMultiply => KeyCode::NUMPAD_MULTIPLY,
+        Keycode::KpMinus => KeyCode::NUMPAD_SUBTRACT,
+        Keycode::KpPlus => KeyCode::NUMPAD_ADD,
+        Keycode::KpPeriod => KeyCode::NUMPAD_DECIMAL,
+        Keycode::KpEquals => KeyCode::NUMPAD_EQUALS,
+        Keycode::KpEnter => KeyCode::NUMPAD_ENTER,
+        Keycode::AudioPlay => KeyCode::MEDIA_PLAY_PAUSE,
+        Keycode::AudioStop => KeyCode::MEDIA_STOP,
+        Keycode::AudioNext => KeyCode::MEDIA_NEXT,
+        Keycode::AudioPrev => KeyCode::MEDIA_PREVIOUS,
+        Keycode::VolumeUp => KeyCode::VOLUME_UP,
+        Keycode::VolumeDown => KeyCode::VOLUME_DOWN,
+        Keycode::Mute | Keycode::AudioMute => KeyCode::VOLUME_MUTE,
         _ => {
             KeyCode::UNKNOWN
         }
@@ -114,150 +163,145 @@ pub fn sdl_to_common(kc: Keycode, keymod: Mod) -> KeyCode {
     code
 }
 
-pub fn sdl_to_common_letter(kc: Keycode, keymod: Mod) -> String {
-    if keymod.contains(Mod::LSHIFTMOD) || keymod.contains(Mod::RSHIFTMOD) {
-        match kc {
-        Keycode::A => "A".to_string(),
-        Keycode::B => "B".to_string(),
-        Keycode::C => "C".to_string(),
-        Keycode::D => "D".to_string(),
-        Keycode::E => "E".to_string(),
-        Keycode::F => "F".to_string(),
-        Keycode::G => "G".to_string(),
-        Keycode::H => "H".to_string(),
-        Keycode::I => "I".to_string(),
-        Keycode::J => "J".to_string(),
-        Keycode::K => "K".to_string(),
-        Keycode::L => "L".to_string(),
-        Keycode::M => "M".to_string(),
-        Keycode::N => "N".to_string(),
-        Keycode::O => "O".to_string(),
-        Keycode::P => "P".to_string(),
-        Keycode::Q => "Q".to_string(),
-        Keycode::R => "R".to_string(),
-        Keycode::S => "S".to_string(),
-        Keycode::T => "T".to_string(),
-        Keycode::U => "U".to_string(),
-        Keycode::V => "V".to_string(),
-        Keycode::W => "W".to_string(),
-        Keycode::X => "X".to_string(),
-        Keycode::Y => "Y".to_string(),
-        Keycode::Z => "Z".to_string(),
-        Keycode::Num0 => ")".to_string(),
-        Keycode::Num1 => "!".to_string(),
-        Keycode::Num2 => "@".to_string(),
-        Keycode::Num3 => "#".to_string(),
-        Keycode::Num4 => "$".to_string(),
-        Keycode::Num5 => "%".to_string(),
-        Keycode::Num6 => "^".to_string(),
-        Keycode::Num7 => "&".to_string(),
-        Keycode::Num8 => "*".to_string(),
-        Keycode::Num9 => "(".to_string(),
-        Keycode::Comma => "<".to_string(),
-        Keycode::Less => "<".to_string(),
-        Keycode::Period => ">".to_string(),
-        Keycode::Greater => ">".to_string(),
-        Keycode::Slash => "?".to_string(),
-        Keycode::Question => "?".to_string(),
-        Keycode::Semicolon => ":".to_string(),
-        Keycode::Colon => ":".to_string(),
-        Keycode::Quote => "\"".to_string(),
-        Keycode::Quotedbl => "\"".to_string(),
-        Keycode::Backquote => "~".to_string(),
-        Keycode::Exclaim => "!".to_string(),
-        Keycode::At => "@".to_string(),
-        Keycode::Hash => "#".to_string(),
-        Keycode::Dollar => "$".to_string(),
-        Keycode::Percent => "%".to_string(),
-        Keycode::Caret => "^".to_string(),
-        Keycode::Ampersand => "&".to_string(),
-        Keycode::Asterisk => "*".to_string(),
-        Keycode::LeftParen => "(".to_string(),
-        Keycode::RightParen => ")".to_string(),
-        Keycode::Underscore => "_".to_string(),
-        Keycode::Plus => "+".to_string(),
-        Keycode::LeftBracket => "{".to_string(),
-        Keycode::RightBracket => "}".to_string(),
-        Keycode::KpLeftBrace => "{".to_string(),
-        Keycode::KpRightBrace => "}".to_string(),
-        Keycode::Backslash => "|".to_string(),
-        Keycode::KpVerticalBar => "|".to_string(),
-        Keycode::Minus => "_".to_string(),
-        Keycode::Equals => "+".to_string(),
-        Keycode::Space => " ".to_string(),
-        _ => "".to_string()
-        }
-    } else {
-        match kc {
-        Keycode::A => "a".to_string(),
-        Keycode::B => "b".to_string(),
-        Keycode::C => "c".to_string(),
-        Keycode::D => "d".to_string(),
-        Keycode::E => "e".to_string(),
-        Keycode::F => "f".to_string(),
-        Keycode::G => "g".to_string(),
-        Keycode::H => "h".to_string(),
-        Keycode::I => "i".to_string(),
-        Keycode::J => "j".to_string(),
-        Keycode::K => "k".to_string(),
-        Keycode::L => "l".to_string(),
-        Keycode::M => "m".to_string(),
-        Keycode::N => "n".to_string(),
-        Keycode::O => "o".to_string(),
-        Keycode::P => "p".to_string(),
-        Keycode::Q => "q".to_string(),
-        Keycode::R => "r".to_string(),
-        Keycode::S => "s".to_string(),
-        Keycode::T => "t".to_string(),
-        Keycode::U => "u".to_string(),
-        Keycode::V => "v".to_string(),
-        Keycode::W => "w".to_string(),
-        Keycode::X => "x".to_string(),
-        Keycode::Y => "y".to_string(),
-        Keycode::Z => "z".to_string(),
-        Keycode::Num0 => "0".to_string(),
-        Keycode::Num1 => "1".to_string(),
-        Keycode::Num2 => "2".to_string(),
-        Keycode::Num3 => "3".to_string(),
-        Keycode::Num4 => "4".to_string(),
-        Keycode::Num5 => "5".to_string(),
-        Keycode::Num6 => "6".to_string(),
-        Keycode::Num7 => "7".to_string(),
-        Keycode::Num8 => "8".to_string(),
-        Keycode::Num9 => "9".to_string(),
-        Keycode::Comma => ",".to_string(),
-        Keycode::Less => "<".to_string(),
-        Keycode::Period => ".".to_string(),
-        Keycode::Greater => ">".to_string(),
-        Keycode::Slash => "/".to_string(),
-        Keycode::Question => "?".to_string(),
-        Keycode::Semicolon => ";".to_string(),
-        Keycode::Colon => ":".to_string(),
-        Keycode::Quote => "\'".to_string(),
-        Keycode::Quotedbl => "\"".to_string(),
-        Keycode::Backquote => "`".to_string(),
-        Keycode::Exclaim => "!".to_string(),
-        Keycode::At => "@".to_string(),
-        Keycode::Hash => "#".to_string(),
-        Keycode::Dollar => "$".to_string(),
-        Keycode::Percent => "%".to_string(),
-        Keycode::Caret => "^".to_string(),
-        Keycode::Ampersand => "&".to_string(),
-        Keycode::Asterisk => "*".to_string(),
-        Keycode::LeftParen => "(".to_string(),
-        Keycode::RightParen => ")".to_string(),
-        Keycode::Underscore => "_".to_string(),
-        Keycode::Plus => "+".to_string(),
-        Keycode::LeftBracket => "[".to_string(),
-        Keycode::RightBracket => "]".to_string(),
-        Keycode::KpLeftBrace => "{".to_string(),
-        Keycode::KpRightBrace => "}".to_string(),
-        Keycode::Backslash => "\\".to_string(),
-        Keycode::KpVerticalBar => "|".to_string(),
-        Keycode::Minus => "-".to_string(),
-        Keycode::Equals => "=".to_string(),
-        Keycode::Space => " ".to_string(),
-            _ => "".to_string()
-        }
+// which modifiers sdl says are held down
+pub fn sdl_to_mods(keymod: Mod) -> ModifierState {
+    ModifierState {
+        shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+        alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        meta: keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD),
+        altgr: keymod.intersects(Mod::RALTMOD | Mod::MODEMOD),
+    }
+}
+
+// None for buttons we don't know
+pub fn sdl_to_mouse_button(btn: SDLMouseButton) -> Option<MouseButton> {
+    match btn {
+        SDLMouseButton::Left => Some(MouseButton::Primary),
+        SDLMouseButton::Right => Some(MouseButton::Secondary),
+        SDLMouseButton::Middle => Some(MouseButton::Middle),
+        SDLMouseButton::X1 => Some(MouseButton::Back),
+        SDLMouseButton::X2 => Some(MouseButton::Forward),
+        SDLMouseButton::Unknown => None,
     }
-}
\ No newline at end of file
+}
+
+// the key at this position on a US keyboard. keyboard layouts pick what it means.
+pub fn sdl_scancode_to_common(sc: Scancode) -> KeyCode {
+    match sc {
+        Scancode::A => KeyCode::LETTER_A,
+        Scancode::B => KeyCode::LETTER_B,
+        Scancode::C => KeyCode::LETTER_C,
+        Scancode::D => KeyCode::LETTER_D,
+        Scancode::E => KeyCode::LETTER_E,
+        Scancode::F => KeyCode::LETTER_F,
+        Scancode::G => KeyCode::LETTER_G,
+        Scancode::H => KeyCode::LETTER_H,
+        Scancode::I => KeyCode::LETTER_I,
+        Scancode::J => KeyCode::LETTER_J,
+        Scancode::K => KeyCode::LETTER_K,
+        Scancode::L => KeyCode::LETTER_L,
+        Scancode::M => KeyCode::LETTER_M,
+        Scancode::N => KeyCode::LETTER_N,
+        Scancode::O => KeyCode::LETTER_O,
+        Scancode::P => KeyCode::LETTER_P,
+        Scancode::Q => KeyCode::LETTER_Q,
+        Scancode::R => KeyCode::LETTER_R,
+        Scancode::S => KeyCode::LETTER_S,
+        Scancode::T => KeyCode::LETTER_T,
+        Scancode::U => KeyCode::LETTER_U,
+        Scancode::V => KeyCode::LETTER_V,
+        Scancode::W => KeyCode::LETTER_W,
+        Scancode::X => KeyCode::LETTER_X,
+        Scancode::Y => KeyCode::LETTER_Y,
+        Scancode::Z => KeyCode::LETTER_Z,
+        Scancode::Num0 => KeyCode::DIGIT_0,
+        Scancode::Num1 => KeyCode::DIGIT_1,
+        Scancode::Num2 => KeyCode::DIGIT_2,
+        Scancode::Num3 => KeyCode::DIGIT_3,
+        Scancode::Num4 => KeyCode::DIGIT_4,
+        Scancode::Num5 => KeyCode::DIGIT_5,
+        Scancode::Num6 => KeyCode::DIGIT_6,
+        Scancode::Num7 => KeyCode::DIGIT_7,
+        Scancode::Num8 => KeyCode::DIGIT_8,
+        Scancode::Num9 => KeyCode::DIGIT_9,
+        Scancode::Return => KeyCode::ENTER,
+        Scancode::Escape => KeyCode::ESCAPE,
+        Scancode::Backspace => KeyCode::BACKSPACE,
+        Scancode::Tab => KeyCode::TAB,
+        Scancode::Space => KeyCode::SPACE,
+        Scancode::Minus => KeyCode::MINUS,
+        Scancode::Equals => KeyCode::EQUALS,
+        Scancode::LeftBracket => KeyCode::BRACKET_LEFT,
+        Scancode::RightBracket => KeyCode::BRACKET_RIGHT,
+        Scancode::Backslash | Scancode::NonUsHash => KeyCode::BACKSLASH,
+        Scancode::Semicolon => KeyCode::SEMICOLON,
+        Scancode::Apostrophe => KeyCode::QUOTE,
+        Scancode::Grave => KeyCode::BACKQUOTE,
+        Scancode::Comma => KeyCode::COMMA,
+        Scancode::Period => KeyCode::PERIOD,
+        Scancode::Slash => KeyCode::SLASH,
+        Scancode::NonUsBackslash => KeyCode::INTL_BACKSLASH,
+        Scancode::F1 => KeyCode::F1,
+        Scancode::F2 => KeyCode::F2,
+        Scancode::F3 => KeyCode::F3,
+        Scancode::F4 => KeyCode::F4,
+        Scancode::F5 => KeyCode::F5,
+        Scancode::F6 => KeyCode::F6,
+        Scancode::F7 => KeyCode::F7,
+        Scancode::F8 => KeyCode::F8,
+        Scancode::F9 => KeyCode::F9,
+        Scancode::F10 => KeyCode::F10,
+        Scancode::F11 => KeyCode::F11,
+        Scancode::F12 => KeyCode::F12,
+        Scancode::Insert => KeyCode::INSERT,
+        Scancode::Delete => KeyCode::DELETE,
+        Scancode::Home => KeyCode::HOME,
+        Scancode::End => KeyCode::END,
+        Scancode::PageUp => KeyCode::PAGE_UP,
+        Scancode::PageDown => KeyCode::PAGE_DOWN,
+        Scancode::Left => KeyCode::ARROW_LEFT,
+        Scancode::Right => KeyCode::ARROW_RIGHT,
+        Scancode::Up => KeyCode::ARROW_UP,
+        Scancode::Down => KeyCode::ARROW_DOWN,
+        Scancode::CapsLock => KeyCode::CAPS_LOCK,
+        Scancode::NumLockClear => KeyCode::NUM_LOCK,
+        Scancode::ScrollLock => KeyCode::SCROLL_LOCK,
+        Scancode::PrintScreen => KeyCode::PRINT_SCREEN,
+        Scancode::Pause => KeyCode::PAUSE,
+        Scancode::Application => KeyCode::CONTEXT_MENU,
+        Scancode::Kp0 => KeyCode::NUMPAD_0,
+        Scancode::Kp1 => KeyCode::NUMPAD_1,
+        Scancode::Kp2 => KeyCode::NUMPAD_2,
+        Scancode::Kp3 => KeyCode::NUMPAD_3,
+        Scancode::Kp4 => KeyCode::NUMPAD_4,
+        Scancode::Kp5 => KeyCode::NUMPAD_5,
+        Scancode::Kp6 => KeyCode::NUMPAD_6,
+        Scancode::Kp7 => KeyCode::NUMPAD_7,
+        Scancode::Kp8 => KeyCode::NUMPAD_8,
+        Scancode::Kp9 => KeyCode::NUMPAD_9,
+        Scancode::KpDivide => KeyCode::NUMPAD_DIVIDE,
+        Scancode::KpMultiply => KeyCode::NUMPAD_MULTIPLY,
+        Scancode::KpMinus => KeyCode::NUMPAD_SUBTRACT,
+        Scancode::KpPlus => KeyCode::NUMPAD_ADD,
+        Scancode::KpPeriod => KeyCode::NUMPAD_DECIMAL,
+        Scancode::KpEquals => KeyCode::NUMPAD_EQUALS,
+        Scancode::KpEnter => KeyCode::NUMPAD_ENTER,
+        Scancode::LShift => KeyCode::SHIFT_LEFT,
+        Scancode::RShift => KeyCode::SHIFT_RIGHT,
+        Scancode::LCtrl => KeyCode::CONTROL_LEFT,
+        Scancode::RCtrl => KeyCode::CONTROL_RIGHT,
+        Scancode::LAlt => KeyCode::ALT_LEFT,
+        Scancode::RAlt => KeyCode::ALT_RIGHT,
+        Scancode::LGui => KeyCode::META_LEFT,
+        Scancode::RGui => KeyCode::META_RIGHT,
+        Scancode::AudioPlay => KeyCode::MEDIA_PLAY_PAUSE,
+        Scancode::AudioStop => KeyCode::MEDIA_STOP,
+        Scancode::AudioNext => KeyCode::MEDIA_NEXT,
+        Scancode::AudioPrev => KeyCode::MEDIA_PREVIOUS,
+        Scancode::VolumeUp => KeyCode::VOLUME_UP,
+        Scancode::VolumeDown => KeyCode::VOLUME_DOWN,
+        Scancode::Mute | Scancode::AudioMute => KeyCode::VOLUME_MUTE,
+        _ => KeyCode::UNKNOWN,
+    }
+}
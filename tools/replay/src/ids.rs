@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use serde_json::Value;
+use uuid::Uuid;
+
+// a fresh central hands out new app, window and object ids. this maps the recorded ones to the live ones.
+pub type IdMap = HashMap<String, String>;
+
+// the APICommand variant of a message, as json
+pub fn variant_name(msg: &Value) -> String {
+    match msg.get("command") {
+        Some(Value::String(name)) => name.clone(),
+        Some(Value::Object(obj)) => obj.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+// central makes up ids with a uuid in them, either bare or at the end, like attachment_<uuid>
+fn is_id(s: &str) -> bool {
+    s.len() >= 36 && s.get(s.len() - 36..).is_some_and(|tail| Uuid::parse_str(tail).is_ok())
+}
+
+// when something happened, which is never the same twice
+fn is_timestamp(key: &str) -> bool {
+    key.contains("timestamp")
+}
+
+// compare a recorded message from central with the live one it corresponds to.
+// ids are the only strings central makes up, so an id which differs has a new live one.
+pub fn learn(recorded: &Value, live: &Value, ids: &mut IdMap) {
+    match (recorded, live) {
+        (Value::Object(rec), Value::Object(liv)) => {
+            for (key, rec_val) in rec {
+                if let Some(liv_val) = liv.get(key) {
+                    learn(rec_val, liv_val, ids);
+                }
+            }
+        }
+        (Value::Array(rec), Value::Array(liv)) => {
+            for (rec_val, liv_val) in rec.iter().zip(liv.iter()) {
+                learn(rec_val, liv_val, ids);
+            }
+        }
+        (Value::String(rec), Value::String(liv)) if rec != liv && is_id(rec) && is_id(liv) => {
+            ids.insert(rec.clone(), liv.clone());
+        }
+        _ => {}
+    }
+}
+
+// where a live message from central differs from the recorded one, apart from ids and timestamps
+pub fn differences(recorded: &Value, live: &Value) -> Vec<String> {
+    let mut found = vec![];
+    differ(recorded, live, "", &mut found);
+    found
+}
+
+fn differ(recorded: &Value, live: &Value, path: &str, found: &mut Vec<String>) {
+    match (recorded, live) {
+        (Value::Object(rec), Value::Object(liv)) => {
+            for key in rec.keys().chain(liv.keys().filter(|k| !rec.contains_key(*k))) {
+                if is_timestamp(key) {
+                    continue;
+                }
+                let path = format!("{}/{}", path, key);
+                match (rec.get(key), liv.get(key)) {
+                    (Some(rec_val), Some(liv_val)) => differ(rec_val, liv_val, &path, found),
+                    (Some(_), None) => found.push(format!("{} is missing", path)),
+                    (None, _) => found.push(format!("{} wasn't in the recording", path)),
+                }
+            }
+        }
+        (Value::Array(rec), Value::Array(liv)) if rec.len() == liv.len() => {
+            for (n, (rec_val, liv_val)) in rec.iter().zip(liv.iter()).enumerate() {
+                differ(rec_val, liv_val, &format!("{}/{}", path, n), found);
+            }
+        }
+        (Value::Array(rec), Value::Array(liv)) => {
+            found.push(format!("{} has {} items, not {}", path, liv.len(), rec.len()));
+        }
+        (Value::String(rec), Value::String(liv)) if is_id(rec) && is_id(liv) => {}
+        (rec, liv) if rec != liv => found.push(format!("{} is {}, not {}", path, liv, rec)),
+        _ => {}
+    }
+}
+
+// swap every recorded id for its live one
+pub fn rewrite(value: &mut Value, ids: &IdMap) {
+    match value {
+        Value::Object(obj) => obj.values_mut().for_each(|v| rewrite(v, ids)),
+        Value::Array(arr) => arr.iter_mut().for_each(|v| rewrite(v, ids)),
+        Value::String(s) => {
+            if let Some(live) = ids.get(s) {
+                *s = live.clone();
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::ids::{differences, IdMap, learn, rewrite, variant_name};
+
+    const APP_1:&str = "6a0f8a52-6f0c-4c1c-9a8e-0d1b6f3b1a01";
+    const WIN_1:&str = "6a0f8a52-6f0c-4c1c-9a8e-0d1b6f3b1a02";
+    const APP_2:&str = "d7c2e9a0-3b5f-4e3e-8f7a-5c9b2a4d6e01";
+    const WIN_2:&str = "d7c2e9a0-3b5f-4e3e-8f7a-5c9b2a4d6e02";
+
+    #[test]
+    fn map_window_ids() {
+        let recorded = json!({"source":"0", "command":{"OpenWindowResponse":{"app_id":APP_1,"window_id":WIN_1,"window_title":"hi"}}, "timestamp_usec":5});
+        let live = json!({"source":"0", "command":{"OpenWindowResponse":{"app_id":APP_2,"window_id":WIN_2,"window_title":"hi"}}, "timestamp_usec":9});
+        assert_eq!(variant_name(&live), "OpenWindowResponse");
+        let mut ids = IdMap::new();
+        learn(&recorded, &live, &mut ids);
+        assert_eq!(ids.len(), 2);
+        assert!(differences(&recorded, &live).is_empty());
+
+        let mut click = json!({"command":{"MouseDown":{"app_id":APP_1,"window_id":WIN_1,"x":5}}});
+        rewrite(&mut click, &ids);
+        assert_eq!(click, json!({"command":{"MouseDown":{"app_id":APP_2,"window_id":WIN_2,"x":5}}}));
+        assert_eq!(variant_name(&json!({"command":"SystemShutdown"})), "SystemShutdown");
+    }
+
+    #[test]
+    fn payloads_must_match() {
+        let recorded = json!({"command":{"WindowResized":{"app_id":APP_1,"size":{"w":100,"h":50}}}, "timestamp_usec":5});
+        let live = json!({"command":{"WindowResized":{"app_id":APP_2,"size":{"w":120,"h":50}}}, "timestamp_usec":9});
+        assert_eq!(differences(&recorded, &live), vec![String::from("/command/WindowResized/size/w is 120, not 100")]);
+
+        let recorded = json!({"command":{"DBQueryResponse":{"results":[{"id":"attachment_6a0f8a52-6f0c-4c1c-9a8e-0d1b6f3b1a03","title":"a"}]}}});
+        let live = json!({"command":{"DBQueryResponse":{"results":[{"id":"attachment_d7c2e9a0-3b5f-4e3e-8f7a-5c9b2a4d6e03","title":"b"}]}}});
+        assert_eq!(differences(&recorded, &live), vec![String::from("/command/DBQueryResponse/results/0/title is \"b\", not \"a\"")]);
+        let mut ids = IdMap::new();
+        learn(&recorded, &live, &mut ids);
+        // a title isn't an id
+        assert_eq!(ids.len(), 1);
+    }
+}
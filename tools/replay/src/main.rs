@@ -0,0 +1,382 @@
+use structopt::StructOpt;
+mod ids;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::Shutdown;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info, LevelFilter, set_logger, warn};
+use serde_json::Value;
+use uuid::Uuid;
+use common::{APICommand, DebugMessage, IncomingMessage};
+use common::journal::{Direction, JournalEntry, PeerKind, read_journal};
+use common::transport::{ClientStream, WM_SOCKET_ENV};
+use common::wire::{MessageReader, WireFormat, write_message};
+use cool_logger::CoolLogger;
+use crate::ids::{differences, IdMap, learn, rewrite, variant_name};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "replay", about = "plays a recording made with central --record back into a fresh central and headless window manager")]
+struct Cli {
+    #[structopt(parse(from_os_str))]
+    recording: PathBuf,
+    // the central binary to start
+    #[structopt(long, parse(from_os_str), default_value="./target/debug/central")]
+    central: PathBuf,
+    // the runner binary, which provides the headless window manager
+    #[structopt(long, parse(from_os_str), default_value="./target/debug/runner")]
+    runner: PathBuf,
+    // use the database the recording was made with, or the replies won't match
+    #[structopt(long, parse(from_os_str), default_value="db/test_data.json")]
+    database: PathBuf,
+    // don't keep the original timing. only wait for central to catch up with the recording.
+    #[structopt(long)]
+    fast: bool,
+    // how long to wait for something central sent in the recording before calling it missing
+    #[structopt(long, default_value="2000")]
+    timeout_ms: u64,
+}
+
+// a message from central, recorded or live, which hasn't been paired with its other half yet
+struct Sent {
+    // the recorded id of the connection it went to
+    connection: Uuid,
+    variant: String,
+    value: Value,
+}
+
+impl Sent {
+    fn new(connection: Uuid, msg: &IncomingMessage) -> Sent {
+        let value = serde_json::to_value(msg).unwrap();
+        Sent { connection, variant: variant_name(&value), value }
+    }
+}
+
+// Plays the app connections from a recording against a live headless window manager. The
+// recorded window manager's input is still fed to central, over a connection of its own, but
+// everything else on the window manager's side comes from the headless one. Only the messages
+// central sent to apps are compared. Debugger connections are skipped.
+struct Player {
+    socket_dir: PathBuf,
+    connections: HashMap<Uuid, ClientStream>,
+    tx: Sender<(Uuid, IncomingMessage)>,
+    rx: Receiver<(Uuid, IncomingMessage)>,
+    ids: IdMap,
+    // sent in the recording but not seen live yet
+    expected: Vec<Sent>,
+    // sent live but not found in the recording yet
+    unexpected: Vec<Sent>,
+    missing: Vec<Sent>,
+    // sent live, but not the same as in the recording
+    different: Vec<String>,
+    matched: usize,
+    timeout: Duration,
+}
+
+impl Player {
+    fn new(socket_dir: PathBuf, timeout: Duration) -> Player {
+        let (tx, rx) = mpsc::channel();
+        Player {
+            socket_dir,
+            connections: HashMap::new(),
+            tx,
+            rx,
+            ids: IdMap::new(),
+            expected: vec![],
+            unexpected: vec![],
+            missing: vec![],
+            different: vec![],
+            matched: 0,
+            timeout,
+        }
+    }
+
+    fn play(&mut self, entries: &[JournalEntry], fast: bool) -> bool {
+        let start = Instant::now();
+        // when the first message went out, and when it went out in the recording
+        let mut clock: Option<(Instant, u64)> = None;
+        let mut sent = 0;
+        for entry in entries {
+            match (entry.peer, entry.direction) {
+                (PeerKind::Debugger, _) => continue,
+                (PeerKind::WindowManager, Direction::Outbound) => continue,
+                // the hello greets the connection, so central treats it as a window manager
+                (PeerKind::WindowManager, Direction::Inbound) if !is_user_input(&entry.message.command)
+                    && !matches!(entry.message.command, APICommand::WMConnect(_)) => continue,
+                _ => {}
+            }
+            match entry.direction {
+                Direction::Outbound => self.central_sent(Sent::new(entry.connection, &entry.message), false),
+                Direction::Inbound => {
+                    if !fast {
+                        let (started, first) = *clock.get_or_insert((Instant::now(), entry.offset_usec));
+                        let due = Duration::from_micros(entry.offset_usec.saturating_sub(first));
+                        let elapsed = started.elapsed();
+                        if due > elapsed {
+                            thread::sleep(due - elapsed);
+                        }
+                    }
+                    // everything central said before this message was sent must have happened,
+                    // so the ids in it can be translated
+                    self.catch_up();
+                    if let Err(e) = self.send(entry) {
+                        error!("couldn't send to central: {}", e);
+                        return false;
+                    }
+                    sent += 1;
+                }
+            }
+        }
+        self.catch_up();
+        // give central a moment to say anything that isn't in the recording
+        thread::sleep(Duration::from_millis(100));
+        self.drain();
+
+        info!("replayed {} messages in {:?}. central sent {} of the {} recorded messages",
+            sent, start.elapsed(), self.matched, self.matched + self.missing.len());
+        for s in &self.missing {
+            warn!("missing {} to {}", s.variant, s.connection);
+        }
+        for s in &self.unexpected {
+            warn!("not in the recording: {} to {}", s.variant, s.connection);
+        }
+        for d in &self.different {
+            warn!("{}", d);
+        }
+        self.missing.is_empty() && self.unexpected.is_empty() && self.different.is_empty()
+    }
+
+    fn send(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut value = serde_json::to_value(&entry.message)?;
+        // replay everything as json. central behaves the same either way.
+        for hello in ["AppConnect", "WMConnect"] {
+            if let Some(obj) = value.pointer_mut(&format!("/command/{}", hello)).and_then(|v| v.as_object_mut()) {
+                obj.insert(String::from("wire_format"), Value::String(String::from("Json")));
+            }
+        }
+        rewrite(&mut value, &self.ids);
+        let msg: IncomingMessage = serde_json::from_value(value)?;
+        if !self.connections.contains_key(&entry.connection) {
+            self.open(entry.connection, entry.peer)?;
+        }
+        let stream = self.connections.get_mut(&entry.connection).unwrap();
+        write_message(stream, &msg, WireFormat::Json)?;
+        // this is what central saw when the app hung up
+        if matches!(msg.command, APICommand::AppDisconnected(_)) {
+            if let Some(stream) = self.connections.remove(&entry.connection) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, connection: Uuid, peer: PeerKind) -> io::Result<()> {
+        let stream = connect_to(&self.socket_dir, peer)?;
+        let mut reader = MessageReader::new(stream.try_clone()?, WireFormat::Json);
+        let tx = self.tx.clone();
+        // only what central sends apps is compared
+        let compared = peer == PeerKind::App;
+        thread::spawn(move || {
+            while let Ok(msg) = reader.read::<IncomingMessage>() {
+                if compared && tx.send((connection, msg)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.connections.insert(connection, stream);
+        Ok(())
+    }
+
+    // pair a message from central with its other half and learn the ids in it
+    fn central_sent(&mut self, sent: Sent, live: bool) {
+        let (others, unpaired) = if live {
+            (&mut self.expected, &mut self.unexpected)
+        } else {
+            (&mut self.unexpected, &mut self.expected)
+        };
+        match others.iter().position(|o| o.connection == sent.connection && o.variant == sent.variant) {
+            Some(n) => {
+                let other = others.remove(n);
+                let (recorded, live) = if live { (&other, &sent) } else { (&sent, &other) };
+                learn(&recorded.value, &live.value, &mut self.ids);
+                for d in differences(&recorded.value, &live.value) {
+                    self.different.push(format!("{} to {}: {}", sent.variant, sent.connection, d));
+                }
+                self.matched += 1;
+            }
+            None => unpaired.push(sent),
+        }
+    }
+
+    fn drain(&mut self) {
+        while let Ok((connection, msg)) = self.rx.try_recv() {
+            self.central_sent(Sent::new(connection, &msg), true);
+        }
+    }
+
+    // wait until central has sent everything it had sent at this point in the recording
+    fn catch_up(&mut self) {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            self.drain();
+            if self.expected.is_empty() {
+                return;
+            }
+            match self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((connection, msg)) => self.central_sent(Sent::new(connection, &msg), true),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    for s in &self.expected {
+                        warn!("central never sent {} to {}", s.variant, s.connection);
+                    }
+                    self.missing.append(&mut self.expected);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// the things a window manager tells central because of what the user did, rather than to answer
+// central. the headless window manager does the answering itself. drags are run by the window
+// manager the mouse input came from, which is the recording's.
+fn is_user_input(cmd: &APICommand) -> bool {
+    matches!(cmd,
+        APICommand::MouseDown(_) | APICommand::MouseUp(_) | APICommand::MouseMove(_) | APICommand::MouseWheel(_)
+        | APICommand::KeyDown(_) | APICommand::KeyUp(_) | APICommand::TextInput(_)
+        | APICommand::ClipboardShortcut(_) | APICommand::CloseWindowRequest(_)
+        | APICommand::NotificationActionInvoked(_) | APICommand::KeyboardLayoutChanged(_)
+        | APICommand::DragEnter(_) | APICommand::DragOver(_) | APICommand::DragLeave(_)
+        | APICommand::Drop(_) | APICommand::DragEnded(_))
+}
+
+// connect as a debugger, once central is up, to find out when the window manager has connected
+fn debug_connection(socket_dir: &Path, timeout: Duration) -> io::Result<ClientStream> {
+    let deadline = Instant::now() + timeout;
+    let mut stream = loop {
+        match connect_to(socket_dir, PeerKind::Debugger) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() < deadline => {
+                info!("waiting for central: {}", e);
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    // debuggers speak bare debug messages
+    serde_json::to_writer(&mut stream, &DebugMessage::HelloDebugger)?;
+    Ok(stream)
+}
+
+// wait until the headless window manager has finished its handshake
+fn wait_for_wm(stream: ClientStream, timeout: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    let mut reader = MessageReader::new(stream.try_clone()?, WireFormat::Json);
+    loop {
+        if let DebugMessage::WindowManagerConnected = reader.read::<DebugMessage>()? {
+            let _ = stream.shutdown(Shutdown::Both);
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn connect_to(socket_dir: &Path, peer: PeerKind) -> io::Result<ClientStream> {
+    let name = match peer {
+        PeerKind::App => "app.sock",
+        PeerKind::WindowManager => "wm.sock",
+        PeerKind::Debugger => "debug.sock",
+    };
+    UnixStream::connect(socket_dir.join(name)).map(ClientStream::Unix)
+}
+
+#[cfg(not(unix))]
+fn connect_to(_socket_dir: &Path, peer: PeerKind) -> io::Result<ClientStream> {
+    let port = match peer {
+        PeerKind::App => common::APP_MANAGER_PORT,
+        PeerKind::WindowManager => common::WINDOW_MANAGER_PORT,
+        PeerKind::Debugger => common::DEBUG_PORT,
+    };
+    std::net::TcpStream::connect(format!("localhost:{}", port)).map(ClientStream::Tcp)
+}
+
+fn start_central(args: &Cli, socket_dir: &Path) -> io::Result<Child> {
+    let mut cmd = Command::new(&args.central);
+    cmd.arg(format!("--database={}", args.database.display()))
+        .arg("--socket-dir").arg(socket_dir);
+    // keep out of the way of any central which is already running
+    if cfg!(unix) {
+        cmd.arg("--no-tcp");
+    }
+    cmd.spawn()
+}
+
+fn start_headless_wm(args: &Cli, socket_dir: &Path) -> io::Result<Child> {
+    let mut cmd = Command::new(&args.runner);
+    cmd.arg("--wmtype").arg("headless").arg("--attach");
+    if cfg!(unix) {
+        cmd.env(WM_SOCKET_ENV, socket_dir.join("wm.sock"));
+    }
+    cmd.spawn()
+}
+
+static COOL_LOGGER:CoolLogger = CoolLogger;
+fn main() {
+    set_logger(&COOL_LOGGER).map(|()|log::set_max_level(LevelFilter::Info)).unwrap();
+    let args: Cli = Cli::from_args();
+    let entries = match read_journal(&args.recording) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("couldn't read the recording {:?}: {}", args.recording, e);
+            std::process::exit(1);
+        }
+    };
+    info!("loaded {} messages from {:?}", entries.len(), args.recording);
+    let socket_dir = std::env::temp_dir().join(format!("clogwench-replay-{}", std::process::id()));
+    let mut central = match start_central(&args, &socket_dir) {
+        Ok(child) => child,
+        Err(e) => {
+            error!("couldn't start central from {:?}: {}", args.central, e);
+            std::process::exit(1);
+        }
+    };
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let same = match debug_connection(&socket_dir, timeout) {
+        Ok(debugger) => match start_headless_wm(&args, &socket_dir) {
+            Ok(mut wm) => {
+                let same = match wait_for_wm(debugger, timeout) {
+                    Ok(()) => Player::new(socket_dir.clone(), timeout).play(&entries, args.fast),
+                    Err(e) => {
+                        error!("the headless window manager never connected: {}", e);
+                        false
+                    }
+                };
+                let _ = wm.kill();
+                let _ = wm.wait();
+                same
+            }
+            Err(e) => {
+                error!("couldn't start the headless window manager from {:?}: {}", args.runner, e);
+                false
+            }
+        },
+        Err(e) => {
+            error!("couldn't connect to central: {}", e);
+            false
+        }
+    };
+    let _ = central.kill();
+    let _ = central.wait();
+    let _ = std::fs::remove_dir_all(&socket_dir);
+    if !same {
+        error!("the replay didn't match the recording");
+        std::process::exit(1);
+    }
+    info!("the replay matched the recording");
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::fs::File;
+use std::mem::discriminant;
 use std::sync::mpsc::{Receiver, Sender};
 use common::{DEBUG_PORT, DebugMessage};
 use std::process::{Child, Command};
@@ -28,23 +29,27 @@ impl CentralConnection {
     pub(crate) fn send_mouse_event(&mut self, evt: MouseDownEvent) {
         self.send(DebugMessage::FakeMouseEvent(evt));
     }
+    // wait for the next message of the same kind as msg. its contents are ignored.
     pub(crate) fn wait_for(&self, msg: DebugMessage) -> Result<DebugMessage,String> {
         info!("waiting for {:?}",msg);
-        let mut de = serde_json::Deserializer::from_reader(&self.master_stream);
-        match DebugMessage::deserialize(&mut de) {
-            Ok(cmd) => {
-                let cmd2 = cmd.clone();
-                // info!("received command {:?}", cmd);
-                if matches!(cmd2,msg) {
-                    Ok(cmd.clone())
-                } else {
-                    info!("incorrect message!");
-                    Ok(cmd.clone())
+        self.wait_until(|cmd| discriminant(cmd) == discriminant(&msg))
+    }
+    // skip debug messages until one passes the check
+    pub(crate) fn wait_until<F>(&self, check: F) -> Result<DebugMessage,String> where F: Fn(&DebugMessage) -> bool {
+        loop {
+            let mut de = serde_json::Deserializer::from_reader(&self.master_stream);
+            match DebugMessage::deserialize(&mut de) {
+                Ok(cmd) => {
+                    // info!("received command {:?}", cmd);
+                    if check(&cmd) {
+                        return Ok(cmd);
+                    }
+                    info!("skipping {:?}", cmd);
+                }
+                Err(e) => {
+                    info!("error deserializing {:?}", e);
+                    return Err(e.to_string());
                 }
-            }
-            Err(e) => {
-                info!("error deserializing {:?}", e);
-                Err(e.to_string())
             }
         }
     }
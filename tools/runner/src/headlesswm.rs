@@ -1,25 +1,27 @@
-use common::{APICommand, DebugMessage, HelloWindowManager, IncomingMessage, WINDOW_MANAGER_PORT};
-use common_wm::{WindowManagerState};
+use common::{APICommand, DebugMessage, DebugWindowInfo, HelloWindowManager, IncomingMessage, WindowResized, WINDOW_MANAGER_PORT};
+use common::protocol::{default_capabilities, PROTOCOL_VERSION};
+use common::transport::{ClientStream, connect, WM_SOCKET_ENV};
+use common::wire::WireFormat;
+use common_wm::{send_drag_refused, send_mouse_wheel, send_window_closed, WindowManagerState, WindowType};
 use core::default::Default;
 use core::option::Option;
 use core::option::Option::{None, Some};
 use core::result::Result::{Err, Ok};
 use std::io::Write;
-use std::net::TcpStream;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::mpsc::{SendError};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use thread::spawn;
 use log::{error, info, warn};
 use serde::Deserialize;
-use common::events::{MouseButton, MouseDownEvent};
+use common::events::MouseDownEvent;
 use gfx::graphics::{GFXBuffer, PixelLayout, Point, WHITE};
 
 pub struct HeadlessWindowManager {
-    stream: TcpStream,
+    stream: ClientStream,
     pub(crate) handle:JoinHandle<()>,
 }
 
@@ -27,9 +29,8 @@ impl HeadlessWindowManager {
     pub fn init(w: u32, h: u32) -> Option<HeadlessWindowManager> {
         let mut buf = GFXBuffer::new( w, h, &PixelLayout::ARGB());
         buf.clear(&WHITE);
-        let conn_string = format!("localhost:{}",WINDOW_MANAGER_PORT);
 
-        match TcpStream::connect(conn_string) {
+        match connect(WM_SOCKET_ENV, WINDOW_MANAGER_PORT) {
             Ok(stream) => {
                 let (tx_out, rx_out) =mpsc::channel::<IncomingMessage>();
                 let (tx_in, rx_in) = mpsc::channel::<IncomingMessage>();
@@ -46,7 +47,8 @@ impl HeadlessWindowManager {
                                     trace: false,
                                     timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                                     source: Default::default(),
-                                    command: out.command
+                                    command: out.command,
+                                    request_id: out.request_id,
                                 };
                                 info!("sending {:?}", im);
                                 let data = serde_json::to_string(&im).unwrap();
@@ -95,12 +97,19 @@ impl HeadlessWindowManager {
                                 match cmd.command {
                                     APICommand::WMConnectResponse(res) => {
                                         // info!("got response for connecting");
+                                        if let Some(reason) = res.rejected {
+                                            error!("central rejected the headless wm: {}", reason);
+                                            return;
+                                        }
                                     },
                                     APICommand::AppConnectResponse(res) => {
                                         state.add_app(res.app_id);
                                     },
+                                    APICommand::AppDisconnected(dis) => {
+                                        state.remove_app(dis.app_id);
+                                    },
                                     APICommand::OpenWindowResponse(ow) => {
-                                        state.add_window(ow.app_id, ow.window_id, &ow.bounds,&ow.window_title );
+                                        state.add_child_window(ow.app_id, ow.window_id, &ow.bounds, &ow.window_title, WindowType::from_name(&ow.window_type), ow.parent);
                                     },
                                     APICommand::DrawRectCommand(dr) => {
                                         if let Some(mut win) = state.lookup_window_mut(dr.window_id) {
@@ -109,26 +118,119 @@ impl HeadlessWindowManager {
                                             buf.draw_image(&win.position, &win.backbuffer.bounds(), &win.backbuffer);
                                         }
                                     },
-                                    APICommand::DrawImageCommand(di) => {
+                                    APICommand::DrawImageCommand(mut di) => {
+                                        if let Err(e) = di.decode() {
+                                            warn!("couldn't decode image {}", e);
+                                            continue;
+                                        }
                                         if let Some(mut win) = state.lookup_window_mut(di.window_id) {
                                             win.backbuffer.fill_rect_with_image(&di.rect, &di.buffer);
                                             buf.draw_image(&win.position, &win.backbuffer.bounds(), &win.backbuffer);
                                         }
                                     }
+                                    APICommand::DrawBatchCommand(batch) => {
+                                        if let Some(win) = state.lookup_window_mut(batch.window_id) {
+                                            // draw the whole batch before the window is copied to the screen
+                                            batch.apply_to(&mut win.backbuffer);
+                                            buf.draw_image(&win.position, &win.backbuffer.bounds(), &win.backbuffer);
+                                        }
+                                    }
+                                    APICommand::RegisterSharedBuffer(reg) => {
+                                        state.add_shared_buffer(reg.app_id, &reg.info);
+                                    }
+                                    APICommand::UnregisterSharedBuffer(unreg) => {
+                                        state.remove_shared_buffer(unreg.app_id, unreg.buffer_id);
+                                    }
+                                    APICommand::DrawSharedImageCommand(ds) => {
+                                        if state.draw_shared_image(&ds) {
+                                            if let Some(win) = state.lookup_window(ds.window_id) {
+                                                buf.draw_image(&win.position, &win.backbuffer.bounds(), &win.backbuffer);
+                                            }
+                                        }
+                                    }
+                                    APICommand::SetWindowTitle(cmd) => {
+                                        state.set_window_title(cmd.window_id, &cmd.title);
+                                    }
+                                    APICommand::SetWindowBounds(cmd) => {
+                                        state.set_window_bounds(cmd.window_id, &cmd.bounds);
+                                        if let Some(win) = state.lookup_window_mut(cmd.window_id) {
+                                            if win.backbuffer.bounds().size() != win.content_size {
+                                                // keep what the app already drew
+                                                let mut backbuffer = GFXBuffer::new(win.content_size.w as u32, win.content_size.h as u32, &win.backbuffer.layout);
+                                                backbuffer.draw_image(&Point::init(0, 0), &win.backbuffer.bounds(), &win.backbuffer);
+                                                win.backbuffer = backbuffer;
+                                                tx_out.send(IncomingMessage {
+                                                    source: Default::default(),
+                                                    trace: false,
+                                                    timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                                    command: APICommand::WindowResized(WindowResized {
+                                                        app_id: win.owner,
+                                                        window_id: win.id,
+                                                        size: win.content_size,
+                                                    }),
+                                                    request_id: None,
+                                                }).unwrap();
+                                            }
+                                            buf.draw_image(&win.position, &win.backbuffer.bounds(), &win.backbuffer);
+                                        }
+                                    }
+                                    APICommand::PostNotification(notification) => {
+                                        // nowhere to show it
+                                        info!("notification from {}: {}", notification.app_id, notification.title);
+                                    }
+                                    APICommand::StartDrag(start) => {
+                                        // the headless wm has no mouse to drag with
+                                        send_drag_refused(&tx_out, &start);
+                                    }
+                                    APICommand::RaiseWindow(cmd) => {
+                                        state.raise_window(cmd.window_id);
+                                    }
+                                    APICommand::SetWindowVisible(cmd) => {
+                                        state.set_window_visible(cmd.window_id, cmd.visible);
+                                    }
+                                    APICommand::CloseWindowRequest(cmd) => {
+                                        state.close_window(cmd.window_id);
+                                    }
+                                    APICommand::MouseWheel(evt) => {
+                                        send_mouse_wheel(&tx_out, &state, &evt);
+                                    }
                                     APICommand::MouseDown(evt) => {
                                         // info!("pretending to process a mouse down. lets see what becomes focused?");
                                         let point = Point::init(evt.x, evt.y);
+                                        let clicks = state.clicks.click(&evt.button, point, Instant::now());
+                                        // a click outside the open popups only closes them
+                                        let dismissed = state.dismiss_popups_at(point);
+                                        if !dismissed.is_empty() {
+                                            for win in dismissed {
+                                                send_window_closed(&tx_out, &win);
+                                            }
+                                            state.clicks.reset();
+                                            continue;
+                                        }
                                         if let Some(win) = state.pick_window_at(point) {
                                             // info!("picked a window");
                                             let wid = win.id.clone();
                                             let aid = win.owner.clone();
+                                            let title = win.title.clone();
+                                            if let Some(modal) = state.blocking_modal(wid) {
+                                                state.set_focused_window(modal);
+                                                state.raise_window(modal);
+                                                state.clicks.reset();
+                                                continue;
+                                            }
                                             state.set_focused_window(wid);
                                             tx_out.send(IncomingMessage {
                                                 source:Default::default(),
                                                 trace: false,
                                                 timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                                                 // recipient: Default::default(),
-                                                command: APICommand::Debug(DebugMessage::WindowFocusChanged(String::from("foo")))
+                                                command: APICommand::Debug(DebugMessage::WindowFocusChanged(DebugWindowInfo {
+                                                    app_id: aid,
+                                                    app_name: String::new(),
+                                                    window_id: wid,
+                                                    window_title: title,
+                                                })),
+                                                request_id: None,
                                             }).unwrap();
                                             tx_out.send(IncomingMessage {
                                                 source:Default::default(),
@@ -139,10 +241,12 @@ impl HeadlessWindowManager {
                                                     app_id: aid,
                                                     window_id: wid,
                                                     original_timestamp: evt.original_timestamp,
-                                                    button: MouseButton::Primary,
+                                                    button: evt.button,
                                                     x: evt.x,
-                                                    y: evt.y
-                                                })
+                                                    y: evt.y,
+                                                    clicks,
+                                                }),
+                                                request_id: None,
                                             }).unwrap();
                                         } else {
                                             // info!("clicked on nothing. sending background debug event");
@@ -151,7 +255,8 @@ impl HeadlessWindowManager {
                                                 trace: false,
                                                 timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                                                 // recipient: Default::default(),
-                                                command: APICommand::Debug(DebugMessage::BackgroundReceivedMouseEvent)
+                                                command: APICommand::Debug(DebugMessage::BackgroundReceivedMouseEvent),
+                                                request_id: None,
                                             }).unwrap();
                                         }
                                     }
@@ -165,6 +270,7 @@ impl HeadlessWindowManager {
                                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                                             // recipient: Default::default(),
                                             command: APICommand::Debug(DebugMessage::ScreenCaptureResponse()),
+                                            request_id: None,
                                         }).unwrap();
                                     }
                                     APICommand::SystemShutdown => {
@@ -186,7 +292,14 @@ impl HeadlessWindowManager {
                     trace: false,
                     timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                     // recipient: Default::default(),
-                    command: APICommand::WMConnect(HelloWindowManager {})
+                    // the headless wm stays on json so its traffic is easy to read
+                    command: APICommand::WMConnect(HelloWindowManager {
+                        wire_format: WireFormat::Json,
+                        protocol_version: PROTOCOL_VERSION,
+                        client_name: String::from("headless-wm"),
+                        capabilities: default_capabilities(),
+                    }),
+                    request_id: None,
                 };
                 tx_out.send(im).unwrap();
                 info!("window manager fully connected to the central server");
@@ -61,6 +61,9 @@ struct Cli {
     height:u32,
     #[structopt(long, parse(from_os_str))]
     datafile: Vec<PathBuf>,
+    // connect to a central which is already running instead of starting one
+    #[structopt(long)]
+    attach:bool,
 }
 
 fn init_setup() -> Cli {
@@ -74,71 +77,73 @@ fn main() -> Result<(),String> {
     let args:Cli = init_setup();
     set_logger(&COOL_LOGGER).map(|()|log::set_max_level(LevelFilter::Info));
 
-    // start central server
-    let mut debug_channel = central_conn::start_central_server(&args.datafile)?;
-    // info!("runner: connected to the central server");
-    debug_channel.send(DebugMessage::HelloDebugger);
-    // info!("runner: sent the hello debugger message");
-    debug_channel.wait_for(DebugMessage::HelloDebuggerResponse);
-    // info!("runner: got back the response!");
-
-    // wait(1000);
-
-    if args.test {
-        let test_handler = spawn({
-            move || {
-                // wait for Debug::window_manager_connected
-                info!("test: test thread waiting for window manager connected");
-                debug_channel.wait_for(DebugMessage::WindowManagerConnected);
-
-                // wait(4000);
-                info!("test: starting the app");
-
-                // start demo click grid. opens window at 50,50 to 250,250
-                let mut app_thread = start_app("demo-click-grid");
-                // wait for the app to start
-                debug_channel.wait_for(DebugMessage::AppConnected(String::from("demo-click-grid")));
-                info!("test: app connected");
-
-                // send wait for the window to open
-                debug_channel.wait_for(DebugMessage::WindowOpened(String::from("demo-click-grid")));
-                info!("test: app window open");
-                // send fake click to the background
-                // debug_channel.send_mouse_event(MouseDownEvent::init_primary(600,500));
-                // wait for debug::background received click
-                // debug_channel.wait_for(DebugMessage::BackgroundReceivedMouseEvent);
-                // send fake click to window
-                // debug_channel.send_mouse_event(MouseDownEvent::init_primary(200,200));
-                // wait for debug::focused window changed, appname == name passed to demo click grid)
-                // debug_channel.wait_for(DebugMessage::WindowFocusChanged(String::from("demo-click-grid")));
-                // app receives click. sends out a debug log event saying it got a click
-                // wait for debug log event from that appname.
-                // debug_channel.wait_for(DebugMessage::AppLog(String::from("input-received")));
-
-                //request a screen capture
-                debug_channel.send(DebugMessage::ScreenCapture(Rect::from_ints(0, 0, 500, 500), String::from("path.png")));
-                debug_channel.wait_for(DebugMessage::ScreenCaptureResponse());
-                info!("waiting 5 seconds");
-                wait(5000);
-                info!("RUNNER: killing the central server");
-                debug_channel.send(DebugMessage::RequestServerShutdown);
-                wait(5000);
-                info!("sending a process kill in case its still running");
-                debug_channel.child.kill().unwrap();
-
-                app_thread.child.kill().unwrap();
-            }
-        });
-    } else {
-        info!("Lets just dump debug messages instead of running a test");
-        let test_handler = spawn(||{
-            info!("monitoring the debug log");
-            debug_channel.loop_until_done();
-        });
+    // a central which is already running, like the replay tool's, is used as it is
+    if !args.attach {
+        // start central server
+        let mut debug_channel = central_conn::start_central_server(&args.datafile)?;
+        // info!("runner: connected to the central server");
+        debug_channel.send(DebugMessage::HelloDebugger);
+        // info!("runner: sent the hello debugger message");
+        debug_channel.wait_for(DebugMessage::HelloDebuggerResponse);
+        // info!("runner: got back the response!");
+
+        // wait(1000);
+
+        if args.test {
+            let test_handler = spawn({
+                move || {
+                    // wait for Debug::window_manager_connected
+                    info!("test: test thread waiting for window manager connected");
+                    debug_channel.wait_for(DebugMessage::WindowManagerConnected);
+
+                    // wait(4000);
+                    info!("test: starting the app");
+
+                    // start demo click grid. opens window at 50,50 to 250,250
+                    let mut app_thread = start_app("demo-click-grid");
+                    // wait for the app to start
+                    debug_channel.wait_until(|m| matches!(m, DebugMessage::AppConnected(info) if info.app_name == "demo-click-grid"));
+                    info!("test: app connected");
+
+                    // send wait for the window to open
+                    debug_channel.wait_until(|m| matches!(m, DebugMessage::WindowOpened(info) if info.app_name == "demo-click-grid"));
+                    info!("test: app window open");
+                    // send fake click to the background
+                    // debug_channel.send_mouse_event(MouseDownEvent::init_primary(600,500));
+                    // wait for debug::background received click
+                    // debug_channel.wait_for(DebugMessage::BackgroundReceivedMouseEvent);
+                    // send fake click to window
+                    // debug_channel.send_mouse_event(MouseDownEvent::init_primary(200,200));
+                    // wait for debug::focused window changed, appname == name passed to demo click grid)
+                    // debug_channel.wait_until(|m| matches!(m, DebugMessage::WindowFocusChanged(info) if info.app_name == "demo-click-grid"));
+                    // app receives click. sends out a debug log event saying it got a click
+                    // wait for debug log event from that appname.
+                    // debug_channel.wait_for(DebugMessage::AppLog(String::from("input-received")));
+
+                    //request a screen capture
+                    debug_channel.send(DebugMessage::ScreenCapture(Rect::from_ints(0, 0, 500, 500), String::from("path.png")));
+                    debug_channel.wait_for(DebugMessage::ScreenCaptureResponse());
+                    info!("waiting 5 seconds");
+                    wait(5000);
+                    info!("RUNNER: killing the central server");
+                    debug_channel.send(DebugMessage::RequestServerShutdown);
+                    wait(5000);
+                    info!("sending a process kill in case its still running");
+                    debug_channel.child.kill().unwrap();
+
+                    app_thread.child.kill().unwrap();
+                }
+            });
+        } else {
+            info!("Lets just dump debug messages instead of running a test");
+            let test_handler = spawn(||{
+                info!("monitoring the debug log");
+                debug_channel.loop_until_done();
+            });
 
+        }
     }
 
-
     match args.wmtype {
         WMType::Native => {
             info!("creating a native window manager");
@@ -168,9 +173,9 @@ fn main() -> Result<(),String> {
     // wait for app handle to join
     app_thread.join();
     // wait for debug::window closed event
-    debug_channel.wait_for(DebugMessage::WindowClosed(String::from("demo-click-grid")));
+    debug_channel.wait_until(|m| matches!(m, DebugMessage::WindowClosed(info) if info.app_name == "demo-click-grid"));
     // wait for debug::app exit event
-    debug_channel.wait_for(DebugMessage::AppDisconnected(String::from("demo-click-grid")));
+    debug_channel.wait_until(|m| matches!(m, DebugMessage::AppDisconnected(info) if info.app_name == "demo-click-grid"));
     // send shutdown message to central server
      */
     // debug_channel.send(DebugMessage::RequestServerShutdown);
@@ -1,19 +1,23 @@
+use std::collections::HashSet;
 use std::io::Write;
 use std::net::TcpStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, mpsc};
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 use std::thread::{JoinHandle, spawn};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use log::info;
+use log::{info, warn};
 use serde::Deserialize;
 use uuid::Uuid;
-use common::{APICommand, DebugMessage, IncomingMessage, WINDOW_MANAGER_PORT, WindowResized};
-use common::events::{KeyDownEvent, ModifierState};
+use common::{APICommand, ClipboardShortcut, DebugMessage, NotificationActionInvoked, PostNotification, DebugWindowInfo, IncomingMessage, WINDOW_MANAGER_PORT, WindowResized};
+use common::events::{KeyDownEvent, KeyUpEvent, ModifierState, TextInputEvent};
 use common::generated::KeyCode;
-use common_wm::{AppMouseGesture, CentralConnection, FOCUSED_TITLEBAR_COLOR, FOCUSED_WINDOW_COLOR, InputGesture, NoOpGesture, start_wm_network_connection, TITLE_BAR_HEIGHT, TITLEBAR_COLOR, Window, WINDOW_BUTTON_COLOR, WINDOW_COLOR, WindowCloseButtonGesture, WindowDragGesture, WindowManagerState, WindowResizeGesture};
+use common::compose::Composer;
+use common::keymap::{KEYBOARD_LAYOUT_DIR, KeyboardLayout, load_keyboard_layout};
+use common_wm::toasts::{action_bounds, TOAST_HEIGHT, TOAST_WIDTH};
+use common_wm::{AppMouseGesture, CentralConnection, clipboard_shortcut, DragGesture, send_drag_refused, FOCUSED_TITLEBAR_COLOR, FOCUSED_WINDOW_COLOR, InputGesture, NoOpGesture, send_mouse_wheel, start_wm_network_connection, TITLE_BAR_HEIGHT, TITLEBAR_COLOR, Window, WINDOW_BUTTON_COLOR, WINDOW_COLOR, WindowCloseButtonGesture, WindowDragGesture, WindowManagerState, WindowResizeGesture, WindowType, send_keyboard_layout_changed, send_window_closed};
 use gfx::font::{FontInfo2, load_font_from_json};
 use gfx::graphics::{ARGBColor, BLACK, GFXBuffer, Point, Rect, WHITE};
 // use minibuf::{make_plat, Plat};
@@ -34,6 +38,9 @@ pub struct PlatformWindowManager {
     pub debug_buffer: GFXBuffer,
     pub title_buffer: GFXBuffer,
     pub exit_button_bounds:Rect,
+    pub composer: Composer,
+    // keys whose KeyDown the app never saw. their KeyUp is kept from it too.
+    swallowed_keys: HashSet<KeyCode>,
 
     tick:u128,
     fps:Vec<u128>,
@@ -90,6 +97,8 @@ impl PlatformWindowManager {
                 debug_pos: Point::init(0, bds.h - 50),
                 debug_buffer,
                 title_buffer,
+                composer: Composer::load(Path::new(KEYBOARD_LAYOUT_DIR), &KeyboardLayout::us()),
+                swallowed_keys: HashSet::new(),
             })
         } else {
             info!("could not connect to server at");
@@ -107,6 +116,9 @@ impl PlatformWindowManager {
 
         // check for windows that need to be resized
         self.check_window_sizes();
+        for toast in self.state.toasts.expire(Instant::now()) {
+            self.plat.unregister_image2(&toast.buffer);
+        }
 
 
 
@@ -142,7 +154,8 @@ impl PlatformWindowManager {
                     self.state.remove_app(dis.app_id);
                 }
                 APICommand::OpenWindowResponse(ow) => {
-                    let win_id = self.state.add_window(ow.app_id, ow.window_id, &ow.bounds, &ow.window_title);
+                    let window_type = WindowType::from_name(&ow.window_type);
+                    let win_id = self.state.add_child_window(ow.app_id, ow.window_id, &ow.bounds, &ow.window_title, window_type, ow.parent);
                     if let Some(win) = self.state.lookup_window(win_id) {
                         self.plat.register_image2(&win.backbuffer);
                     }
@@ -154,12 +167,73 @@ impl PlatformWindowManager {
                         // buf.copy_from(win.position.x, win.position.y, &win.backbuffer);
                     }
                 },
-                APICommand::DrawImageCommand(dr) => {
+                APICommand::DrawImageCommand(mut dr) => {
+                    if let Err(e) = dr.decode() {
+                        warn!("couldn't decode image {}", e);
+                        continue;
+                    }
                     if let Some(win) = self.state.lookup_window_mut(dr.window_id) {
                         // info!("NativeWM: draw image to window {:?}", &dr.rect);
                         win.backbuffer.fill_rect_with_image(&dr.rect,&dr.buffer);
                     }
                 },
+                APICommand::DrawBatchCommand(batch) => {
+                    // the screen is only redrawn between messages, so the whole batch shows up at once
+                    if let Some(win) = self.state.lookup_window_mut(batch.window_id) {
+                        batch.apply_to(&mut win.backbuffer);
+                    }
+                },
+                APICommand::RegisterSharedBuffer(reg) => {
+                    self.state.add_shared_buffer(reg.app_id, &reg.info);
+                },
+                APICommand::UnregisterSharedBuffer(unreg) => {
+                    self.state.remove_shared_buffer(unreg.app_id, unreg.buffer_id);
+                },
+                APICommand::DrawSharedImageCommand(ds) => {
+                    // copied straight out of the app's memory into the registered backbuffer
+                    self.state.draw_shared_image(&ds);
+                },
+                APICommand::SetWindowTitle(cmd) => {
+                    self.state.set_window_title(cmd.window_id, &cmd.title);
+                },
+                APICommand::SetWindowBounds(cmd) => {
+                    // check_window_sizes reallocates the backbuffer and tells the app
+                    self.state.set_window_bounds(cmd.window_id, &cmd.bounds);
+                },
+                APICommand::RaiseWindow(cmd) => {
+                    self.state.raise_window(cmd.window_id);
+                },
+                APICommand::SetWindowVisible(cmd) => {
+                    self.state.set_window_visible(cmd.window_id, cmd.visible);
+                },
+                APICommand::CloseWindowRequest(cmd) => {
+                    for win in self.state.close_window(cmd.window_id) {
+                        self.plat.unregister_image2(&win.backbuffer);
+                    }
+                },
+                APICommand::PostNotification(notification) => {
+                    let buffer = self.draw_toast(&notification);
+                    self.plat.register_image2(&buffer);
+                    self.state.toasts.add(notification, buffer);
+                },
+                APICommand::SetKeyboardLayout(cmd) => {
+                    match load_keyboard_layout(Path::new(KEYBOARD_LAYOUT_DIR), &cmd.name) {
+                        Ok(layout) => {
+                            info!("switching to the {} keyboard layout", layout.title);
+                            send_keyboard_layout_changed(&self.connection.tx_out, &layout);
+                            self.composer.set_layout(&layout);
+                            self.plat.set_keyboard_layout(layout);
+                        }
+                        Err(e) => warn!("can't switch keyboard layout: {}", e),
+                    }
+                },
+                APICommand::StartDrag(start) => {
+                    if self.gesture.app_window() == Some(start.window_id) {
+                        self.gesture = Box::new(DragGesture::init(&start));
+                    } else {
+                        send_drag_refused(&self.connection.tx_out, &start);
+                    }
+                },
                 APICommand::MouseUp(evt) => {
                     self.gesture.mouse_up(evt, &mut self.state, &self.connection.tx_out);
                     self.gesture = Box::new(NoOpGesture::init()) as Box<dyn InputGesture>;
@@ -168,8 +242,14 @@ impl PlatformWindowManager {
                     self.cursor = Point::init(evt.x, evt.y);
                     self.gesture.mouse_move(evt, &mut self.state, &self.connection.tx_out);
                 }
-                APICommand::MouseDown(evt) => {
+                APICommand::MouseWheel(evt) => {
+                    send_mouse_wheel(&self.connection.tx_out, &self.state, &evt);
+                }
+                APICommand::MouseDown(mut evt) => {
                     let point = Point::init(evt.x, evt.y);
+                    // presses which end up on a window's frame reset this below, so they
+                    // don't count toward the app's double clicks
+                    evt.clicks = self.state.clicks.click(&evt.button, point, Instant::now());
                     // info!("checking mouse down path");
                     if self.exit_button_bounds.contains(&point) {
                         info!("clicked the exit button!");
@@ -177,28 +257,72 @@ impl PlatformWindowManager {
                             source:Default::default(),
                             trace: cmd.trace,
                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
-                            command: APICommand::Debug(DebugMessage::RequestServerShutdown)
+                            command: APICommand::Debug(DebugMessage::RequestServerShutdown),
+                            request_id: None,
                         }).unwrap();
                         thread::sleep(Duration::from_millis(500));
                         return false;
                     }
+                    // toasts are above everything
+                    let screen = self.plat.get_screen_bounds();
+                    if let Some(click) = self.state.toasts.click(&screen, point) {
+                        self.plat.unregister_image2(&click.toast.buffer);
+                        if let Some(action_id) = click.action_id {
+                            self.connection.tx_out.send(IncomingMessage {
+                                source:Default::default(),
+                                trace: cmd.trace,
+                                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                command: APICommand::NotificationActionInvoked(NotificationActionInvoked {
+                                    app_id: click.toast.notification.app_id,
+                                    notification_id: click.toast.notification.notification_id,
+                                    action_id,
+                                }),
+                                request_id: None,
+                            }).unwrap();
+                        }
+                        continue;
+                    }
+                    // open popups grab the mouse. a click outside them only closes them.
+                    let dismissed = self.state.dismiss_popups_at(point);
+                    if !dismissed.is_empty() {
+                        for win in dismissed {
+                            self.plat.unregister_image2(&win.backbuffer);
+                            send_window_closed(&self.connection.tx_out, &win);
+                        }
+                        self.state.clicks.reset();
+                        continue;
+                    }
                     if let Some(win) = self.state.pick_window_at(point) {
                         // info!("picked a window");
                         let wid = win.id.clone();
                         let aid = win.owner.clone();
+                        let title = win.title.clone();
 
-                        if win.close_button_bounds().contains(&point) {
+                        // the window behind a dialog gets nothing until the dialog closes
+                        if let Some(modal) = self.state.blocking_modal(wid) {
+                            self.state.set_focused_window(modal);
+                            self.state.raise_window(modal);
+                            self.state.clicks.reset();
+                            continue;
+                        }
+                        if !win.is_decorated() {
+                            self.gesture = Box::new(AppMouseGesture::init(aid,win.id));
+                            self.gesture.mouse_down(evt, &src,&mut self.state, &self.connection.tx_out);
+                        } else if win.close_button_bounds().contains(&point) {
                             info!("inside the close button");
                             self.gesture = Box::new(WindowCloseButtonGesture::init(point, win.id));
                             self.gesture.mouse_down(evt,&src, &mut self.state, &self.connection.tx_out);
+                            self.state.clicks.reset();
                         } else if win.titlebar_bounds().contains(&point) {
                             info!("inside the title bar");
                             self.gesture = Box::new(WindowDragGesture::init(point, win.id));
                             self.gesture.mouse_down(evt, &src,&mut self.state, &self.connection.tx_out);
+                            self.state.clicks.reset();
                         } else if win.resize_bounds().contains(&point) {
                             info!("inside the resize control");
                             self.gesture = Box::new(WindowResizeGesture::init(point, win.id));
                             self.gesture.mouse_down(evt, &src,&mut self.state, &self.connection.tx_out);
+                            self.state.clicks.reset();
                         } else {
                             // it needs to go to the app
                             // info!("inside the window content for the app");
@@ -213,7 +337,13 @@ impl PlatformWindowManager {
                             trace: cmd.trace,
                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                             // recipient: Default::default(),
-                            command: APICommand::Debug(DebugMessage::WindowFocusChanged(String::from("foo")))
+                            command: APICommand::Debug(DebugMessage::WindowFocusChanged(DebugWindowInfo {
+                                app_id: aid,
+                                app_name: String::new(),
+                                window_id: wid,
+                                window_title: title,
+                            })),
+                            request_id: None,
                         }).unwrap();
                     } else {
                         // info!("clicked on nothing. sending background debug event");
@@ -222,51 +352,111 @@ impl PlatformWindowManager {
                             trace: cmd.trace,
                             timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                             // recipient: Default::default(),
-                            command: APICommand::Debug(DebugMessage::BackgroundReceivedMouseEvent)
+                            command: APICommand::Debug(DebugMessage::BackgroundReceivedMouseEvent),
+                            request_id: None,
                         }).unwrap();
                     }
                 }
                 APICommand::KeyDown(evt) => {
+                    self.composer.focus(self.state.keyboard_window().map(|win| win.id));
                     match evt.key {
+                        // starts a compose sequence. the app doesn't see it.
+                        key if self.composer.is_compose_key(key) => {
+                            self.swallowed_keys.insert(evt.key);
+                            self.composer.start();
+                        }
                         KeyCode::ESCAPE => {
                             self.connection.tx_out.send(IncomingMessage {
                                 source:Default::default(),
                                 trace: false,
                                 timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                                 // recipient: Default::default(),
-                                command: APICommand::Debug(DebugMessage::RequestServerShutdown)
+                                command: APICommand::Debug(DebugMessage::RequestServerShutdown),
+                                request_id: None,
                             }).unwrap();
                             thread::sleep(Duration::from_millis(500));
                             return false;
                         }
                         _ => {
                             info!("got a key down event {:?}. forwarding",evt);
-                            if let Some(id) = self.state.get_focused_window() {
-                                if let Some(win) = self.state.lookup_window(*id) {
-                                    let wid = win.id.clone();
-                                    let aid = win.owner.clone();
-                                    println!("got wid {} and aid {}",wid,aid);
-                                    self.connection.tx_out.send(IncomingMessage {
-                                        source:Default::default(),
-                                        trace: false,
-                                        timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
-                                        // recipient: aid,
-                                        command: APICommand::KeyDown(KeyDownEvent {
+                            // a dialog gets the keys meant for the window behind it
+                            if let Some(win) = self.state.keyboard_window() {
+                                let wid = win.id.clone();
+                                let aid = win.owner.clone();
+                                let command = match clipboard_shortcut(&evt) {
+                                    Some(action) => {
+                                        self.swallowed_keys.insert(evt.key);
+                                        APICommand::ClipboardShortcut(ClipboardShortcut {
                                             app_id: aid,
                                             window_id: wid,
-                                            key: evt.key,
-                                            mods:evt.mods,
+                                            action,
                                         })
-                                    }).unwrap();
-                                } else {
-                                    info!("window not found. dropping keyboard event");
-                                }
+                                    }
+                                    None => APICommand::KeyDown(KeyDownEvent {
+                                        app_id: aid,
+                                        window_id: wid,
+                                        key: evt.key,
+                                        mods:evt.mods,
+                                        repeat: evt.repeat,
+                                    }),
+                                };
+                                self.connection.tx_out.send(IncomingMessage {
+                                    source:Default::default(),
+                                    trace: false,
+                                    timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                    // recipient: aid,
+                                    command,
+                                    request_id: None,
+                                }).unwrap();
                             } else {
                                 info!("no focused window. dropping keyboard event");
                             }
                         }
                     }
                 }
+                APICommand::KeyUp(evt) => {
+                    if self.swallowed_keys.remove(&evt.key) {
+                        continue;
+                    }
+                    match self.state.keyboard_window() {
+                        Some(win) => {
+                            self.connection.tx_out.send(IncomingMessage {
+                                source:Default::default(),
+                                trace: false,
+                                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                command: APICommand::KeyUp(KeyUpEvent {
+                                    app_id: win.owner,
+                                    window_id: win.id,
+                                    key: evt.key,
+                                    mods: evt.mods,
+                                }),
+                                request_id: None,
+                            }).unwrap();
+                        }
+                        None => info!("no focused window. dropping keyboard event"),
+                    }
+                }
+                APICommand::TextInput(evt) => {
+                    // dead keys and compose sequences only send once they make a character
+                    self.composer.focus(self.state.keyboard_window().map(|win| win.id));
+                    let text = self.composer.input(&evt.text);
+                    match self.state.keyboard_window() {
+                        Some(win) => if let Some(text) = text {
+                            self.connection.tx_out.send(IncomingMessage {
+                                source:Default::default(),
+                                trace: false,
+                                timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
+                                command: APICommand::TextInput(TextInputEvent {
+                                    app_id: win.owner,
+                                    window_id: win.id,
+                                    text,
+                                }),
+                                request_id: None,
+                            }).unwrap();
+                        }
+                        None => info!("no focused window. dropping text input"),
+                    }
+                }
                 APICommand::Debug(DebugMessage::ScreenCapture(rect, str)) => {
                     let pth = PathBuf::from("./screencapture.png");
                     info!("rect for screen capture {:?}",pth);
@@ -277,6 +467,7 @@ impl PlatformWindowManager {
                         timestamp_usec: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros(),
                         // recipient: Default::default(),
                         command: APICommand::Debug(DebugMessage::ScreenCaptureResponse()),
+                        request_id: None,
                     }).unwrap();
                 }
                 APICommand::WMConnectResponse(res) => {
@@ -305,7 +496,8 @@ impl PlatformWindowManager {
                         app_id: win.owner,
                         window_id: win.id,
                         size: win.content_size,
-                    })
+                    }),
+                    request_id: None,
                 }).unwrap();
 
             }
@@ -316,6 +508,8 @@ impl PlatformWindowManager {
         self.draw_background();
         self.draw_windows();
         self.draw_resize_rect();
+        self.draw_drag_rect();
+        self.draw_toasts();
         self.draw_debug();
         self.draw_exit_button();
         self.draw_cursor();
@@ -334,6 +528,9 @@ impl PlatformWindowManager {
             };
             //draw window contents
             self.plat.draw_image(&win.content_bounds().position(), &win.backbuffer.bounds(), &win.backbuffer);
+            if !win.is_decorated() {
+                continue;
+            }
 
             // draw the titlebar
             self.plat.fill_rect(win.titlebar_bounds(), &tc);
@@ -391,6 +588,38 @@ impl PlatformWindowManager {
         // self.background.clear(gray);
         // self.plat.draw_image(&Point::init(0, 0), &self.background.bounds(), &self.background);
     }
+    // a toast is drawn once when it arrives, then copied to the screen every frame
+    fn draw_toast(&self, notification: &PostNotification) -> GFXBuffer {
+        let mut buf = GFXBuffer::new(TOAST_WIDTH as u32, TOAST_HEIGHT as u32, self.plat.get_preferred_pixel_layout());
+        buf.clear(&FOCUSED_TITLEBAR_COLOR);
+        let mut text_x = 6;
+        if let Some(icon) = &notification.icon {
+            let icon = icon.to_layout(&buf.layout);
+            let size = Rect::from_ints(0, 0, icon.width.min(32) as i32, icon.height.min(32) as i32);
+            buf.draw_image(&Point::init(6, 6), &size, &icon);
+            text_x = 44;
+        }
+        self.font.draw_text_at(&mut buf, &notification.title, text_x, 8, &BLACK);
+        self.font.draw_text_at(&mut buf, &notification.body, text_x, 24, &BLACK);
+        for (i, action) in notification.actions.iter().enumerate() {
+            let bounds = action_bounds(i);
+            buf.fill_rect(&bounds, &WINDOW_BUTTON_COLOR);
+            self.font.draw_text_at(&mut buf, &action.label, bounds.x + 4, bounds.y + 4, &BLACK);
+        }
+        buf
+    }
+    fn draw_toasts(&mut self) {
+        let screen = self.plat.get_screen_bounds();
+        for (bounds, toast) in self.state.toasts.layout(&screen) {
+            self.plat.draw_image(&bounds.position(), &toast.buffer.bounds(), &toast.buffer);
+        }
+    }
+    fn draw_drag_rect(&mut self) {
+        if let Some(rect) = self.state.drag_rect {
+            self.plat.fill_rect(rect, &WHITE);
+            self.plat.fill_rect(Rect::from_ints(rect.x + 2, rect.y + 2, rect.w - 4, rect.h - 4), &magenta);
+        }
+    }
     fn draw_resize_rect(&mut self) {
         if let Some(rect) =  self.state.resize_rect {
             let size = 2;